@@ -0,0 +1,359 @@
+//! Parsing of the SGR escape sequences `capture-pane -e` emits into styled
+//! spans, and rendering of those spans as inline markup.
+
+use serde::Serialize;
+
+const NAMES: [&str; 16] = [
+    "black",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "white",
+    "bright-black",
+    "bright-red",
+    "bright-green",
+    "bright-yellow",
+    "bright-blue",
+    "bright-magenta",
+    "bright-cyan",
+    "bright-white",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// One of the 256 palette colors; 0-15 are the named ANSI colors.
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// "red", "bright-blue", "color208" or "#ff8800".
+    pub fn name(&self) -> String {
+        match *self {
+            Color::Indexed(i) if i < 16 => NAMES[i as usize].to_string(),
+            Color::Indexed(i) => format!("color{i}"),
+            Color::Rgb(r, g, b) => format!("#{r:02x}{g:02x}{b:02x}"),
+        }
+    }
+
+    /// The color's RGB value, using xterm's default palette for indexed colors.
+    pub fn rgb(&self) -> (u8, u8, u8) {
+        const BASE: [(u8, u8, u8); 16] = [
+            (0, 0, 0),
+            (205, 0, 0),
+            (0, 205, 0),
+            (205, 205, 0),
+            (0, 0, 238),
+            (205, 0, 205),
+            (0, 205, 205),
+            (229, 229, 229),
+            (127, 127, 127),
+            (255, 0, 0),
+            (0, 255, 0),
+            (255, 255, 0),
+            (92, 92, 255),
+            (255, 0, 255),
+            (0, 255, 255),
+            (255, 255, 255),
+        ];
+        match *self {
+            Color::Indexed(i @ 0..=15) => BASE[i as usize],
+            Color::Indexed(i @ 16..=231) => {
+                let level = |n: u8| if n == 0 { 0 } else { 55 + n * 40 };
+                let i = i - 16;
+                (level(i / 36), level(i / 6 % 6), level(i % 6))
+            }
+            Color::Indexed(i) => {
+                let gray = 8 + (i - 232) * 10;
+                (gray, gray, gray)
+            }
+            Color::Rgb(r, g, b) => (r, g, b),
+        }
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.name())
+    }
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Style {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fg: Option<Color>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bg: Option<Color>,
+    #[serde(skip_serializing_if = "is_false")]
+    pub bold: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub dim: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub italic: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub underline: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub blink: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub reverse: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub hidden: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub strikethrough: bool,
+}
+
+impl Style {
+    /// Apply the parameters of one SGR sequence (the part between `ESC [` and
+    /// `m`).
+    fn apply_sgr(&mut self, params: &str) {
+        // Sub-parameters ("4:3" for curly underline) only refine the main one.
+        let codes: Vec<u32> = params
+            .split(';')
+            .map(|p| p.split(':').next().unwrap_or("").parse().unwrap_or(0))
+            .collect();
+        let mut i = 0;
+        while i < codes.len() {
+            match codes[i] {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 | 21 => self.underline = true,
+                5 | 6 => self.blink = true,
+                7 => self.reverse = true,
+                8 => self.hidden = true,
+                9 => self.strikethrough = true,
+                22 => {
+                    self.bold = false;
+                    self.dim = false;
+                }
+                23 => self.italic = false,
+                24 => self.underline = false,
+                25 => self.blink = false,
+                27 => self.reverse = false,
+                28 => self.hidden = false,
+                29 => self.strikethrough = false,
+                c @ 30..=37 => self.fg = Some(Color::Indexed((c - 30) as u8)),
+                c @ 90..=97 => self.fg = Some(Color::Indexed((c - 90 + 8) as u8)),
+                c @ 40..=47 => self.bg = Some(Color::Indexed((c - 40) as u8)),
+                c @ 100..=107 => self.bg = Some(Color::Indexed((c - 100 + 8) as u8)),
+                39 => self.fg = None,
+                49 => self.bg = None,
+                c @ (38 | 48) => {
+                    let (color, used) = extended_color(&codes[i + 1..]);
+                    if c == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                    i += used;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+
+    pub fn is_plain(&self) -> bool {
+        *self == Style::default()
+    }
+
+    /// The markup tag for this style, e.g. "bold red on-blue".
+    fn tag(&self) -> String {
+        let mut parts = Vec::new();
+        let flags = [
+            (self.bold, "bold"),
+            (self.dim, "dim"),
+            (self.italic, "italic"),
+            (self.underline, "underline"),
+            (self.blink, "blink"),
+            (self.reverse, "reverse"),
+            (self.hidden, "hidden"),
+            (self.strikethrough, "strike"),
+        ];
+        for (on, name) in flags {
+            if on {
+                parts.push(name.to_string());
+            }
+        }
+        if let Some(fg) = self.fg {
+            parts.push(fg.name());
+        }
+        if let Some(bg) = self.bg {
+            parts.push(format!("on-{}", bg.name()));
+        }
+        parts.join(" ")
+    }
+}
+
+/// Parse the color after a 38 or 48: "5;N" or "2;R;G;B". Returns the color
+/// and how many parameters it used.
+fn extended_color(rest: &[u32]) -> (Option<Color>, usize) {
+    match rest {
+        [5, n, ..] => (Some(Color::Indexed(*n as u8)), 2),
+        [2, r, g, b, ..] => (Some(Color::Rgb(*r as u8, *g as u8, *b as u8)), 4),
+        _ => (None, rest.len()),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub text: String,
+    #[serde(flatten)]
+    pub style: Style,
+}
+
+/// Parse a captured line into spans of uniformly styled text. `style` carries
+/// attributes across lines, as tmux only emits changes.
+pub fn parse_line(line: &str, style: &mut Style) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::new();
+    let mut text = String::new();
+    let mut chars = line.chars().peekable();
+
+    let mut flush = |text: &mut String, style: Style| {
+        if text.is_empty() {
+            return;
+        }
+        match spans.last_mut() {
+            Some(last) if last.style == style => last.text.push_str(text),
+            _ => spans.push(Span {
+                text: text.clone(),
+                style,
+            }),
+        }
+        text.clear();
+    };
+
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('[') => {
+                let mut params = String::new();
+                let mut fin = None;
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        fin = Some(c);
+                        break;
+                    }
+                    params.push(c);
+                }
+                if fin == Some('m') {
+                    flush(&mut text, *style);
+                    style.apply_sgr(&params);
+                }
+            }
+            // OSC (e.g. hyperlinks): skip to BEL or ST.
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    flush(&mut text, *style);
+    spans
+}
+
+/// Parse a whole capture, one `Vec<Span>` per line.
+pub fn parse(text: &str) -> Vec<Vec<Span>> {
+    let mut style = Style::default();
+    text.lines().map(|line| parse_line(line, &mut style)).collect()
+}
+
+/// Render a capture as text with inline tags: `[bold red]failed[/]`. A literal
+/// `[` that could start a tag is escaped as `\[`.
+pub fn to_markup(text: &str) -> String {
+    let mut out = String::new();
+    for line in parse(text) {
+        for span in line {
+            let escaped = escape(&span.text);
+            if span.style.is_plain() {
+                out.push_str(&escaped);
+            } else {
+                out.push_str(&format!("[{}]{escaped}[/]", span.style.tag()));
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '['
+            && chars
+                .peek()
+                .is_some_and(|n| n.is_ascii_alphabetic() || *n == '/' || *n == '#')
+        {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_colors_and_attributes() {
+        let spans = parse("ok \x1b[1;31mFAIL\x1b[0m done\n\x1b[38;5;208mx\x1b[48;2;0;0;255my");
+        assert_eq!(spans[0].len(), 3);
+        assert_eq!(spans[0][1].text, "FAIL");
+        assert_eq!(spans[0][1].style.fg, Some(Color::Indexed(1)));
+        assert!(spans[0][1].style.bold);
+        assert!(spans[0][2].style.is_plain());
+        assert_eq!(spans[1][0].style.fg.unwrap().name(), "color208");
+        assert_eq!(spans[1][1].style.bg.unwrap().name(), "#0000ff");
+        assert_eq!(spans[1][1].style.fg, Some(Color::Indexed(208)));
+    }
+
+    #[test]
+    fn style_carries_across_lines() {
+        let spans = parse("\x1b[32mone\ntwo\x1b[39m three");
+        assert_eq!(spans[1][0].style.fg, Some(Color::Indexed(2)));
+        assert_eq!(spans[1][1].style.fg, None);
+    }
+
+    #[test]
+    fn skips_non_sgr_sequences() {
+        let spans = parse("a\x1b[2Kb\x1b]8;;http://x\x1b\\c\x1b]8;;\x07d");
+        assert_eq!(spans, vec![vec![Span {
+            text: "abcd".into(),
+            style: Style::default()
+        }]]);
+    }
+
+    #[test]
+    fn renders_markup() {
+        assert_eq!(
+            to_markup("\x1b[1m\x1b[91merror\x1b[0m: a[i] [x]\n\x1b[7;44msel\x1b[m"),
+            "[bold bright-red]error[/]: a\\[i] \\[x]\n[reverse on-blue]sel[/]\n"
+        );
+    }
+
+    #[test]
+    fn serializes_spans_compactly() {
+        let spans = parse("\x1b[1;32mok\x1b[0m!");
+        assert_eq!(
+            serde_json::to_string(&spans).unwrap(),
+            r#"[[{"text":"ok","fg":"green","bold":true},{"text":"!"}]]"#
+        );
+    }
+}
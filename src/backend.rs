@@ -0,0 +1,135 @@
+use std::{collections::HashMap, fmt::Debug, future::Future, pin::Pin, process::Stdio, time::Duration};
+
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::error::TmuxError;
+
+mod control;
+#[cfg(test)]
+pub mod fake;
+
+pub use control::ControlMode;
+
+pub type TmuxFuture<'a> = Pin<Box<dyn Future<Output = Result<String, TmuxError>> + Send + 'a>>;
+
+/// Something that can execute tmux commands.
+pub trait TmuxBackend: Debug + Send + Sync {
+    /// Run a single tmux command (e.g. `["list-panes", "-t", "work:1"]`) and
+    /// return its stdout.
+    fn run<'a>(&'a self, args: &'a [&'a str]) -> TmuxFuture<'a>;
+
+    /// Like `run`, with `input` on the command's stdin, e.g. for
+    /// `load-buffer -`.
+    fn run_with_input<'a>(&'a self, args: &'a [&'a str], input: &'a [u8]) -> TmuxFuture<'a>;
+}
+
+/// How long a tmux command may run before it is killed.
+#[derive(Debug, Clone)]
+pub struct Timeouts {
+    pub default: Duration,
+    /// Overrides keyed by tmux command name, e.g. "capture-pane".
+    pub per_command: HashMap<String, Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            default: Duration::from_secs(10),
+            per_command: HashMap::new(),
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn for_command(&self, args: &[&str]) -> Duration {
+        args.first()
+            .and_then(|cmd| self.per_command.get(*cmd))
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    fn error(&self, args: &[&str]) -> TmuxError {
+        TmuxError::Timeout(format!(
+            "tmux {} did not finish within {:?}",
+            args.join(" "),
+            self.for_command(args)
+        ))
+    }
+}
+
+/// Runs every command as a separate `tmux` process.
+#[derive(Debug, Default, Clone)]
+pub struct Subprocess {
+    /// Server socket name, passed as `tmux -L <name>`.
+    socket_name: Option<String>,
+    timeouts: Timeouts,
+}
+
+impl Subprocess {
+    pub fn new(socket_name: Option<String>, timeouts: Timeouts) -> Self {
+        Self {
+            socket_name,
+            timeouts,
+        }
+    }
+
+    fn command(&self) -> Command {
+        let mut cmd = Command::new("tmux");
+        if let Some(name) = &self.socket_name {
+            cmd.args(["-L", name]);
+        }
+        cmd
+    }
+}
+
+impl Subprocess {
+    async fn execute(&self, args: &[&str], input: Option<&[u8]>) -> Result<String, TmuxError> {
+        let run = async {
+            // kill_on_drop makes both a timeout and a cancelled request kill
+            // the child rather than leaving it running.
+            let mut child = self
+                .command()
+                .args(args)
+                .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()?;
+            let stdin = child.stdin.take();
+            let write = async {
+                if let (Some(mut stdin), Some(input)) = (stdin, input) {
+                    stdin.write_all(input).await?;
+                }
+                Ok(())
+            };
+            let (written, output) = tokio::join!(write, child.wait_with_output());
+            written.and(output)
+        };
+        let output = tokio::time::timeout(self.timeouts.for_command(args), run)
+            .await
+            .map_err(|_| self.timeouts.error(args))?
+            .map_err(|e: std::io::Error| match e.kind() {
+                std::io::ErrorKind::NotFound => {
+                    TmuxError::NotInstalled("tmux was not found on PATH".into())
+                }
+                _ => TmuxError::Tmux(format!("Failed to run tmux: {e}")),
+            })?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(TmuxError::from_stderr(&stderr));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+impl TmuxBackend for Subprocess {
+    fn run<'a>(&'a self, args: &'a [&'a str]) -> TmuxFuture<'a> {
+        Box::pin(self.execute(args, None))
+    }
+
+    fn run_with_input<'a>(&'a self, args: &'a [&'a str], input: &'a [u8]) -> TmuxFuture<'a> {
+        Box::pin(self.execute(args, Some(input)))
+    }
+}
@@ -0,0 +1,227 @@
+use std::process::Stdio;
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    sync::Mutex,
+};
+
+use super::{Subprocess, Timeouts, TmuxBackend, TmuxFuture};
+use crate::error::TmuxError;
+
+/// Sends commands over one long-lived `tmux -C` client instead of spawning a
+/// process per command. The client attaches with `no-output,ignore-size` so it
+/// neither receives pane output nor resizes windows. It does count as
+/// attached, though: tmux sets `session_attached` for its session, so tools
+/// that report whether a user is attached go by `list-clients` instead and
+/// leave out clients with `client_control_mode` set.
+///
+/// Commands fall back to a one-shot subprocess when no control client can be
+/// attached (e.g. the server has no sessions yet), when an argument contains
+/// a newline, which the control protocol cannot carry, and for `wait-for`,
+/// which a control client acknowledges at once and then blocks on, stalling
+/// every later command.
+///
+/// A command that times out or whose request is cancelled mid-flight leaves
+/// its reply unread, so the client is discarded and a new one attached for
+/// the next command.
+#[derive(Debug)]
+pub struct ControlMode {
+    socket_name: Option<String>,
+    timeouts: Timeouts,
+    fallback: Subprocess,
+    conn: Mutex<Option<Connection>>,
+}
+
+#[derive(Debug)]
+struct Connection {
+    // Held so the client is killed when the connection is dropped.
+    _child: Child,
+    stdin: ChildStdin,
+    lines: Lines<BufReader<ChildStdout>>,
+    /// Set while a command's reply is outstanding.
+    in_flight: bool,
+}
+
+/// The client exited or its pipes broke. `sent` records whether the command
+/// had already been written, in which case it may have run.
+struct Disconnected {
+    sent: bool,
+}
+
+impl Connection {
+    async fn open(socket_name: Option<&str>) -> Option<Self> {
+        let mut cmd = Command::new("tmux");
+        if let Some(name) = socket_name {
+            cmd.args(["-L", name]);
+        }
+        let mut child = cmd
+            .args(["-C", "attach-session", "-f", "no-output,ignore-size"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .ok()?;
+        let stdin = child.stdin.take()?;
+        let lines = BufReader::new(child.stdout.take()?).lines();
+        let mut conn = Self {
+            _child: child,
+            stdin,
+            lines,
+            in_flight: false,
+        };
+        // tmux answers the attach itself with an empty %begin/%end block.
+        conn.read_block(false).await.ok()?.ok()?;
+        Some(conn)
+    }
+
+    async fn execute(&mut self, args: &[&str]) -> Result<Result<String, TmuxError>, Disconnected> {
+        let mut line = args.iter().map(|a| quote(a)).collect::<Vec<_>>().join(" ");
+        line.push('\n');
+        let unsent = |_| Disconnected { sent: false };
+        self.in_flight = true;
+        self.stdin
+            .write_all(line.as_bytes())
+            .await
+            .map_err(unsent)?;
+        self.stdin.flush().await.map_err(unsent)?;
+        let result = self.read_block(true).await;
+        self.in_flight = false;
+        result
+    }
+
+    /// Read up to the next `%begin` ... `%end`/`%error` block, skipping
+    /// notifications such as `%session-changed`. The block only ends at the
+    /// guard line carrying the same time and command number as its `%begin`,
+    /// as output such as captured pane text can hold lines like `%end 1 2 0`.
+    async fn read_block(&mut self, sent: bool) -> Result<Result<String, TmuxError>, Disconnected> {
+        let id = loop {
+            let line = self.next_line(sent).await?;
+            if let Some(id) = guard_id(&line, "%begin") {
+                break id.to_string();
+            }
+            if line == "%exit" || line.starts_with("%exit ") {
+                return Err(Disconnected { sent });
+            }
+        };
+        let mut body = String::new();
+        loop {
+            let line = self.next_line(sent).await?;
+            if guard_id(&line, "%end") == Some(&id) {
+                return Ok(Ok(body));
+            }
+            if guard_id(&line, "%error") == Some(&id) {
+                return Ok(Err(TmuxError::from_stderr(&body)));
+            }
+            body.push_str(&line);
+            body.push('\n');
+        }
+    }
+
+    async fn next_line(&mut self, sent: bool) -> Result<String, Disconnected> {
+        match self.lines.next_line().await {
+            Ok(Some(line)) => Ok(line),
+            _ => Err(Disconnected { sent }),
+        }
+    }
+}
+
+/// The `<time> <number>` of a `%begin`, `%end` or `%error` guard line, which
+/// tmux follows with `<flags>`.
+fn guard_id<'a>(line: &'a str, guard: &str) -> Option<&'a str> {
+    let rest = line.strip_prefix(guard)?.strip_prefix(' ')?;
+    let mut fields = rest.splitn(3, ' ');
+    let time = fields.next()?;
+    let number = fields.next()?;
+    let valid = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    (valid(time) && valid(number)).then(|| &rest[..time.len() + 1 + number.len()])
+}
+
+/// Quote an argument for tmux's command parser.
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+impl ControlMode {
+    pub fn new(socket_name: Option<String>, timeouts: Timeouts) -> Self {
+        Self {
+            fallback: Subprocess::new(socket_name.clone(), timeouts.clone()),
+            socket_name,
+            timeouts,
+            conn: Mutex::new(None),
+        }
+    }
+
+    async fn run_control(&self, args: &[&str]) -> Option<Result<String, TmuxError>> {
+        if args.first() == Some(&"wait-for") || args.iter().any(|a| a.contains('\n')) {
+            return None;
+        }
+        let mut guard = self.conn.lock().await;
+        if guard.as_ref().is_some_and(|c| c.in_flight) {
+            *guard = None;
+        }
+        if guard.is_none() {
+            let open = Connection::open(self.socket_name.as_deref());
+            *guard = tokio::time::timeout(self.timeouts.default, open)
+                .await
+                .ok()
+                .flatten();
+        }
+        let conn = guard.as_mut()?;
+        let timeout = self.timeouts.for_command(args);
+        let Ok(executed) = tokio::time::timeout(timeout, conn.execute(args)).await else {
+            *guard = None;
+            return Some(Err(self.timeouts.error(args)));
+        };
+        match executed {
+            Ok(result) => Some(result),
+            Err(Disconnected { sent }) => {
+                tracing::debug!("tmux control client disconnected");
+                *guard = None;
+                // Don't risk running a command twice.
+                sent.then(|| {
+                    Err(TmuxError::Tmux(
+                        "control client disconnected mid-command".into(),
+                    ))
+                })
+            }
+        }
+    }
+}
+
+impl TmuxBackend for ControlMode {
+    fn run<'a>(&'a self, args: &'a [&'a str]) -> TmuxFuture<'a> {
+        Box::pin(async move {
+            match self.run_control(args).await {
+                Some(result) => result,
+                None => self.fallback.run(args).await,
+            }
+        })
+    }
+
+    /// A control client has no stdin to give a command, so these always run
+    /// as a subprocess.
+    fn run_with_input<'a>(&'a self, args: &'a [&'a str], input: &'a [u8]) -> TmuxFuture<'a> {
+        self.fallback.run_with_input(args, input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_guard_lines() {
+        assert_eq!(
+            guard_id("%begin 1792353161 42 1", "%begin"),
+            Some("1792353161 42")
+        );
+        assert_eq!(
+            guard_id("%end 1792353161 42 1", "%end"),
+            Some("1792353161 42")
+        );
+        assert_eq!(guard_id("%end of the list", "%end"), None);
+        assert_eq!(guard_id("%endless 1 2 0", "%end"), None);
+    }
+}
@@ -0,0 +1,659 @@
+//! An in-memory model of a tmux server for tests. It understands the subset of
+//! commands and format variables the tools use, and answers them the way tmux
+//! would, including tmux's error messages for unknown targets.
+
+use std::sync::Mutex;
+
+use super::{TmuxBackend, TmuxFuture};
+use crate::error::TmuxError;
+
+#[derive(Debug, Clone)]
+pub struct FakePane {
+    pub id: u32,
+    pub index: u32,
+    pub width: u32,
+    pub height: u32,
+    pub command: String,
+    pub path: String,
+    pub title: String,
+    /// Scrollback above the visible area, oldest first.
+    pub history: Vec<String>,
+    /// The visible screen, top to bottom. While `alternate_on`, this is the
+    /// alternate screen.
+    pub screen: Vec<String>,
+    pub alternate_on: bool,
+    /// The normal screen saved while the alternate screen is active.
+    pub saved_screen: Vec<String>,
+    /// The active mode, e.g. "copy-mode".
+    pub mode: Option<String>,
+    pub scroll_position: u32,
+    /// The exit status once the pane's program has exited (remain-on-exit).
+    pub dead_status: Option<i32>,
+    /// The signal that killed the pane's program (remain-on-exit).
+    pub dead_signal: Option<i32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeWindow {
+    pub index: u32,
+    pub name: String,
+    pub panes: Vec<FakePane>,
+    pub active_pane: usize,
+    /// `window_layout`; when unset, the panes are laid out side by side.
+    pub layout: Option<String>,
+}
+
+impl FakeWindow {
+    fn layout(&self) -> String {
+        if let Some(layout) = &self.layout {
+            return layout.clone();
+        }
+        let cell = |p: &FakePane, x: u32| format!("{}x{},{x},0,{}", p.width, p.height, p.id);
+        if let [pane] = self.panes.as_slice() {
+            return format!("0000,{}", cell(pane, 0));
+        }
+        let mut x = 0;
+        let mut cells = Vec::new();
+        for pane in &self.panes {
+            cells.push(cell(pane, x));
+            x += pane.width + 1;
+        }
+        let height = self.panes.iter().map(|p| p.height).max().unwrap_or(0);
+        format!("0000,{}x{height},0,0{{{}}}", x.saturating_sub(1), cells.join(","))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeSession {
+    pub name: String,
+    pub attached: bool,
+    pub windows: Vec<FakeWindow>,
+    pub active_window: usize,
+}
+
+/// A client attached to the server.
+#[derive(Debug, Clone)]
+pub struct FakeClient {
+    /// The pane the client is looking at.
+    pub pane_id: String,
+    pub activity: u64,
+    /// A control-mode client (`tmux -C`), like the control-mode backend's.
+    pub control_mode: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct FakeState {
+    pub sessions: Vec<FakeSession>,
+    pub clients: Vec<FakeClient>,
+    /// Paste buffers as (name, contents), most recent first.
+    pub buffers: Vec<(String, String)>,
+    next_pane_id: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct FakeTmux {
+    state: Mutex<FakeState>,
+    /// Every command run, with its arguments joined by spaces.
+    log: Mutex<Vec<String>>,
+}
+
+impl FakeState {
+    fn new_pane(&mut self, index: u32) -> FakePane {
+        let id = self.next_pane_id;
+        self.next_pane_id += 1;
+        FakePane {
+            id,
+            index,
+            width: 80,
+            height: 24,
+            command: "bash".into(),
+            path: "/home/user".into(),
+            title: "host".into(),
+            history: Vec::new(),
+            screen: Vec::new(),
+            alternate_on: false,
+            saved_screen: Vec::new(),
+            mode: None,
+            scroll_position: 0,
+            dead_status: None,
+            dead_signal: None,
+        }
+    }
+}
+
+impl FakeTmux {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a session with a single window and pane. Returns the pane ID.
+    pub fn new_session(&self, name: &str, window_name: &str) -> String {
+        let mut state = self.state.lock().unwrap();
+        let pane = state.new_pane(0);
+        let id = pane.id;
+        state.sessions.push(FakeSession {
+            name: name.into(),
+            attached: false,
+            windows: vec![FakeWindow {
+                index: 0,
+                name: window_name.into(),
+                panes: vec![pane],
+                active_pane: 0,
+                layout: None,
+            }],
+            active_window: 0,
+        });
+        format!("%{id}")
+    }
+
+    /// Append a window to a session. Returns the new pane ID.
+    pub fn new_window(&self, session: &str, window_name: &str) -> String {
+        let mut state = self.state.lock().unwrap();
+        let pane = state.new_pane(0);
+        let id = pane.id;
+        let s = state
+            .sessions
+            .iter_mut()
+            .find(|s| s.name == session)
+            .expect("no such session");
+        let index = s.windows.iter().map(|w| w.index + 1).max().unwrap_or(0);
+        s.windows.push(FakeWindow {
+            index,
+            name: window_name.into(),
+            panes: vec![pane],
+            active_pane: 0,
+            layout: None,
+        });
+        format!("%{id}")
+    }
+
+    /// Add a pane to the window `session:index`. Returns the new pane ID.
+    pub fn split_window(&self, session: &str, window_index: u32) -> String {
+        let mut state = self.state.lock().unwrap();
+        let mut pane = state.new_pane(0);
+        let id = pane.id;
+        let w = state
+            .sessions
+            .iter_mut()
+            .find(|s| s.name == session)
+            .and_then(|s| s.windows.iter_mut().find(|w| w.index == window_index))
+            .expect("no such window");
+        pane.index = w.panes.len() as u32;
+        w.panes.push(pane);
+        format!("%{id}")
+    }
+
+    /// Modify a pane by ID, e.g. to set its command or screen contents.
+    pub fn with_pane(&self, pane_id: &str, f: impl FnOnce(&mut FakePane)) {
+        let mut state = self.state.lock().unwrap();
+        let id: u32 = pane_id.trim_start_matches('%').parse().expect("bad pane id");
+        let pane = state
+            .sessions
+            .iter_mut()
+            .flat_map(|s| s.windows.iter_mut())
+            .flat_map(|w| w.panes.iter_mut())
+            .find(|p| p.id == id)
+            .expect("no such pane");
+        f(pane);
+    }
+
+    /// Modify the raw state, e.g. to mark a session attached.
+    pub fn with_state(&self, f: impl FnOnce(&mut FakeState)) {
+        f(&mut self.state.lock().unwrap());
+    }
+
+    /// The commands run so far, e.g. "select-layout -t work:0 tiled".
+    pub fn commands(&self) -> Vec<String> {
+        self.log.lock().unwrap().clone()
+    }
+
+    fn execute(&self, args: &[&str], input: &[u8]) -> Result<String, String> {
+        self.log.lock().unwrap().push(args.join(" "));
+        let mut state = self.state.lock().unwrap();
+        if state.sessions.is_empty() {
+            return Err("no server running on /tmp/tmux-fake/default".into());
+        }
+        let (command, rest) = args.split_first().ok_or("no command")?;
+        let flags = Flags::parse(rest);
+        match *command {
+            "list-sessions" | "ls" => {
+                let format = flags.value('F').unwrap_or("#{session_name}");
+                Ok(state
+                    .sessions
+                    .iter()
+                    .map(|s| expand(format, &Ctx::session(s)))
+                    .collect())
+            }
+            "list-windows" | "lsw" => {
+                let format = flags.value('F').unwrap_or("#{window_index}");
+                let sessions: Vec<&FakeSession> = if flags.has('a') {
+                    state.sessions.iter().collect()
+                } else {
+                    vec![state.resolve(flags.value('t').unwrap_or(""))?.session]
+                };
+                Ok(sessions
+                    .into_iter()
+                    .flat_map(|s| s.windows.iter().map(move |w| expand(format, &Ctx::window(s, w))))
+                    .collect())
+            }
+            "list-panes" | "lsp" => {
+                let format = flags.value('F').unwrap_or("#{pane_index}");
+                let mut out = String::new();
+                if flags.has('a') {
+                    for s in &state.sessions {
+                        for w in &s.windows {
+                            for p in &w.panes {
+                                out.push_str(&expand(format, &Ctx::pane(s, w, p)));
+                            }
+                        }
+                    }
+                } else if flags.has('s') {
+                    let t = state.resolve(flags.value('t').unwrap_or(""))?;
+                    for w in &t.session.windows {
+                        for p in &w.panes {
+                            out.push_str(&expand(format, &Ctx::pane(t.session, w, p)));
+                        }
+                    }
+                } else {
+                    let t = state.resolve(flags.value('t').unwrap_or(""))?;
+                    for p in &t.window.panes {
+                        out.push_str(&expand(format, &Ctx::pane(t.session, t.window, p)));
+                    }
+                }
+                Ok(out)
+            }
+            "display-message" | "display" => {
+                let t = state.resolve(flags.value('t').unwrap_or(""))?;
+                let format = flags.positional.first().copied().unwrap_or("");
+                Ok(expand(format, &Ctx::pane(t.session, t.window, t.pane)))
+            }
+            "capture-pane" | "capturep" => {
+                let t = state.resolve(flags.value('t').unwrap_or(""))?;
+                let pane = t.pane;
+                if flags.has('a') {
+                    if !pane.alternate_on {
+                        return if flags.has('q') {
+                            Ok(String::new())
+                        } else {
+                            Err("no alternate screen".into())
+                        };
+                    }
+                    return Ok(pane.saved_screen.iter().map(|l| format!("{l}\n")).collect());
+                }
+                // Line numbers as tmux counts them: 0 is the top of the visible
+                // screen, negative numbers are history.
+                let lines: Vec<&String> = pane.history.iter().chain(&pane.screen).collect();
+                let origin = pane.history.len() as i64;
+                // "-" means the start of history for -S and the end for -E.
+                let absolute = |flag: char| {
+                    let n = match flags.value(flag)? {
+                        "-" if flag == 'S' => -origin,
+                        "-" => lines.len() as i64,
+                        v => v.parse::<i64>().ok()?,
+                    };
+                    Some((origin + n).clamp(0, lines.len() as i64) as usize)
+                };
+                let start = absolute('S').unwrap_or(origin as usize);
+                let end = absolute('E').map_or(lines.len(), |e| (e + 1).min(lines.len()));
+                // Lines may hold SGR sequences, which only `-e` keeps.
+                let keep_escapes = flags.has('e');
+                Ok(lines[start..end.max(start)]
+                    .iter()
+                    .map(|l| {
+                        if keep_escapes {
+                            format!("{l}\n")
+                        } else {
+                            let text: String = crate::ansi::parse_line(l, &mut Default::default())
+                                .into_iter()
+                                .map(|span| span.text)
+                                .collect();
+                            format!("{text}\n")
+                        }
+                    })
+                    .collect())
+            }
+            "list-clients" | "lsc" => {
+                let format = flags.value('F').unwrap_or("#{client_name}");
+                let mut out = String::new();
+                for client in &state.clients {
+                    let t = state.resolve(&client.pane_id)?;
+                    let format = format
+                        .replace("#{client_activity}", &client.activity.to_string())
+                        .replace("#{client_control_mode}", if client.control_mode { "1" } else { "0" });
+                    out.push_str(&expand(&format, &Ctx::pane(t.session, t.window, t.pane)));
+                }
+                Ok(out)
+            }
+            "load-buffer" | "loadb" => {
+                let name = match flags.value('b') {
+                    Some(name) => name.to_string(),
+                    None => (0..)
+                        .map(|n| format!("buffer{n}"))
+                        .find(|n| state.buffers.iter().all(|(b, _)| b != n))
+                        .unwrap(),
+                };
+                state.buffers.retain(|(n, _)| *n != name);
+                let contents = String::from_utf8_lossy(input).into_owned();
+                state.buffers.insert(0, (name, contents));
+                Ok(String::new())
+            }
+            "list-buffers" | "lsb" => {
+                let format = flags.value('F').unwrap_or("#{buffer_name}");
+                Ok(state
+                    .buffers
+                    .iter()
+                    .map(|(name, contents)| {
+                        let sample: String = contents.escape_default().take(50).collect();
+                        format
+                            .replace("#{buffer_name}", name)
+                            .replace("#{buffer_size}", &contents.len().to_string())
+                            .replace("#{buffer_created}", "0")
+                            .replace("#{buffer_sample}", &sample)
+                            + "\n"
+                    })
+                    .collect())
+            }
+            "show-buffer" | "showb" => {
+                let buffer = match flags.value('b') {
+                    Some(name) => state.buffers.iter().find(|(n, _)| n == name),
+                    None => state.buffers.first(),
+                };
+                match buffer {
+                    Some((_, contents)) => Ok(contents.clone()),
+                    None => Err(format!("no buffer {}", flags.value('b').unwrap_or(""))),
+                }
+            }
+            "paste-buffer" | "pasteb" => {
+                state.resolve(flags.value('t').unwrap_or(""))?;
+                let name = flags.value('b').ok_or("fake: paste-buffer needs -b")?;
+                let index = state
+                    .buffers
+                    .iter()
+                    .position(|(n, _)| n == name)
+                    .ok_or_else(|| format!("no buffer {name}"))?;
+                if flags.has('d') {
+                    state.buffers.remove(index);
+                }
+                Ok(String::new())
+            }
+            "delete-buffer" | "deleteb" => {
+                let name = flags.value('b').ok_or("fake: delete-buffer needs -b")?;
+                let index = state
+                    .buffers
+                    .iter()
+                    .position(|(n, _)| n == name)
+                    .ok_or_else(|| format!("unknown buffer: {name}"))?;
+                state.buffers.remove(index);
+                Ok(String::new())
+            }
+            // Commands that change the layout or send input are only checked
+            // for a valid target; tests assert on them through `commands()`.
+            "select-layout" | "selectl" | "resize-pane" | "resizep" | "send-keys" | "send"
+            | "respawn-pane" | "respawnp" => {
+                state.resolve(flags.value('t').unwrap_or(""))?;
+                Ok(String::new())
+            }
+            other => Err(format!("unknown command: {other}")),
+        }
+    }
+}
+
+impl TmuxBackend for FakeTmux {
+    fn run<'a>(&'a self, args: &'a [&'a str]) -> TmuxFuture<'a> {
+        self.run_with_input(args, &[])
+    }
+
+    fn run_with_input<'a>(&'a self, args: &'a [&'a str], input: &'a [u8]) -> TmuxFuture<'a> {
+        let result = self.execute(args, input).map_err(|e| TmuxError::from_stderr(&e));
+        Box::pin(async move { result })
+    }
+}
+
+/// Command flags. Every flag in `VALUE_FLAGS` takes an argument; other
+/// single-letter flags are boolean.
+struct Flags<'a> {
+    values: Vec<(char, &'a str)>,
+    switches: Vec<char>,
+    positional: Vec<&'a str>,
+}
+
+const VALUE_FLAGS: &str = "tFSExyb";
+
+impl<'a> Flags<'a> {
+    fn parse(args: &[&'a str]) -> Self {
+        let mut flags = Flags {
+            values: Vec::new(),
+            switches: Vec::new(),
+            positional: Vec::new(),
+        };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.strip_prefix('-') {
+                Some(f) if !f.is_empty() && !arg.starts_with("--") && !is_number(arg) => {
+                    for c in f.chars() {
+                        if VALUE_FLAGS.contains(c) {
+                            flags.values.push((c, iter.next().copied().unwrap_or("")));
+                        } else {
+                            flags.switches.push(c);
+                        }
+                    }
+                }
+                _ => flags.positional.push(arg),
+            }
+        }
+        flags
+    }
+
+    fn value(&self, flag: char) -> Option<&'a str> {
+        self.values.iter().find(|(c, _)| *c == flag).map(|(_, v)| *v)
+    }
+
+    fn has(&self, flag: char) -> bool {
+        self.switches.contains(&flag)
+    }
+}
+
+fn is_number(s: &str) -> bool {
+    s.parse::<i64>().is_ok()
+}
+
+struct Target<'a> {
+    session: &'a FakeSession,
+    window: &'a FakeWindow,
+    pane: &'a FakePane,
+}
+
+impl FakeState {
+    /// Resolve "%id", "sess", "sess:", "sess:win" or "sess:win.pane".
+    fn resolve(&self, target: &str) -> Result<Target<'_>, String> {
+        if let Some(id) = target.strip_prefix('%') {
+            for session in &self.sessions {
+                for window in &session.windows {
+                    for pane in &window.panes {
+                        if id.parse() == Ok(pane.id) {
+                            return Ok(Target {
+                                session,
+                                window,
+                                pane,
+                            });
+                        }
+                    }
+                }
+            }
+            return Err(format!("can't find pane: {target}"));
+        }
+
+        let (session_name, rest) = target.split_once(':').unwrap_or((target, ""));
+        // "=name" asks for an exact match, which is the only kind modelled.
+        let session_name = session_name.strip_prefix('=').unwrap_or(session_name);
+        let session = self
+            .sessions
+            .iter()
+            .find(|s| s.name == session_name)
+            .ok_or_else(|| format!("can't find session: {session_name}"))?;
+        let (window_part, pane_part) = match rest.split_once('.') {
+            Some((w, p)) => (w, Some(p)),
+            None => (rest, None),
+        };
+        let window = if window_part.is_empty() {
+            &session.windows[session.active_window]
+        } else {
+            session
+                .windows
+                .iter()
+                .find(|w| w.index.to_string() == window_part || w.name == window_part)
+                .ok_or_else(|| format!("can't find window: {window_part}"))?
+        };
+        let pane = match pane_part {
+            None => &window.panes[window.active_pane],
+            Some(p) => window
+                .panes
+                .iter()
+                .find(|x| x.index.to_string() == p)
+                .ok_or_else(|| format!("can't find pane: {p}"))?,
+        };
+        Ok(Target {
+            session,
+            window,
+            pane,
+        })
+    }
+}
+
+/// The objects a format is expanded against.
+struct Ctx<'a> {
+    session: &'a FakeSession,
+    window: Option<&'a FakeWindow>,
+    pane: Option<&'a FakePane>,
+}
+
+impl<'a> Ctx<'a> {
+    fn session(session: &'a FakeSession) -> Self {
+        Ctx {
+            session,
+            window: None,
+            pane: None,
+        }
+    }
+
+    fn window(session: &'a FakeSession, window: &'a FakeWindow) -> Self {
+        Ctx {
+            session,
+            window: Some(window),
+            pane: None,
+        }
+    }
+
+    fn pane(session: &'a FakeSession, window: &'a FakeWindow, pane: &'a FakePane) -> Self {
+        Ctx {
+            session,
+            window: Some(window),
+            pane: Some(pane),
+        }
+    }
+
+    fn variable(&self, name: &str) -> String {
+        let s = self.session;
+        let w = self
+            .window
+            .unwrap_or(&s.windows[s.active_window]);
+        let p = self.pane.unwrap_or(&w.panes[w.active_pane]);
+        let flag = |b: bool| if b { "1" } else { "0" }.to_string();
+        match name {
+            "session_name" => s.name.clone(),
+            "session_windows" => s.windows.len().to_string(),
+            "session_attached" => flag(s.attached),
+            "window_index" => w.index.to_string(),
+            "window_name" => w.name.clone(),
+            "window_panes" => w.panes.len().to_string(),
+            "window_layout" => w.layout(),
+            "window_active" => flag(std::ptr::eq(w, &s.windows[s.active_window])),
+            "pane_id" => format!("%{}", p.id),
+            "pane_index" => p.index.to_string(),
+            "pane_width" => p.width.to_string(),
+            "pane_height" => p.height.to_string(),
+            "pane_current_command" => p.command.clone(),
+            "pane_current_path" => p.path.clone(),
+            "pane_title" => p.title.clone(),
+            "pane_active" => flag(std::ptr::eq(p, &w.panes[w.active_pane])),
+            "history_size" => p.history.len().to_string(),
+            // The cursor sits on the last screen line, e.g. the prompt.
+            "cursor_y" => p.screen.len().saturating_sub(1).to_string(),
+            "alternate_on" => flag(p.alternate_on),
+            "pane_in_mode" => flag(p.mode.is_some()),
+            "pane_mode" => p.mode.clone().unwrap_or_default(),
+            "scroll_position" => p.scroll_position.to_string(),
+            "pane_dead" => flag(p.dead_status.is_some() || p.dead_signal.is_some()),
+            "pane_dead_status" => p.dead_status.map(|s| s.to_string()).unwrap_or_default(),
+            "pane_dead_signal" => p.dead_signal.map(|s| s.to_string()).unwrap_or_default(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// Expand `#{var}` and `#{?var,then,else}` and terminate the line, like the
+/// `-F` output of a list command.
+fn expand(format: &str, ctx: &Ctx) -> String {
+    let mut out = expand_inner(format, ctx);
+    out.push('\n');
+    out
+}
+
+fn expand_inner(format: &str, ctx: &Ctx) -> String {
+    let mut out = String::new();
+    let mut rest = format;
+    while let Some(start) = rest.find("#{") {
+        out.push_str(&rest[..start]);
+        let body_start = start + 2;
+        let Some(len) = matching_brace(&rest[body_start..]) else {
+            out.push_str(&rest[start..]);
+            return out;
+        };
+        let body = &rest[body_start..body_start + len];
+        if let Some(cond) = body.strip_prefix('?') {
+            let parts = split_top_level(cond);
+            let value = ctx.variable(parts[0]);
+            let truthy = !value.is_empty() && value != "0";
+            let branch = if truthy { parts.get(1) } else { parts.get(2) };
+            out.push_str(&expand_inner(branch.copied().unwrap_or(""), ctx));
+        } else {
+            out.push_str(&ctx.variable(body));
+        }
+        rest = &rest[body_start + len + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Length of `s` up to the `}` closing an already-opened `#{`.
+fn matching_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn split_top_level(s: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
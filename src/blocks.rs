@@ -0,0 +1,365 @@
+//! Splitting a pane's output into command blocks: a command line, its output
+//! and, when the shell reports them, its exit code and duration.
+//!
+//! Shells with semantic prompt integration mark their output with OSC 133:
+//! `A` where the prompt starts, `B` where the typed command starts, `C` where
+//! its output starts and `D;<exit code>` when it finishes. Those marks never
+//! reach capture-pane, so a [`Recorder`] reads them from the pane's raw output
+//! (via pipe-pane) as it arrives. Without integration, [`split_at_prompts`]
+//! guesses the blocks from a capture by matching prompt lines.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime},
+};
+
+use regex::Regex;
+
+/// Matches up to the end of a typical prompt ending in `$`, `#`, `%` or `❯`
+/// and a space, e.g. `user@host:~/src$ `.
+pub const DEFAULT_PROMPT: &str = r"^.{0,80}?[$#%❯] ";
+
+/// How many finished commands a recorder keeps.
+const MAX_BLOCKS: usize = 100;
+
+/// How much of one command's raw output a recorder keeps.
+const MAX_OUTPUT_BYTES: usize = 1 << 20;
+
+/// How long a mark's parameters can be. An introducer not terminated within
+/// this many bytes is stray output, not a mark.
+const MAX_MARK_BYTES: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub command: String,
+    pub output: Vec<String>,
+    pub exit_code: Option<i32>,
+    pub started: Option<SystemTime>,
+    /// None while the command is still running.
+    pub duration: Option<Duration>,
+    /// Whether the start of the output was dropped.
+    pub truncated: bool,
+}
+
+impl Block {
+    fn new(command: String) -> Self {
+        Block {
+            command,
+            output: Vec::new(),
+            exit_code: None,
+            started: None,
+            duration: None,
+            truncated: false,
+        }
+    }
+}
+
+/// Split captured lines into blocks at each line `prompt` matches; the rest of
+/// that line is the command. Lines before the first prompt and prompts with
+/// no command are dropped.
+pub fn split_at_prompts(lines: &[&str], prompt: &Regex) -> Vec<Block> {
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut current: Option<Block> = None;
+    for line in lines {
+        if let Some(m) = prompt.find(line) {
+            blocks.extend(current.take());
+            let command = line[m.end()..].trim();
+            current = (!command.is_empty()).then(|| Block::new(command.to_string()));
+        } else if let Some(block) = &mut current {
+            block.output.push(line.trim_end().to_string());
+        }
+    }
+    blocks.extend(current);
+    for block in &mut blocks {
+        while block.output.last().is_some_and(|l| l.is_empty()) {
+            block.output.pop();
+        }
+    }
+    blocks
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Before any mark, or after `A`: the prompt is being drawn.
+    Prompt,
+    /// After `B`: the command is being typed.
+    Input,
+    /// After `C`: the command is running.
+    Output,
+}
+
+/// Builds blocks from a pane's raw output as it arrives, going by its OSC 133
+/// marks.
+#[derive(Debug)]
+pub struct Recorder {
+    phase: Phase,
+    /// Raw bytes of the current phase.
+    segment: Vec<u8>,
+    /// Bytes of an escape sequence cut off at the end of the last chunk.
+    carry: Vec<u8>,
+    running: Option<Block>,
+    blocks: VecDeque<Block>,
+    /// Whether any mark has been seen, i.e. the shell has integration.
+    pub marked: bool,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Recorder {
+            phase: Phase::Prompt,
+            segment: Vec::new(),
+            carry: Vec::new(),
+            running: None,
+            blocks: VecDeque::new(),
+            marked: false,
+        }
+    }
+}
+
+impl Recorder {
+    /// Feed output that arrived at `now`.
+    pub fn feed(&mut self, chunk: &[u8], now: SystemTime) {
+        let mut data = std::mem::take(&mut self.carry);
+        data.extend_from_slice(chunk);
+        let mut rest = &data[..];
+        while let Some(start) = find(rest, b"\x1b]133;") {
+            let params = &rest[start + 6..];
+            // Terminated by BEL or ST (ESC \), whichever comes first.
+            let end = params
+                .iter()
+                .take(MAX_MARK_BYTES)
+                .enumerate()
+                .find_map(|(i, &b)| match (b, params.get(i + 1)) {
+                    (0x07, _) => Some((i, 1)),
+                    (0x1b, Some(b'\\')) => Some((i, 2)),
+                    _ => None,
+                });
+            let Some((len, terminator)) = end else {
+                self.push_segment(&rest[..start]);
+                if params.len() < MAX_MARK_BYTES {
+                    self.carry = rest[start..].to_vec();
+                    return;
+                }
+                // Never terminated: keep it as text, without the escape that
+                // would hide what follows.
+                self.push_segment(&rest[start + 1..start + 6]);
+                rest = params;
+                continue;
+            };
+            self.push_segment(&rest[..start]);
+            self.mark(&String::from_utf8_lossy(&params[..len]), now);
+            rest = &params[len + terminator..];
+        }
+        // Keep a partial introducer for the next chunk.
+        let keep = (1..6)
+            .rev()
+            .find(|&n| rest.ends_with(&b"\x1b]133;"[..n]))
+            .unwrap_or(0);
+        self.push_segment(&rest[..rest.len() - keep]);
+        self.carry = rest[rest.len() - keep..].to_vec();
+    }
+
+    fn push_segment(&mut self, bytes: &[u8]) {
+        self.segment.extend_from_slice(bytes);
+        if self.segment.len() > MAX_OUTPUT_BYTES {
+            let excess = self.segment.len() - MAX_OUTPUT_BYTES;
+            self.segment.drain(..excess);
+            if let Some(block) = &mut self.running {
+                block.truncated = true;
+            }
+        }
+    }
+
+    fn mark(&mut self, params: &str, now: SystemTime) {
+        self.marked = true;
+        let mut fields = params.split(';');
+        match fields.next().unwrap_or_default() {
+            "A" => {
+                // A shell that never sends `D` still starts a new prompt.
+                self.finish(None, now);
+                self.phase = Phase::Prompt;
+            }
+            "B" => self.phase = Phase::Input,
+            "C" => {
+                self.finish(None, now);
+                let typed = clean(&self.segment);
+                let command = typed
+                    .iter()
+                    .rev()
+                    .find(|l| !l.trim().is_empty())
+                    .map_or("", |l| l.trim());
+                let mut block = Block::new(command.to_string());
+                block.started = Some(now);
+                self.running = Some(block);
+                self.phase = Phase::Output;
+            }
+            "D" => {
+                let code = fields.next().and_then(|c| c.trim().parse().ok());
+                self.finish(code, now);
+                self.phase = Phase::Prompt;
+            }
+            _ => return,
+        }
+        self.segment.clear();
+    }
+
+    fn finish(&mut self, exit_code: Option<i32>, now: SystemTime) {
+        let Some(mut block) = self.running.take() else {
+            return;
+        };
+        if self.phase == Phase::Output {
+            block.output = clean(&self.segment);
+        }
+        block.exit_code = exit_code;
+        block.duration = block.started.and_then(|s| now.duration_since(s).ok());
+        if self.blocks.len() == MAX_BLOCKS {
+            self.blocks.pop_front();
+        }
+        self.blocks.push_back(block);
+    }
+
+    /// The finished commands, oldest first, followed by the one running now.
+    pub fn blocks(&self) -> Vec<Block> {
+        let mut blocks: Vec<Block> = self.blocks.iter().cloned().collect();
+        if let Some(running) = &self.running {
+            blocks.push(Block {
+                output: clean(&self.segment),
+                ..running.clone()
+            });
+        }
+        blocks
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Turn raw terminal output into lines of text: escape sequences are dropped,
+/// a carriage return starts the line over and backspace erases.
+pub fn clean(raw: &[u8]) -> Vec<String> {
+    let text = String::from_utf8_lossy(raw);
+    let mut lines = vec![String::new()];
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let line = lines.last_mut().unwrap();
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI: parameters and intermediates up to a final byte.
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC, DCS and friends: up to BEL or ST.
+                Some(']' | 'P' | '_' | '^') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                // Charset selection takes one more character.
+                Some('(' | ')') => {
+                    chars.next();
+                }
+                _ => {}
+            },
+            '\n' => lines.push(String::new()),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\r' => line.clear(),
+            '\x08' => {
+                line.pop();
+            }
+            '\t' => line.push(c),
+            c if c.is_control() => {}
+            c => line.push(c),
+        }
+    }
+    while lines.last().is_some_and(|l| l.trim().is_empty()) {
+        lines.pop();
+    }
+    lines
+        .into_iter()
+        .map(|l| l.trim_end().to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_captures_at_prompts() {
+        let prompt = Regex::new(DEFAULT_PROMPT).unwrap();
+        let lines = [
+            "Last login: today",
+            "user@host:~/src$ ls",
+            "Cargo.toml  src",
+            "user@host:~/src$ ",
+            "user@host:~/src$ cargo build",
+            "   Compiling demo",
+            "",
+            "user@host:~/src$ ",
+        ];
+        let blocks = split_at_prompts(&lines, &prompt);
+        let summary: Vec<_> = blocks
+            .iter()
+            .map(|b| (b.command.as_str(), b.output.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("ls", vec!["Cargo.toml  src".to_string()]),
+                ("cargo build", vec!["   Compiling demo".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn records_blocks_from_marks_split_across_chunks() {
+        let t0 = SystemTime::UNIX_EPOCH;
+        let at = |secs| t0 + Duration::from_secs(secs);
+        let mut recorder = Recorder::default();
+        recorder.feed(b"\x1b]133;A\x07$ \x1b]133;B\x07ls -l\r\n\x1b]1", at(0));
+        recorder.feed(b"33;C\x07total 0\r\n\x1b[1mfile\x1b[0m\r\n", at(1));
+        let running = recorder.blocks();
+        assert_eq!(running[0].command, "ls -l");
+        assert_eq!(running[0].output, vec!["total 0", "file"]);
+        assert_eq!(running[0].duration, None);
+
+        recorder.feed(b"\x1b]133;D;2\x1b\\\x1b]133;A\x07$ \x1b]133;B\x07", at(4));
+        recorder.feed(b"sleep 1\x082\r\n\x1b]133;C\x07", at(5));
+        let blocks = recorder.blocks();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].exit_code, Some(2));
+        assert_eq!(blocks[0].duration, Some(Duration::from_secs(3)));
+        assert_eq!(blocks[1].command, "sleep 2");
+        assert!(recorder.marked);
+    }
+
+    #[test]
+    fn treats_unterminated_marks_as_output() {
+        let mut recorder = Recorder::default();
+        recorder.feed(b"\x1b]133;C\x07", SystemTime::UNIX_EPOCH);
+        recorder.feed(b"stray \x1b]133;", SystemTime::UNIX_EPOCH);
+        for _ in 0..100 {
+            recorder.feed(&[b'x'; 100], SystemTime::UNIX_EPOCH);
+        }
+        assert!(recorder.carry.len() < MAX_MARK_BYTES + 6);
+        recorder.feed(b"\r\n\x1b]133;D;0\x07", SystemTime::UNIX_EPOCH);
+        let blocks = recorder.blocks();
+        assert_eq!(blocks[0].exit_code, Some(0));
+        assert!(blocks[0].output[0].starts_with("stray ]133;xxx"));
+    }
+
+    #[test]
+    fn cleans_terminal_output() {
+        assert_eq!(
+            clean(b"\x1b]0;title\x0750%\r100%\r\n\x1b[32mok\x1b[m\r\n\r\n"),
+            vec!["100%", "ok"]
+        );
+    }
+}
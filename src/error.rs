@@ -0,0 +1,88 @@
+use std::fmt;
+
+use rmcp::model::{Content, IntoContents};
+
+/// Why a tool call failed. Tools return `Result<_, TmuxError>`, which rmcp turns
+/// into a result with `is_error: true` whose text starts with the error kind,
+/// e.g. "target_not_found: can't find pane: 9".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TmuxError {
+    /// A relative target or "current" query was used without $TMUX_PANE.
+    NotInTmux(String),
+    /// tmux couldn't find the session, window or pane.
+    TargetNotFound(String),
+    /// The tmux binary couldn't be executed.
+    NotInstalled(String),
+    /// No tmux server is listening on the socket.
+    ServerNotRunning(String),
+    /// A safety check refused the request.
+    PolicyDenied(String),
+    /// A tmux command didn't finish in time.
+    Timeout(String),
+    /// The client cancelled the request.
+    Cancelled(String),
+    /// The tool arguments were malformed, e.g. a bad target or pattern.
+    InvalidArgument(String),
+    /// Any other tmux failure.
+    Tmux(String),
+}
+
+impl TmuxError {
+    /// Classify a tmux error message (its stderr, or the body of a
+    /// control-mode `%error` block).
+    pub fn from_stderr(stderr: &str) -> Self {
+        let msg = stderr.trim().to_string();
+        if msg.starts_with("no server running") || msg.starts_with("error connecting to") {
+            Self::ServerNotRunning(msg)
+        } else if msg.starts_with("can't find")
+            || msg.starts_with("no buffer")
+            || msg.starts_with("unknown buffer")
+        {
+            Self::TargetNotFound(msg)
+        } else {
+            Self::Tmux(msg)
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::NotInTmux(_) => "not_in_tmux",
+            Self::TargetNotFound(_) => "target_not_found",
+            Self::NotInstalled(_) => "tmux_not_installed",
+            Self::ServerNotRunning(_) => "server_not_running",
+            Self::PolicyDenied(_) => "policy_denied",
+            Self::Timeout(_) => "timeout",
+            Self::Cancelled(_) => "cancelled",
+            Self::InvalidArgument(_) => "invalid_argument",
+            Self::Tmux(_) => "tmux_error",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Self::NotInTmux(m)
+            | Self::TargetNotFound(m)
+            | Self::NotInstalled(m)
+            | Self::ServerNotRunning(m)
+            | Self::PolicyDenied(m)
+            | Self::Timeout(m)
+            | Self::Cancelled(m)
+            | Self::InvalidArgument(m)
+            | Self::Tmux(m) => m,
+        }
+    }
+}
+
+impl fmt::Display for TmuxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind(), self.message())
+    }
+}
+
+impl std::error::Error for TmuxError {}
+
+impl IntoContents for TmuxError {
+    fn into_contents(self) -> Vec<Content> {
+        vec![Content::text(self.to_string())]
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result, bail};
+use axum::{
+    Router,
+    extract::{Request, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+};
+use rmcp::transport::streamable_http_server::{
+    StreamableHttpServerConfig, StreamableHttpService, session::local::LocalSessionManager,
+};
+use tokio::net::{TcpListener, UnixListener};
+
+use crate::TmuxMcp;
+
+/// Where the streamable HTTP transport listens.
+pub enum Listen {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+pub struct HttpOptions {
+    pub listen: Listen,
+    /// When set, every request must carry `Authorization: Bearer <token>`.
+    pub token: Option<String>,
+}
+
+/// Serve the MCP endpoint at `/mcp` until Ctrl-C. Each client session gets a
+/// clone of `mcp`, sharing its tool router and tmux backend but with its own
+/// read cursors.
+pub async fn serve(opts: HttpOptions, mcp: TmuxMcp) -> Result<()> {
+    let config = StreamableHttpServerConfig::default();
+    let cancel = config.cancellation_token.clone();
+    let service = StreamableHttpService::new(
+        move || Ok(mcp.for_new_client()),
+        LocalSessionManager::default().into(),
+        config,
+    );
+
+    let mut router = Router::new().nest_service("/mcp", service);
+    if let Listen::Tcp(addr) = &opts.listen
+        && addr.ip().is_loopback()
+    {
+        router = router.layer(middleware::from_fn(require_loopback_origin));
+    }
+    if let Some(token) = opts.token {
+        router = router.layer(middleware::from_fn_with_state(
+            Arc::new(token),
+            require_bearer,
+        ));
+    }
+
+    let shutdown = async move {
+        let _ = tokio::signal::ctrl_c().await;
+        cancel.cancel();
+    };
+
+    match opts.listen {
+        Listen::Tcp(addr) => {
+            let listener = TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("failed to bind {addr}"))?;
+            tracing::info!("Serving streamable HTTP on http://{addr}/mcp");
+            axum::serve(listener, router)
+                .with_graceful_shutdown(shutdown)
+                .await?;
+        }
+        Listen::Unix(path) => {
+            let listener = bind_private(&path)?;
+            tracing::info!("Serving streamable HTTP on unix:{}", path.display());
+            let result = axum::serve(listener, router)
+                .with_graceful_shutdown(shutdown)
+                .await;
+            let _ = std::fs::remove_file(&path);
+            result?;
+        }
+    }
+    Ok(())
+}
+
+/// Bind a Unix socket at `path` that only this user can connect to. The
+/// socket is bound and made 0600 inside a private directory and then moved
+/// into place, so there is no moment when others could connect. Only a
+/// socket left behind by a previous run is replaced, not one a running
+/// server still answers on.
+fn bind_private(path: &Path) -> Result<UnixListener> {
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            bail!("refusing to replace {}: it is not a socket", path.display());
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            bail!("failed to bind {}: address in use", path.display());
+        }
+    }
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let dir = tempfile::Builder::new()
+        .prefix(".tmux-mcp-")
+        .permissions(std::fs::Permissions::from_mode(0o700))
+        .tempdir_in(parent)
+        .with_context(|| format!("failed to create a directory in {}", parent.display()))?;
+    let staged = dir.path().join("socket");
+    let listener = UnixListener::bind(&staged)
+        .with_context(|| format!("failed to bind {}", path.display()))?;
+    std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+    std::fs::rename(&staged, path).with_context(|| format!("failed to bind {}", path.display()))?;
+    Ok(listener)
+}
+
+/// Reject requests that a web page could have made: on a loopback address,
+/// `Host` and any `Origin` must name a loopback host too, or a page using
+/// DNS rebinding could reach the tools through the browser.
+async fn require_loopback_origin(request: Request, next: Next) -> Response {
+    if !is_loopback_request(request.headers()) {
+        return (
+            StatusCode::FORBIDDEN,
+            "only loopback hosts and origins are allowed",
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+fn is_loopback_request(headers: &HeaderMap) -> bool {
+    let host_ok = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(is_loopback_host);
+    let origin_ok = headers.get(header::ORIGIN).is_none_or(|origin| {
+        let origin = origin.to_str().unwrap_or_default();
+        origin
+            .strip_prefix("http://")
+            .or_else(|| origin.strip_prefix("https://"))
+            .is_some_and(is_loopback_host)
+    });
+    host_ok && origin_ok
+}
+
+/// Whether `host`, a `Host` header or the authority of an `Origin`, names a
+/// loopback host, e.g. `localhost:8808`, `127.0.0.1` or `[::1]:8808`.
+fn is_loopback_host(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((ip, port)) if port.is_empty() || port.starts_with(':') => ip,
+            _ => return false,
+        },
+        None => host.split_once(':').map_or(host, |(name, _)| name),
+    };
+    name.eq_ignore_ascii_case("localhost")
+        || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Compare a presented token with the expected one in time that doesn't
+/// depend on where they first differ, so it can't be guessed byte by byte.
+fn tokens_match(presented: &str, expected: &str) -> bool {
+    presented.len() == expected.len()
+        && presented
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn require_bearer(
+    State(token): State<Arc<String>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|t| tokens_match(t, token.as_str()));
+    if !authorized {
+        return (StatusCode::UNAUTHORIZED, "missing or invalid bearer token").into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_loopback_hosts() {
+        for host in [
+            "localhost",
+            "LOCALHOST:8808",
+            "127.0.0.1:8808",
+            "127.1.2.3",
+            "[::1]:8808",
+        ] {
+            assert!(is_loopback_host(host), "{host}");
+        }
+        for host in [
+            "evil.example:8808",
+            "localhost.evil.example",
+            "10.0.0.1",
+            "[::1]x",
+            "",
+        ] {
+            assert!(!is_loopback_host(host), "{host}");
+        }
+    }
+
+    #[tokio::test]
+    async fn replaces_only_stale_sockets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mcp.sock");
+        let listener = bind_private(&path).unwrap();
+        let err = bind_private(&path).unwrap_err();
+        assert!(err.to_string().contains("address in use"), "{err}");
+        drop(listener);
+        bind_private(&path).unwrap();
+    }
+
+    #[test]
+    fn matches_tokens_exactly() {
+        assert!(tokens_match("s3cret", "s3cret"));
+        assert!(!tokens_match("s3creT", "s3cret"));
+        assert!(!tokens_match("s3cret!", "s3cret"));
+        assert!(!tokens_match("", "s3cret"));
+    }
+}
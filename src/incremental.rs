@@ -0,0 +1,96 @@
+//! Bookkeeping for incremental reads. A pane's lines are numbered from the
+//! oldest line in its history, so a number stays put while output is appended
+//! but shifts down when tmux trims the history to `history-limit`. A cursor
+//! therefore also remembers hashes of the last lines it returned and finds
+//! them again after a shift.
+
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
+/// How many lines before the read position identify it.
+const ANCHOR_LINES: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadCursor {
+    /// Number of the first line not yet returned.
+    pub next: u64,
+    /// Hashes of the lines just before `next`, oldest first.
+    anchor: Vec<u64>,
+}
+
+/// Read cursors by pane ID, for one client.
+pub type Cursors = Arc<Mutex<HashMap<String, ReadCursor>>>;
+
+pub fn hash_line(line: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    line.trim_end().hash(&mut hasher);
+    hasher.finish()
+}
+
+impl ReadCursor {
+    /// A cursor at `next`, given the lines ending just before it (any number;
+    /// only the last few are kept).
+    pub fn new(next: u64, preceding: &[&str]) -> Self {
+        let skip = preceding.len().saturating_sub(ANCHOR_LINES);
+        ReadCursor {
+            next,
+            anchor: preceding[skip..].iter().map(|l| hash_line(l)).collect(),
+        }
+    }
+
+    /// How many lines before `next` the anchor needs.
+    pub fn anchor_len(&self) -> u64 {
+        self.anchor.len() as u64
+    }
+
+    /// Find where reading should resume in `lines`, which are numbered from
+    /// `first`. Returns the new number of the cursor's position, which is
+    /// `next` unless history was trimmed, or None when the anchor is gone,
+    /// i.e. the output since the last read can't be told apart.
+    pub fn locate(&self, first: u64, lines: &[&str]) -> Option<u64> {
+        let k = self.anchor.len();
+        if k == 0 {
+            return (self.next >= first && self.next <= first + lines.len() as u64)
+                .then_some(self.next);
+        }
+        let hashes: Vec<u64> = lines.iter().map(|l| hash_line(l)).collect();
+        // Trimming only moves lines towards the start, so search from the
+        // old position backwards.
+        let newest_end = (self.next.saturating_sub(first) as usize).min(hashes.len());
+        (k..=newest_end)
+            .rev()
+            .find(|&end| hashes[end - k..end] == self.anchor[..])
+            .map(|end| first + end as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_position_after_trimming() {
+        let lines = ["a", "b", "c", "d", "e"];
+        let cursor = ReadCursor::new(4, &lines[..4]);
+        assert_eq!(cursor.anchor_len(), 3);
+        // Nothing trimmed: the anchor is where it was.
+        assert_eq!(cursor.locate(0, &lines), Some(4));
+        // One line trimmed from the top: "b c d" now end at 3.
+        assert_eq!(cursor.locate(0, &["b", "c", "d", "e"]), Some(3));
+        // The anchor scrolled out of history entirely.
+        assert_eq!(cursor.locate(0, &["x", "y"]), None);
+    }
+
+    #[test]
+    fn prefers_the_latest_match_for_repeated_lines() {
+        let cursor = ReadCursor::new(6, &["$", "$", "$"]);
+        assert_eq!(
+            cursor.locate(0, &["$", "$", "$", "$", "$", "$", "$"]),
+            Some(6)
+        );
+        assert_eq!(cursor.locate(2, &["$", "$", "$", "$"]), Some(6));
+    }
+}
@@ -0,0 +1,208 @@
+//! Background jobs: commands run in their own window of a detached session,
+//! away from the panes the user looks at.
+//!
+//! Everything known about a job lives in tmux, so jobs outlive this server:
+//! the window is tagged with user options holding the job's name, command and
+//! start time, and when the command finishes a wrapper script records its
+//! exit code and end time there too, keeps the window open (remain-on-exit)
+//! and signals the job's `wait-for` channel.
+
+use std::time::{Duration, SystemTime};
+
+/// The session job windows are created in.
+pub const SESSION: &str = "mcp-jobs";
+
+/// A `list-windows` format giving one line per window, for [`Job::parse`].
+pub const FORMAT: &str = "#{@mcp_job}\t#{pane_id}\t#{session_name}:#{window_index}\t#{@mcp_job_started}\t#{@mcp_job_finished}\t#{@mcp_job_exit}\t#{pane_dead}\t#{pane_dead_status}\t#{pane_dead_signal}\t#{@mcp_job_command}";
+
+/// A `display-message` format for how the command in a wrapped pane is doing,
+/// for [`Status::parse`].
+pub const STATUS_FORMAT: &str =
+    "#{@mcp_job_exit}\t#{pane_dead}\t#{pane_dead_status}\t#{pane_dead_signal}";
+
+/// Runs `$1` with sh in a job's pane and records how it ended. The window is
+/// kept first, so a job killed along with its shell still leaves its output.
+pub const WRAPPER: &str = r#"tmux set-option -w -t "$TMUX_PANE" remain-on-exit on; sh -c "$1"; s=$?; tmux set-option -w -t "$TMUX_PANE" @mcp_job_exit "$s" \; set-option -w -t "$TMUX_PANE" @mcp_job_finished "$(date +%s)" \; wait-for -S "mcp-job-$TMUX_PANE"; exit "$s""#;
+
+/// The `wait-for` channel signalled when the job in `pane_id` finishes.
+pub fn channel(pane_id: &str) -> String {
+    format!("mcp-job-{pane_id}")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Running,
+    Exited(i32),
+    /// Killed by a signal before the wrapper could record an exit code.
+    Signalled(i32),
+    /// Ended without either being known, e.g. respawned by hand.
+    Ended,
+}
+
+impl Status {
+    /// From `@mcp_job_exit`, `pane_dead`, `pane_dead_status` and
+    /// `pane_dead_signal`, the fields of [`STATUS_FORMAT`].
+    pub fn parse(exit: &str, dead: &str, dead_status: &str, dead_signal: &str) -> Status {
+        if let Ok(code) = exit.parse() {
+            Status::Exited(code)
+        } else if dead != "1" {
+            Status::Running
+        } else if let Ok(code) = dead_status.parse() {
+            Status::Exited(code)
+        } else if let Ok(signal) = dead_signal.parse() {
+            Status::Signalled(signal)
+        } else {
+            Status::Ended
+        }
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Running => write!(f, "running"),
+            Status::Exited(code) => write!(f, "exit {code}"),
+            Status::Signalled(signal) => write!(f, "signal {signal}"),
+            Status::Ended => write!(f, "ended"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    pub name: String,
+    pub pane_id: String,
+    /// `session:window`.
+    pub window: String,
+    pub command: String,
+    pub started: Option<SystemTime>,
+    pub finished: Option<SystemTime>,
+    pub status: Status,
+}
+
+impl Job {
+    /// Parse a line of [`FORMAT`]; None for windows that aren't jobs.
+    pub fn parse(line: &str) -> Option<Job> {
+        let f: Vec<&str> = line.splitn(10, '\t').collect();
+        let [
+            name,
+            pane_id,
+            window,
+            started,
+            finished,
+            exit,
+            dead,
+            dead_status,
+            dead_signal,
+            command,
+        ] = f[..]
+        else {
+            return None;
+        };
+        if name.is_empty() {
+            return None;
+        }
+        let time = |secs: &str| {
+            secs.parse()
+                .ok()
+                .map(|s| SystemTime::UNIX_EPOCH + Duration::from_secs(s))
+        };
+        Some(Job {
+            name: name.to_string(),
+            pane_id: pane_id.to_string(),
+            window: window.to_string(),
+            command: command.to_string(),
+            started: time(started),
+            finished: time(finished),
+            status: Status::parse(exit, dead, dead_status, dead_signal),
+        })
+    }
+
+    /// How long the job ran, or has run so far.
+    pub fn duration(&self, now: SystemTime) -> Option<Duration> {
+        let end = match self.status {
+            Status::Running => now,
+            _ => self.finished?,
+        };
+        end.duration_since(self.started?).ok()
+    }
+}
+
+/// A name for a new job, `wanted` or else the command's program, made unique
+/// among `taken` with a numeric suffix.
+pub fn unique_name(wanted: Option<&str>, command: &str, taken: &[&str]) -> String {
+    let base = match wanted.map(str::trim).filter(|n| !n.is_empty()) {
+        Some(name) => name.to_string(),
+        None => {
+            let program = command.split_whitespace().next().unwrap_or("job");
+            program.rsplit('/').next().unwrap_or(program).to_string()
+        }
+    };
+    if !taken.contains(&base.as_str()) {
+        return base;
+    }
+    (2..)
+        .map(|n| format!("{base}-{n}"))
+        .find(|name| !taken.contains(&name.as_str()))
+        .unwrap()
+}
+
+/// The lines of a job's captured output, without trailing blank lines or the
+/// note tmux shows in a dead pane.
+pub fn output_lines(captured: &str) -> Vec<&str> {
+    let mut lines: Vec<&str> = captured.lines().map(str::trim_end).collect();
+    while lines
+        .last()
+        .is_some_and(|l| l.is_empty() || l.starts_with("Pane is dead"))
+    {
+        lines.pop();
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_job_windows() {
+        let at = |secs| Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        let line = |exit: &str, dead: &str, status: &str, signal: &str| {
+            format!(
+                "tests\t%4\tmcp-jobs:1\t1000\t1041\t{exit}\t{dead}\t{status}\t{signal}\tcargo test\t-q"
+            )
+        };
+        let job = Job::parse(&line("1", "1", "1", "")).unwrap();
+        assert_eq!(job.command, "cargo test\t-q");
+        assert_eq!(job.status, Status::Exited(1));
+        assert_eq!((job.started, job.finished), (at(1000), at(1041)));
+        assert_eq!(
+            job.duration(SystemTime::now()),
+            Some(Duration::from_secs(41))
+        );
+
+        let running = Job::parse(&line("", "0", "", "")).unwrap();
+        assert_eq!(running.status, Status::Running);
+        let now = at(1005).unwrap();
+        assert_eq!(running.duration(now), Some(Duration::from_secs(5)));
+        let killed = Job::parse(&line("", "1", "", "9")).unwrap();
+        assert_eq!(killed.status.to_string(), "signal 9");
+
+        assert_eq!(Job::parse("\t%1\twork:0\t\t\t\t0\t\t\t"), None);
+    }
+
+    #[test]
+    fn drops_the_dead_pane_note() {
+        let captured = "ok 1\nok 2\n\nPane is dead (status 0, Sun Oct 18 18:14:26 2026)\n\n";
+        assert_eq!(output_lines(captured), vec!["ok 1", "ok 2"]);
+    }
+
+    #[test]
+    fn names_jobs_uniquely() {
+        assert_eq!(unique_name(None, "./scripts/ci.sh --all", &[]), "ci.sh");
+        assert_eq!(
+            unique_name(Some("tests"), "make", &["tests", "tests-2"]),
+            "tests-3"
+        );
+    }
+}
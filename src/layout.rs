@@ -0,0 +1,319 @@
+//! Parsing of tmux's `window_layout` strings, e.g.
+//! `adc3,159x48,0,0{79x48,0,0,0,79x48,80,0[79x24,80,0,1,79x23,80,25,2]}`, and
+//! drawing them as a scaled ASCII map.
+
+use std::fmt;
+
+/// The layouts `select-layout` knows by name.
+pub const PRESETS: [&str; 5] = [
+    "even-horizontal",
+    "even-vertical",
+    "main-horizontal",
+    "main-vertical",
+    "tiled",
+];
+
+/// How a cell's children are arranged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Split {
+    /// Side by side, left to right (`{...}`).
+    Horizontal,
+    /// Stacked, top to bottom (`[...]`).
+    Vertical,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    /// A pane, by the number in its `%` ID.
+    Pane(u32),
+    Split(Split, Vec<Layout>),
+}
+
+/// A cell of the layout tree: a rectangle of the window in character cells.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    pub width: u32,
+    pub height: u32,
+    pub x: u32,
+    pub y: u32,
+    pub node: Node,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Strip the leading checksum, if any, from a layout string.
+pub fn body(s: &str) -> &str {
+    match s.split_once(',') {
+        Some((csum, body)) if csum.len() == 4 && !csum.contains('x') => body,
+        _ => s,
+    }
+}
+
+/// Prefix a layout body with the checksum `select-layout` requires, the same
+/// 16-bit rotating sum tmux computes.
+pub fn with_checksum(body: &str) -> String {
+    let csum = body.bytes().fold(0u16, |csum, b| {
+        ((csum >> 1) | ((csum & 1) << 15)).wrapping_add(b as u16)
+    });
+    format!("{csum:04x},{body}")
+}
+
+impl Layout {
+    /// Parse a `window_layout` value. The leading checksum is not verified.
+    pub fn parse(s: &str) -> Result<Self, ParseError> {
+        let body = body(s);
+        let mut parser = Parser { s: body, pos: 0 };
+        let layout = parser.cell()?;
+        if parser.pos != body.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(layout)
+    }
+
+    /// The leaves in layout order, which is also tmux's pane index order.
+    pub fn panes(&self) -> Vec<&Layout> {
+        match &self.node {
+            Node::Pane(_) => vec![self],
+            Node::Split(_, children) => children.iter().flat_map(|c| c.panes()).collect(),
+        }
+    }
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, what: &str) -> ParseError {
+        ParseError(format!("invalid layout at offset {}: {what}", self.pos))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.s.as_bytes().get(self.pos).copied()
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), ParseError> {
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("expected '{}'", c as char)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<u32, ParseError> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.s[start..self.pos]
+            .parse()
+            .map_err(|_| self.error("expected a number"))
+    }
+
+    /// `WxH,X,Y` followed by `,ID`, `{children}` or `[children]`.
+    fn cell(&mut self) -> Result<Layout, ParseError> {
+        let width = self.number()?;
+        self.expect(b'x')?;
+        let height = self.number()?;
+        self.expect(b',')?;
+        let x = self.number()?;
+        self.expect(b',')?;
+        let y = self.number()?;
+        let node = match self.peek() {
+            Some(b',') => {
+                self.pos += 1;
+                Node::Pane(self.number()?)
+            }
+            Some(open @ (b'{' | b'[')) => {
+                self.pos += 1;
+                let (split, close) = if open == b'{' {
+                    (Split::Horizontal, b'}')
+                } else {
+                    (Split::Vertical, b']')
+                };
+                let mut children = vec![self.cell()?];
+                while self.peek() == Some(b',') {
+                    self.pos += 1;
+                    children.push(self.cell()?);
+                }
+                self.expect(close)?;
+                Node::Split(split, children)
+            }
+            _ => return Err(self.error("expected ',', '{' or '['")),
+        };
+        Ok(Layout {
+            width,
+            height,
+            x,
+            y,
+            node,
+        })
+    }
+}
+
+/// Draw the panes of `layout` as boxes, scaled down to at most `max_width`
+/// columns. `label` gives the lines written inside each pane's box, cut to
+/// fit.
+pub fn draw(layout: &Layout, max_width: u32, label: impl Fn(u32) -> Vec<String>) -> String {
+    let scale = (max_width.saturating_sub(1) as f64 / layout.width as f64).min(1.0);
+    // A pane's box runs from the border on its left/top to the border on its
+    // right/bottom, so neighbours share an edge.
+    let to_grid = |v: u32| (v as f64 * scale).round() as usize;
+    let start = |v: u32, origin: u32| if v == origin { 0 } else { to_grid(v - 1 - origin) };
+    let (cols, rows) = (
+        (to_grid(layout.width) + 1).max(2),
+        (to_grid(layout.height) + 1).max(2),
+    );
+    let mut grid = vec![vec![' '; cols]; rows];
+
+    for pane in layout.panes() {
+        let Node::Pane(id) = pane.node else {
+            continue;
+        };
+        // A pane scaled below one cell still gets a box two borders wide.
+        let left = start(pane.x, layout.x).min(cols - 2);
+        let top = start(pane.y, layout.y).min(rows - 2);
+        let right = to_grid(pane.x + pane.width - layout.x).clamp(left + 1, cols - 1);
+        let bottom = to_grid(pane.y + pane.height - layout.y).clamp(top + 1, rows - 1);
+
+        for row in [top, bottom] {
+            grid[row][left..=right].fill('-');
+            grid[row][left] = '+';
+            grid[row][right] = '+';
+        }
+        for line in &mut grid[top + 1..bottom] {
+            line[left] = '|';
+            line[right] = '|';
+        }
+
+        let inner = right.saturating_sub(left + 1);
+        for (i, line) in label(id).into_iter().enumerate() {
+            let row = top + 1 + i;
+            if row >= bottom {
+                break;
+            }
+            for (j, c) in line.chars().take(inner).enumerate() {
+                grid[row][left + 1 + j] = c;
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for row in grid {
+        let line: String = row.into_iter().collect();
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// Where a pane sits in its window, e.g. "top-right", "left" or "whole window",
+/// going by which of the window's edges it touches.
+pub fn position(pane: &Layout, window: &Layout) -> String {
+    let side = |start: u32, size: u32, origin: u32, total: u32, names: [&'static str; 3]| {
+        let first = start == origin;
+        let last = start + size == origin + total;
+        match (first, last) {
+            (true, true) => None,
+            (true, false) => Some(names[0]),
+            (false, false) => Some(names[1]),
+            (false, true) => Some(names[2]),
+        }
+    };
+    let vertical = side(pane.y, pane.height, window.y, window.height, ["top", "middle", "bottom"]);
+    let horizontal = side(pane.x, pane.width, window.x, window.width, ["left", "center", "right"]);
+    match (vertical, horizontal) {
+        (None, None) => "whole window".into(),
+        (Some(v), None) => v.into(),
+        (None, Some(h)) => h.into(),
+        (Some(v), Some(h)) => format!("{v}-{h}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THREE_PANES: &str = "adc3,159x48,0,0{79x48,0,0,0,79x48,80,0[79x24,80,0,1,79x23,80,25,2]}";
+
+    #[test]
+    fn parses_nested_splits() {
+        let layout = Layout::parse(THREE_PANES).unwrap();
+        assert_eq!((layout.width, layout.height), (159, 48));
+        let Node::Split(Split::Horizontal, children) = &layout.node else {
+            panic!("expected a horizontal split");
+        };
+        assert_eq!(children[0].node, Node::Pane(0));
+        assert!(matches!(&children[1].node, Node::Split(Split::Vertical, c) if c.len() == 2));
+        let ids: Vec<_> = layout
+            .panes()
+            .iter()
+            .map(|p| (p.node.clone(), p.x, p.y))
+            .collect();
+        assert_eq!(ids, vec![
+            (Node::Pane(0), 0, 0),
+            (Node::Pane(1), 80, 0),
+            (Node::Pane(2), 80, 25)
+        ]);
+    }
+
+    #[test]
+    fn computes_tmux_checksums() {
+        assert_eq!(with_checksum(body(THREE_PANES)), THREE_PANES);
+        assert_eq!(
+            with_checksum("120x30,0,0{60x30,0,0,0,59x30,61,0[59x15,61,0,1,59x14,61,16,4]}"),
+            "4523,120x30,0,0{60x30,0,0,0,59x30,61,0[59x15,61,0,1,59x14,61,16,4]}"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_layouts() {
+        assert!(Layout::parse("adc3,159x48,0,0{79x48,0,0,0").is_err());
+        assert!(Layout::parse("159x48,0,0,3").is_ok());
+        assert!(Layout::parse("159x48,0,0,3x").is_err());
+    }
+
+    #[test]
+    fn draws_scaled_boxes() {
+        let layout = Layout::parse(THREE_PANES).unwrap();
+        let map = draw(&layout, 40, |id| vec![format!("%{id}")]);
+        let lines: Vec<&str> = map.lines().collect();
+        assert_eq!(lines.len(), 13);
+        assert_eq!(lines[0], "+------------------+-------------------+");
+        assert_eq!(lines[1], "|%0                |%1                 |");
+        assert_eq!(lines[6], "|                  +-------------------+");
+        assert_eq!(lines[7], "|                  |%2                 |");
+        assert_eq!(lines[12], lines[0]);
+    }
+
+    #[test]
+    fn draws_panes_scaled_below_a_cell() {
+        for (layout, max_width) in [
+            ("300x50,0,0[300x11,0,0,1,300x1,0,12,2,300x36,0,14,3]", 80),
+            ("200x50,0,0[200x16,0,0,1,200x16,0,17,2,200x16,0,34,3]", 10),
+            ("200x1,0,0{100x1,0,0,1,99x1,101,0,2}", 4),
+        ] {
+            let layout = Layout::parse(layout).unwrap();
+            let map = draw(&layout, max_width, |id| vec![format!("%{id}")]);
+            assert!(map.lines().all(|l| l.chars().count() <= max_width as usize), "{map}");
+        }
+    }
+
+    #[test]
+    fn describes_positions() {
+        let layout = Layout::parse(THREE_PANES).unwrap();
+        let panes = layout.panes();
+        assert_eq!(position(panes[0], &layout), "left");
+        assert_eq!(position(panes[1], &layout), "top-right");
+        assert_eq!(position(panes[2], &layout), "bottom-right");
+        assert_eq!(position(&layout, &layout), "whole window");
+    }
+}
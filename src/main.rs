@@ -1,29 +1,183 @@
-use std::process::Stdio;
+mod ansi;
+mod backend;
+mod blocks;
+mod error;
+mod http;
+mod incremental;
+mod jobs;
+mod layout;
+mod pane_state;
+mod procfs;
+mod render;
+mod selection;
+mod snapshot;
+mod template;
 
-use anyhow::Result;
+use std::{
+    collections::{HashMap, HashSet},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Result, bail};
+use base64::Engine;
+use regex::Regex;
 use rmcp::{
-    ServerHandler, ServiceExt,
-    handler::server::{router::tool::ToolRouter, wrapper::Parameters},
-    model::{ServerCapabilities, ServerInfo},
-    schemars, tool, tool_handler, tool_router,
+    ErrorData as McpError, RoleServer, ServerHandler, ServiceExt,
+    handler::server::{
+        router::tool::ToolRouter,
+        tool::{IntoCallToolResult, ToolCallContext},
+        wrapper::Parameters,
+    },
+    model::{
+        CallToolRequestParams, CallToolResult, Content, IntoContents, ListToolsResult,
+        PaginatedRequestParams, ServerCapabilities, ServerInfo, Tool,
+    },
+    schemars,
+    service::RequestContext,
+    tool, tool_router,
     transport::stdio,
 };
 use serde::Deserialize;
-use tokio::process::Command;
 use tracing_subscriber::{self, EnvFilter};
 
+use crate::{
+    backend::{ControlMode, Subprocess, Timeouts, TmuxBackend},
+    error::TmuxError,
+    http::{HttpOptions, Listen},
+};
+
 const MAX_NAME_LEN: usize = 20;
 const MAX_CMD_LEN: usize = 16;
 
 #[derive(Debug, Clone)]
 struct TmuxMcp {
     tool_router: ToolRouter<Self>,
+    tmux: Arc<dyn TmuxBackend>,
     /// The pane ID (e.g. %47) this server process is running in, from $TMUX_PANE.
     current_pane_id: Option<String>,
+    /// Where create_workspace and list_templates look for templates.
+    templates_dir: PathBuf,
+    /// Where save_snapshot and restore_snapshot keep snapshots.
+    snapshots_dir: PathBuf,
+    /// Where read_new_output left off in each pane, for this client.
+    read_cursors: incremental::Cursors,
+    /// Panes track_commands is recording, by pane ID.
+    command_trackers: Arc<Mutex<HashMap<String, CommandTracker>>>,
+}
+
+/// A pane whose raw output is piped to us so its shell integration marks can
+/// be read.
+#[derive(Debug)]
+struct CommandTracker {
+    recorder: blocks::Recorder,
+    reader: tokio::task::AbortHandle,
+    /// The private directory holding the FIFO, removed with the tracker.
+    dir: tempfile::TempDir,
+}
+
+impl CommandTracker {
+    fn fifo(dir: &Path) -> PathBuf {
+        dir.join("output.fifo")
+    }
+
+    /// Stop the reader, which may still be waiting for the pipe's writer to
+    /// open the FIFO: opening it for writing ourselves lets that open finish.
+    fn stop(&self) {
+        self.reader.abort();
+        let _ = std::fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(nix::fcntl::OFlag::O_NONBLOCK.bits())
+            .open(Self::fifo(self.dir.path()));
+    }
+}
+
+/// One pane of a broadcast_command.
+struct BroadcastRun {
+    pane_id: String,
+    address: String,
+    /// The line the command was typed on, numbered from the oldest line of
+    /// the pane's history.
+    start: u64,
+    /// How the command went, or why it wasn't sent.
+    status: Result<jobs::Status, String>,
+    output: Vec<String>,
 }
 
 // -- Helper types and functions --
 
+/// A short human duration, e.g. "0.4s", "12s" or "3m05s".
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0..10 => format!("{:.1}s", d.as_secs_f64()),
+        10..60 => format!("{secs}s"),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// A memory size given in KiB, e.g. "820K", "12.5M" or "1.2G".
+fn format_kib(kib: u64) -> String {
+    match kib {
+        0..1024 => format!("{kib}K"),
+        1024..1_048_576 => format!("{:.1}M", kib as f64 / 1024.0),
+        _ => format!("{:.1}G", kib as f64 / 1_048_576.0),
+    }
+}
+
+/// How a dead pane's program ended, from `pane_dead_status` and
+/// `pane_dead_signal`: "exit 1", "signal 9" or, when tmux knows neither,
+/// "exited".
+fn describe_exit(status: &str, signal: &str) -> String {
+    if !status.is_empty() {
+        format!("exit {status}")
+    } else if !signal.is_empty() {
+        format!("signal {signal}")
+    } else {
+        "exited".to_string()
+    }
+}
+
+/// The line typed into each pane by broadcast_command. The command runs in a
+/// `sh -c` of its own, so a trailing `&` or a syntax error can't stop the
+/// status marker running after it, and the marker is printed by `sh` too, so
+/// it works whatever shell the pane has, e.g. fish over SSH.
+fn broadcast_line(command: &str, token: &str) -> String {
+    let script = format!(r#"sh -c "$1"; printf "\n%s:%s\n" {token} "$?""#);
+    format!(
+        "sh -c {} sh {}",
+        snapshot::shell_quote(&script),
+        snapshot::shell_quote(command)
+    )
+}
+
+/// Find the line `printf "\n%s:%s\n" <token> "$?"` printed after a broadcast
+/// command, returning its index and the exit status. The echo of the typed
+/// command has a space after the token rather than a colon, so it never
+/// matches.
+fn find_done_marker(lines: &[&str], token: &str) -> Option<(usize, i32)> {
+    lines.iter().enumerate().find_map(|(i, line)| {
+        let code = line.trim().strip_prefix(token)?.strip_prefix(':')?.parse().ok()?;
+        Some((i, code))
+    })
+}
+
+/// The size of some text, e.g. "12 lines, 340 bytes".
+fn describe_text(text: &str) -> String {
+    let lines = text.lines().count();
+    format!(
+        "{lines} line{}, {} bytes",
+        if lines == 1 { "" } else { "s" },
+        text.len()
+    )
+}
+
 fn truncate(s: &str, max: usize) -> String {
     if s.chars().count() <= max {
         s.to_string()
@@ -68,29 +222,95 @@ struct PaneInfo {
     current_command: String,
     is_active: bool,
     pane_id: String,
+    /// How the pane's program ended, once it has exited and the pane stayed
+    /// (remain-on-exit).
+    dead: Option<String>,
 }
 
-async fn fetch_panes(session: &str, window_index: &str) -> Result<Vec<PaneInfo>, String> {
-    let target = format!("{session}:{window_index}");
-    let format =
-        "#{pane_index}\t#{pane_width}\t#{pane_height}\t#{pane_current_command}\t#{?pane_active,1,0}\t#{pane_id}";
-    let output = run_tmux(&["list-panes", "-t", &target, "-F", format]).await?;
-    let mut panes = Vec::new();
-    for line in output.lines() {
-        let fields: Vec<&str> = line.split('\t').collect();
-        if fields.len() < 6 {
-            continue;
-        }
-        panes.push(PaneInfo {
-            pane_index: fields[0].parse().unwrap_or(0),
-            width: fields[1].parse().unwrap_or(0),
-            height: fields[2].parse().unwrap_or(0),
-            current_command: fields[3].to_string(),
-            is_active: fields[4] == "1",
-            pane_id: fields[5].to_string(),
-        });
+/// Which screen a pane shows and whether it is in a mode, so agents can tell a
+/// full-screen program from shell output.
+struct PaneScreenState {
+    alternate_on: bool,
+    in_mode: bool,
+    mode: String,
+    scroll_position: u32,
+    history_size: u32,
+    command: String,
+}
+
+impl PaneScreenState {
+    const FORMAT: &str = "#{alternate_on}\t#{pane_in_mode}\t#{pane_mode}\t#{scroll_position}\t#{history_size}\t#{pane_current_command}";
+
+    fn parse(line: &str) -> Self {
+        let f: Vec<&str> = line.trim_end_matches('\n').split('\t').collect();
+        let field = |i: usize| f.get(i).copied().unwrap_or("");
+        Self {
+            alternate_on: field(0) == "1",
+            in_mode: field(1) == "1",
+            mode: field(2).to_string(),
+            scroll_position: field(3).parse().unwrap_or(0),
+            history_size: field(4).parse().unwrap_or(0),
+            command: field(5).to_string(),
+        }
+    }
+
+    /// A one-line note to put above a capture, or None for a plain shell on
+    /// the normal screen.
+    fn note(&self, showing_normal: bool) -> Option<String> {
+        if !self.alternate_on && !self.in_mode {
+            return None;
+        }
+        let mut note = format!(
+            "[alternate_on={} pane_in_mode={}",
+            u8::from(self.alternate_on),
+            u8::from(self.in_mode)
+        );
+        if self.in_mode {
+            note.push_str(&format!(
+                " pane_mode={} scroll_position={}/{}",
+                self.mode, self.scroll_position, self.history_size
+            ));
+        }
+        note.push_str(&format!(" command={}]", self.command));
+        if showing_normal {
+            note.push_str(" Showing the normal screen underneath the alternate screen.");
+        } else if self.alternate_on {
+            note.push_str(" Showing the alternate screen of a full-screen program; set normal_screen=true for the shell output underneath.");
+        }
+        if self.in_mode && self.scroll_position > 0 {
+            note.push_str(&format!(
+                " The user has scrolled back {} lines in {}.",
+                self.scroll_position, self.mode
+            ));
+        }
+        Some(note)
+    }
+}
+
+struct PaneCapture {
+    note: Option<String>,
+    contents: String,
+}
+
+impl PaneCapture {
+    fn into_text(self) -> String {
+        match self.note {
+            Some(note) => format!("{note}\n{}", self.contents),
+            None => self.contents,
+        }
+    }
+}
+
+/// A rendered pane image, preceded by the screen-state note if there is one.
+struct RenderedPane {
+    note: Option<String>,
+    image: Content,
+}
+
+impl IntoContents for RenderedPane {
+    fn into_contents(self) -> Vec<Content> {
+        self.note.map(Content::text).into_iter().chain([self.image]).collect()
     }
-    Ok(panes)
 }
 
 // -- Tool parameter types --
@@ -116,6 +336,20 @@ struct ListWindowsRequest {
     verbose: Option<bool>,
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "lowercase")]
+enum CaptureStyle {
+    /// Text only, attributes stripped.
+    #[default]
+    Plain,
+    /// Text with inline tags such as "[bold red]FAILED[/]"; a literal "[" that
+    /// could start a tag is escaped as "\[".
+    Markup,
+    /// JSON: {"note": ..., "lines": [[{"text", "fg", "bg", "bold", ...}]]}, one
+    /// array of styled spans per line.
+    Spans,
+}
+
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
 struct GetPaneContentsRequest {
     #[schemars(
@@ -127,6 +361,16 @@ struct GetPaneContentsRequest {
         description = "Number of lines of scrollback history to include. 0 means visible area only. Defaults to 0 (visible area only)."
     )]
     scroll_back_lines: Option<u32>,
+
+    #[schemars(
+        description = "When true and a full-screen program (vim, less, htop) has switched the pane to the alternate screen, capture the normal screen underneath instead, i.e. the shell output. Defaults to false."
+    )]
+    normal_screen: Option<bool>,
+
+    #[schemars(
+        description = "How to return text attributes: \"plain\" strips them, \"markup\" adds inline tags like \"[red]...[/]\" and \"[bold on-blue]...[/]\", \"spans\" returns JSON with one array of {text, fg, bg, bold, ...} spans per line. Colors are names (\"red\", \"bright-green\"), \"colorN\" for the 256-color palette, or \"#rrggbb\". Defaults to \"plain\"."
+    )]
+    style: Option<CaptureStyle>,
 }
 
 #[derive(Debug, Deserialize, schemars::JsonSchema)]
@@ -140,505 +384,4790 @@ struct GetWindowContentsRequest {
         description = "Number of lines of scrollback history to include. 0 means visible area only. Defaults to 0 (visible area only)."
     )]
     scroll_back_lines: Option<u32>,
+
+    #[schemars(
+        description = "When true, capture the normal screen of panes that are on the alternate screen (see get_pane_contents). Defaults to false."
+    )]
+    normal_screen: Option<bool>,
 }
 
-// -- Helpers --
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "lowercase")]
+enum ImageFormat {
+    #[default]
+    Svg,
+    /// Drawn with a built-in 8x16 bitmap font, for clients that can't show SVG.
+    Png,
+}
 
-async fn run_tmux(args: &[&str]) -> Result<String, String> {
-    let output = Command::new("tmux")
-        .args(args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .await
-        .map_err(|e| format!("Failed to run tmux: {e}"))?;
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct RenderPaneRequest {
+    #[schemars(
+        description = "Target pane. Formats:\n- \"x\" - pane x in current window\n- \"y.x\" - pane x in window y (current session)\n- \"sess:y.x\" - pane x in window y in session sess\nExamples: \"1\", \"5.1\", \"API:5.1\""
+    )]
+    target: String,
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("tmux error: {stderr}"));
-    }
+    #[schemars(description = "Image format, \"svg\" or \"png\". Defaults to \"svg\".")]
+    format: Option<ImageFormat>,
 
-    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    #[schemars(
+        description = "When true, render the normal screen of a pane that is on the alternate screen (see get_pane_contents). Defaults to false."
+    )]
+    normal_screen: Option<bool>,
 }
 
-/// Given a pane ID like %47, query tmux for session:window or session:window.pane.
-async fn resolve_pane_id(pane_id: &str, format: &str) -> Result<String, String> {
-    run_tmux(&["display-message", "-t", pane_id, "-p", format])
-        .await
-        .map(|s| s.trim().to_string())
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GetWindowLayoutRequest {
+    #[schemars(
+        description = "Target window. Formats:\n- \"y\" - window y in current session\n- \"sess:y\" - window y in session sess\nExamples: \"5\", \"API:5\"\nIf omitted, defaults to the current window."
+    )]
+    target: Option<String>,
+
+    #[schemars(
+        description = "Maximum width of the map in columns; larger windows are scaled down. Defaults to 80."
+    )]
+    width: Option<u32>,
 }
 
-async fn capture_pane(target: &str, scroll_back: u32) -> String {
-    let start_line = if scroll_back > 0 {
-        format!("-{scroll_back}")
-    } else {
-        "0".to_string()
-    };
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct SelectLayoutRequest {
+    #[schemars(
+        description = "Target window. Formats:\n- \"y\" - window y in current session\n- \"sess:y\" - window y in session sess\nExamples: \"5\", \"API:5\"\nIf omitted, defaults to the current window."
+    )]
+    target: Option<String>,
 
-    match run_tmux(&["capture-pane", "-p", "-J", "-t", target, "-S", &start_line]).await {
-        Ok(contents) => contents,
-        Err(e) => format!("Error capturing {target}: {e}\n"),
-    }
+    #[schemars(
+        description = "A preset (\"even-horizontal\", \"even-vertical\", \"main-horizontal\", \"main-vertical\", \"tiled\") or a layout string as shown by get_window_layout, e.g. \"4523,120x30,0,0{60x30,0,0,0,59x30,61,0,1}\". The checksum may be omitted or stale; it is recomputed. Panes are assigned to the layout's cells in index order."
+    )]
+    layout: String,
 }
 
-// -- Tool implementations --
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "lowercase")]
+enum ResizeDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
 
-#[tool_router]
-impl TmuxMcp {
-    fn new() -> Self {
-        let current_pane_id = std::env::var("TMUX_PANE").ok();
-        Self {
-            tool_router: Self::tool_router(),
-            current_pane_id,
+impl ResizeDirection {
+    fn flag(self) -> &'static str {
+        match self {
+            Self::Left => "-L",
+            Self::Right => "-R",
+            Self::Up => "-U",
+            Self::Down => "-D",
         }
     }
+}
 
-    #[tool(
-        description = "List all tmux sessions with their properties. Set verbose=true for a full tree showing sessions, windows, and panes."
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct ResizePaneRequest {
+    #[schemars(
+        description = "Target pane. Formats:\n- \"x\" - pane x in current window\n- \"y.x\" - pane x in window y (current session)\n- \"sess:y.x\" - pane x in window y in session sess\nExamples: \"1\", \"5.1\", \"API:5.1\""
     )]
-    async fn list_sessions(
-        &self,
-        Parameters(req): Parameters<ListSessionsRequest>,
-    ) -> String {
-        let verbose = req.verbose.unwrap_or(false);
-
-        let format =
-            "#{session_name}\t#{session_windows}\t#{?session_attached,attached,detached}";
-        let output = match run_tmux(&["list-sessions", "-F", format]).await {
-            Ok(o) => o,
-            Err(e) => return e,
-        };
+    target: String,
 
-        struct SessionRow {
-            name: String,
-            window_count: String,
-            state: String,
-        }
+    #[schemars(
+        description = "New width in cells (\"80\") or as a percentage of the window (\"50%\")."
+    )]
+    width: Option<String>,
 
-        let sessions: Vec<SessionRow> = output
-            .lines()
-            .filter_map(|line| {
-                let f: Vec<&str> = line.split('\t').collect();
-                if f.len() < 3 {
-                    return None;
-                }
-                Some(SessionRow {
-                    name: f[0].to_string(),
-                    window_count: f[1].to_string(),
-                    state: f[2].to_string(),
-                })
-            })
-            .collect();
+    #[schemars(
+        description = "New height in cells (\"20\") or as a percentage of the window (\"30%\")."
+    )]
+    height: Option<String>,
 
-        if !verbose {
-            let rows: Vec<Vec<String>> = sessions
-                .iter()
-                .map(|s| {
-                    vec![
-                        truncate(&s.name, MAX_NAME_LEN),
-                        format!("({})", s.state),
-                        format!(
-                            "{} window{}",
-                            s.window_count,
-                            if s.window_count == "1" { "" } else { "s" }
-                        ),
-                    ]
-                })
-                .collect();
-            return align_columns(&rows).join("\n");
-        }
+    #[schemars(
+        description = "Move the pane's edge in this direction instead: \"left\", \"right\", \"up\" or \"down\", by amount cells."
+    )]
+    direction: Option<ResizeDirection>,
 
-        // Verbose: full tree
-        let mut out = String::new();
-        for session in &sessions {
-            let wcount: u32 = session.window_count.parse().unwrap_or(0);
-            out.push_str(&format!(
-                "{} ({})  {} window{}\n",
-                truncate(&session.name, MAX_NAME_LEN),
-                session.state,
-                wcount,
-                if wcount == 1 { "" } else { "s" }
-            ));
+    #[schemars(description = "Cells to move the edge by when direction is set. Defaults to 1.")]
+    amount: Option<u32>,
+}
 
-            // Fetch windows for this session
-            let win_format = "#{window_index}\t#{window_name}\t#{window_panes}";
-            let wins = match run_tmux(&[
-                "list-windows",
-                "-t",
-                &format!("{}:", session.name),
-                "-F",
-                win_format,
-            ])
-            .await
-            {
-                Ok(w) => w,
-                Err(_) => continue,
-            };
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct ZoomPaneRequest {
+    #[schemars(
+        description = "Target pane. Formats:\n- \"x\" - pane x in current window\n- \"y.x\" - pane x in window y (current session)\n- \"sess:y.x\" - pane x in window y in session sess\nExamples: \"1\", \"5.1\", \"API:5.1\""
+    )]
+    target: String,
 
-            for win_line in wins.lines() {
-                let wf: Vec<&str> = win_line.split('\t').collect();
-                if wf.len() < 3 {
-                    continue;
-                }
-                let win_idx = wf[0];
-                let win_name = wf[1];
-                let pane_count: u32 = wf[2].parse().unwrap_or(0);
+    #[schemars(
+        description = "true to zoom the pane to fill its window, false to unzoom the window. If omitted, toggles."
+    )]
+    zoom: Option<bool>,
+}
 
-                out.push_str(&format!(
-                    "  {}:  {}  {} pane{}\n",
-                    win_idx,
-                    truncate(win_name, MAX_NAME_LEN),
-                    pane_count,
-                    if pane_count == 1 { "" } else { "s" }
-                ));
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct CreateWorkspaceRequest {
+    #[schemars(description = "Template name, as shown by list_templates.")]
+    template: String,
 
-                // Fetch panes
-                let panes = match fetch_panes(&session.name, win_idx).await {
-                    Ok(p) => p,
-                    Err(_) => continue,
-                };
+    #[schemars(
+        description = "Session name to create. Defaults to the template's session, or its name."
+    )]
+    session: Option<String>,
 
-                let pane_rows: Vec<Vec<String>> = panes
-                    .iter()
-                    .map(|p| {
-                        let mut cols = vec![
-                            format!("    .{}", p.pane_index),
-                            format!("{}x{}", p.width, p.height),
-                            truncate(&p.current_command, MAX_CMD_LEN),
-                        ];
-                        let mut suffix = String::new();
-                        if p.is_active {
-                            suffix.push_str("(active)");
-                        }
-                        if self.current_pane_id.as_deref() == Some(p.pane_id.as_str()) {
-                            if !suffix.is_empty() {
-                                suffix.push_str("  ");
-                            }
-                            suffix.push_str("<-- current");
-                        }
-                        if !suffix.is_empty() {
-                            cols.push(suffix);
-                        }
-                        cols
-                    })
-                    .collect();
+    #[schemars(
+        description = "Send commands even into a pane showing a password or confirmation prompt. Defaults to false."
+    )]
+    force: Option<bool>,
+}
 
-                for line in align_columns(&pane_rows) {
-                    out.push_str(&line);
-                    out.push('\n');
-                }
-            }
-        }
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct SaveSnapshotRequest {
+    #[schemars(
+        description = "Snapshot name; it is saved as <name>.json in the snapshots directory, replacing any earlier snapshot of that name."
+    )]
+    name: String,
 
-        out.trim_end().to_string()
-    }
+    #[schemars(description = "Sessions to include. Defaults to all sessions.")]
+    sessions: Option<Vec<String>>,
 
-    #[tool(
-        description = "List tmux windows. Optionally filter by session name. Set verbose=true to include pane details beneath each window."
+    #[schemars(
+        description = "Lines of scrollback (including the visible screen) to save per pane, shown again when restored. Defaults to 0 (none)."
     )]
-    async fn list_windows(
-        &self,
-        Parameters(req): Parameters<ListWindowsRequest>,
-    ) -> String {
-        let verbose = req.verbose.unwrap_or(false);
+    scroll_back_lines: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct RestoreSnapshotRequest {
+    #[schemars(description = "Name the snapshot was saved under.")]
+    name: String,
+
+    #[schemars(
+        description = "Sessions to restore. Defaults to every session in the snapshot. Sessions that already exist are left alone."
+    )]
+    sessions: Option<Vec<String>>,
+
+    #[schemars(
+        description = "Programs to start again in the panes that were running them, replacing the default list (vim, nvim, vi, less, man, htop, top, tail, watch). Other panes get a shell in the saved directory."
+    )]
+    rerun: Option<Vec<String>>,
+
+    #[schemars(
+        description = "Send commands even into a pane showing a password or confirmation prompt. Defaults to false."
+    )]
+    force: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct ReadNewOutputRequest {
+    #[schemars(
+        description = "Target pane. Formats:\n- \"x\" - pane x in current window\n- \"y.x\" - pane x in window y (current session)\n- \"sess:y.x\" - pane x in window y in session sess\nExamples: \"1\", \"5.1\", \"API:5.1\""
+    )]
+    target: String,
+
+    #[schemars(
+        description = "Return at most this many new lines, the most recent ones. Defaults to 500."
+    )]
+    max_lines: Option<u32>,
+
+    #[schemars(
+        description = "When true, forget the previous position and start again from the visible screen. Defaults to false."
+    )]
+    reset: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct SendKeysRequest {
+    #[schemars(
+        description = "Target pane. Formats:\n- \"x\" - pane x in current window\n- \"y.x\" - pane x in window y (current session)\n- \"sess:y.x\" - pane x in window y in session sess\nExamples: \"1\", \"5.1\", \"API:5.1\""
+    )]
+    target: String,
+
+    #[schemars(description = "Text to type, sent literally.")]
+    text: Option<String>,
+
+    #[schemars(
+        description = "tmux key names to press after the text, e.g. [\"Enter\"], [\"C-c\"] or [\"Escape\", \"Up\"]."
+    )]
+    keys: Option<Vec<String>>,
+
+    #[schemars(description = "Press Enter after the text and keys. Defaults to false.")]
+    enter: Option<bool>,
+
+    #[schemars(
+        description = "Send even when the pane is showing a password or confirmation prompt. Only set this when the input is meant as the answer. Defaults to false."
+    )]
+    force: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GetPaneStateRequest {
+    #[schemars(
+        description = "Target pane. Formats:\n- \"x\" - pane x in current window\n- \"y.x\" - pane x in window y (current session)\n- \"sess:y.x\" - pane x in window y in session sess\nExamples: \"1\", \"5.1\", \"API:5.1\""
+    )]
+    target: String,
+
+    #[schemars(
+        description = "Regex matching the shell's prompt at the start of the cursor line, which tells an idle shell from one waiting for other input. Defaults to a prompt ending in $, #, % or ❯ and a space."
+    )]
+    prompt_pattern: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GetPaneProcessesRequest {
+    #[schemars(
+        description = "Target pane. Formats:\n- \"x\" - pane x in current window\n- \"y.x\" - pane x in window y (current session)\n- \"sess:y.x\" - pane x in window y in session sess\nExamples: \"1\", \"5.1\", \"API:5.1\""
+    )]
+    target: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "UPPERCASE")]
+enum Signal {
+    Sigint,
+    Sigterm,
+    Sigkill,
+    Sighup,
+}
+
+impl Signal {
+    /// The name without its `SIG` prefix, as shown in messages.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Sigint => "INT",
+            Self::Sigterm => "TERM",
+            Self::Sigkill => "KILL",
+            Self::Sighup => "HUP",
+        }
+    }
+
+    fn to_nix(self) -> nix::sys::signal::Signal {
+        use nix::sys::signal::Signal as S;
+        match self {
+            Self::Sigint => S::SIGINT,
+            Self::Sigterm => S::SIGTERM,
+            Self::Sigkill => S::SIGKILL,
+            Self::Sighup => S::SIGHUP,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct SignalPaneRequest {
+    #[schemars(
+        description = "Target pane. Formats:\n- \"x\" - pane x in current window\n- \"y.x\" - pane x in window y (current session)\n- \"sess:y.x\" - pane x in window y in session sess\nExamples: \"1\", \"5.1\", \"API:5.1\""
+    )]
+    target: String,
+
+    #[schemars(
+        description = "SIGINT (like C-c), SIGTERM (ask to exit), SIGKILL (force, can't be caught) or SIGHUP (terminal closed). Defaults to SIGINT."
+    )]
+    signal: Option<Signal>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct RespawnPaneRequest {
+    #[schemars(
+        description = "Target pane. Formats:\n- \"x\" - pane x in current window\n- \"y.x\" - pane x in window y (current session)\n- \"sess:y.x\" - pane x in window y in session sess\nExamples: \"1\", \"5.1\", \"API:5.1\""
+    )]
+    target: String,
+
+    #[schemars(
+        description = "Shell command to run in the pane instead, e.g. \"npm run dev\". If omitted, the command the pane was created or last respawned with runs again."
+    )]
+    command: Option<String>,
+
+    #[schemars(description = "Directory to start the command in. If omitted, the pane's current directory.")]
+    start_directory: Option<String>,
+
+    #[schemars(
+        description = "Kill the pane's program first if it is still running. Without it, only dead panes are respawned. Defaults to false."
+    )]
+    kill: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct StartJobRequest {
+    #[schemars(description = "Shell command to run with sh, e.g. \"cargo test --workspace\".")]
+    command: String,
+
+    #[schemars(
+        description = "Name to refer to the job by. Defaults to the command's program; a number is appended if the name is taken."
+    )]
+    name: Option<String>,
+
+    #[schemars(description = "Directory to run the command in. Defaults to tmux's default.")]
+    start_directory: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct JobStatusRequest {
+    #[schemars(description = "Job name, as shown by list_jobs, or its pane ID.")]
+    job: String,
+
+    #[schemars(
+        description = "Seconds to wait for the job to finish before reporting, at most 300. Defaults to 0, reporting at once."
+    )]
+    wait_seconds: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct JobOutputRequest {
+    #[schemars(description = "Job name, as shown by list_jobs, or its pane ID.")]
+    job: String,
+
+    #[schemars(description = "How many of the last lines to return. Defaults to 100.")]
+    lines: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct ExecRequest {
+    #[schemars(description = "Shell command to run with sh, e.g. \"git status --short\".")]
+    command: String,
+
+    #[schemars(
+        description = "Pane whose working directory the command gets, and whose environment env copies from. Formats:\n- \"x\" - pane x in current window\n- \"y.x\" - pane x in window y (current session)\n- \"sess:y.x\" - pane x in window y in session sess\nDefaults to the pane this server runs in, if any."
+    )]
+    target: Option<String>,
+
+    #[schemars(description = "Directory to run the command in, instead of the pane's.")]
+    start_directory: Option<String>,
+
+    #[schemars(
+        description = "Names of variables to copy from the environment the pane's program started with, e.g. [\"VIRTUAL_ENV\"]. Their values appear briefly on tmux's command line, so leave out secrets. Variables exported later in the pane's shell aren't seen. Everything else comes from the tmux server's environment."
+    )]
+    env: Option<Vec<String>>,
+
+    #[schemars(
+        description = "Seconds to wait for the command, at most 300. A command still running then is killed. Defaults to 30."
+    )]
+    timeout_seconds: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct BroadcastCommandRequest {
+    #[schemars(description = "Shell command to run in each pane, e.g. \"uptime\".")]
+    command: String,
+
+    #[schemars(
+        description = "Run in every pane of this window. Formats: \"y\" (window y in the current session), \"sess:y\". Give exactly one of window, panes or filter."
+    )]
+    window: Option<String>,
+
+    #[schemars(
+        description = "Run in these panes, each as \"x\", \"y.x\", \"sess:y.x\" or a pane ID like \"%3\"."
+    )]
+    panes: Option<Vec<String>>,
+
+    #[schemars(description = "Run in the panes find_panes would return for this filter.")]
+    filter: Option<FindPanesRequest>,
+
+    #[schemars(
+        description = "Seconds to wait for the command to finish in every pane, at most 300. Defaults to 30."
+    )]
+    timeout_seconds: Option<u64>,
+
+    #[schemars(description = "Most lines of output to return per pane, the last ones. Defaults to 50.")]
+    max_lines: Option<usize>,
+
+    #[schemars(
+        description = "Send even to panes showing a password or confirmation prompt. Defaults to false."
+    )]
+    force: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct PasteTextRequest {
+    #[schemars(
+        description = "Target pane. Formats:\n- \"x\" - pane x in current window\n- \"y.x\" - pane x in window y (current session)\n- \"sess:y.x\" - pane x in window y in session sess\nExamples: \"1\", \"5.1\", \"API:5.1\""
+    )]
+    target: String,
+
+    #[schemars(description = "Text to paste, e.g. a multi-line script or a block of code.")]
+    text: String,
+
+    #[schemars(description = "Press Enter after pasting. Defaults to false.")]
+    enter: Option<bool>,
+
+    #[schemars(
+        description = "Paste even if the pane shows a password or confirmation prompt. Defaults to false."
+    )]
+    force: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct PasteFileRequest {
+    #[schemars(
+        description = "Target pane. Formats:\n- \"x\" - pane x in current window\n- \"y.x\" - pane x in window y (current session)\n- \"sess:y.x\" - pane x in window y in session sess\nExamples: \"1\", \"5.1\", \"API:5.1\""
+    )]
+    target: String,
+
+    #[schemars(
+        description = "Path of a local text file to paste, at most 1 MiB. A leading \"~\" expands to $HOME."
+    )]
+    path: String,
+
+    #[schemars(description = "Press Enter after pasting. Defaults to false.")]
+    enter: Option<bool>,
+
+    #[schemars(
+        description = "Paste even if the pane shows a password or confirmation prompt. Defaults to false."
+    )]
+    force: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GetBufferRequest {
+    #[schemars(description = "Buffer name, as shown by list_buffers. Defaults to the most recent buffer.")]
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct SetBufferRequest {
+    #[schemars(description = "Text to put in the buffer.")]
+    content: String,
+
+    #[schemars(
+        description = "Buffer name. If omitted, tmux picks one (buffer0, buffer1, ...) and the buffer becomes the most recent, which is what the user's paste key pastes."
+    )]
+    name: Option<String>,
+
+    #[schemars(
+        description = "Also copy the text to the system clipboard of the terminals attached to tmux, where they support it (OSC 52, tmux's set-clipboard option). Defaults to false."
+    )]
+    clipboard: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct DeleteBufferRequest {
+    #[schemars(description = "Buffer name, as shown by list_buffers.")]
+    name: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GetUserSelectionRequest {
+    #[schemars(
+        description = "Pane to read the selection from: \"x\", \"y.x\", \"sess:y.x\" or a pane ID. Defaults to the pane of the most recently active attached client."
+    )]
+    target: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct GetCommandHistoryRequest {
+    #[schemars(
+        description = "Target pane. Formats:\n- \"x\" - pane x in current window\n- \"y.x\" - pane x in window y (current session)\n- \"sess:y.x\" - pane x in window y in session sess\nExamples: \"1\", \"5.1\", \"API:5.1\""
+    )]
+    target: String,
+
+    #[schemars(description = "Number of most recent commands to return. Defaults to 5.")]
+    count: Option<u32>,
+
+    #[schemars(
+        description = "Show at most this many output lines per command, the last ones. Defaults to 50."
+    )]
+    max_output_lines: Option<u32>,
+
+    #[schemars(
+        description = "Regex matching a prompt at the start of a line, used when the pane isn't being tracked with shell integration; the rest of the line is the command. Defaults to a prompt ending in $, #, % or ❯ and a space."
+    )]
+    prompt_pattern: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct TrackCommandsRequest {
+    #[schemars(
+        description = "Target pane. Formats:\n- \"x\" - pane x in current window\n- \"y.x\" - pane x in window y (current session)\n- \"sess:y.x\" - pane x in window y in session sess\nExamples: \"1\", \"5.1\", \"API:5.1\""
+    )]
+    target: String,
+
+    #[schemars(description = "When true, stop tracking the pane. Defaults to false.")]
+    stop: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct FindPanesRequest {
+    #[schemars(
+        description = "Pattern to match against the command running in the pane (pane_current_command), e.g. \"psql\" or \"*vim*\"."
+    )]
+    command: Option<String>,
+
+    #[schemars(
+        description = "Pattern to match against the pane's working directory (pane_current_path). A leading \"~\" expands to $HOME, e.g. \"~/src/api*\"."
+    )]
+    path: Option<String>,
+
+    #[schemars(description = "Pattern to match against the pane title (pane_title).")]
+    title: Option<String>,
+
+    #[schemars(description = "Pattern to match against the window name.")]
+    window_name: Option<String>,
+
+    #[schemars(description = "Pattern to match against the session name.")]
+    session_name: Option<String>,
+
+    #[schemars(
+        description = "When true, patterns are regular expressions (unanchored). Otherwise they are globs matched against the whole value, where \"*\" matches any run of characters and \"?\" a single character. Defaults to false."
+    )]
+    regex: Option<bool>,
+}
+
+// -- Helpers --
+
+/// Compile a find_panes filter. Globs are anchored and translated to a regex;
+/// regex patterns are used as given.
+fn compile_pattern(pattern: &str, is_regex: bool) -> Result<Regex, TmuxError> {
+    let source = if is_regex {
+        pattern.to_string()
+    } else {
+        let mut re = String::from("^");
+        for c in pattern.chars() {
+            match c {
+                '*' => re.push_str(".*"),
+                '?' => re.push('.'),
+                c => re.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        re.push('$');
+        re
+    };
+    Regex::new(&source)
+        .map_err(|e| TmuxError::InvalidArgument(format!("Invalid pattern \"{pattern}\": {e}")))
+}
+
+/// This process's ID and a count of the calls so far, for names like a paste
+/// buffer's that must not collide with another call's or another server's.
+fn unique_suffix() -> String {
+    static CALLS: AtomicU64 = AtomicU64::new(0);
+    format!("{}-{}", std::process::id(), CALLS.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Expand a leading "~" in a path to $HOME.
+fn expand_home(path: &str) -> String {
+    expand_tilde(path, std::env::var("HOME").ok().as_deref(), false)
+}
+
+/// Expand a leading "~" in a path or path pattern to `home`. In a regex, `home`
+/// is escaped so it matches only itself.
+fn expand_tilde(pattern: &str, home: Option<&str>, is_regex: bool) -> String {
+    match (pattern.strip_prefix('~'), home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            let home = if is_regex { regex::escape(home) } else { home.to_string() };
+            format!("{home}{rest}")
+        }
+        _ => pattern.to_string(),
+    }
+}
+
+impl TmuxMcp {
+    fn new(tmux: Arc<dyn TmuxBackend>, current_pane_id: Option<String>) -> Self {
+        Self {
+            tool_router: Self::tool_router(),
+            tmux,
+            current_pane_id,
+            templates_dir: template::default_dir(),
+            snapshots_dir: snapshot::default_dir(),
+            read_cursors: Default::default(),
+            command_trackers: Default::default(),
+        }
+    }
+
+    /// A clone for another client: shared backend, separate read cursors.
+    fn for_new_client(&self) -> Self {
+        Self {
+            read_cursors: Default::default(),
+            ..self.clone()
+        }
+    }
+
+    fn with_templates_dir(mut self, dir: PathBuf) -> Self {
+        self.templates_dir = dir;
+        self
+    }
+
+    fn with_snapshots_dir(mut self, dir: PathBuf) -> Self {
+        self.snapshots_dir = dir;
+        self
+    }
+
+    async fn run_tmux(&self, args: &[&str]) -> Result<String, TmuxError> {
+        self.tmux.run(args).await
+    }
+
+    /// Paste `content` into `target` through a buffer of its own, which is
+    /// deleted afterwards, after check_input. The paste is bracketed if the
+    /// program in the pane asked for that, so editors and shells take it as
+    /// typed text rather than as keys.
+    async fn paste(&self, target: &str, content: &[u8], force: bool) -> Result<(), TmuxError> {
+        self.check_input(target, force).await?;
+        let buffer = format!("mcp-paste-{}", unique_suffix());
+        self.tmux
+            .run_with_input(&["load-buffer", "-b", &buffer, "-"], content)
+            .await?;
+        // -d deletes the buffer once pasted, but not when the paste fails.
+        let pasted = self
+            .run_tmux(&["paste-buffer", "-p", "-d", "-b", &buffer, "-t", target])
+            .await;
+        if pasted.is_err() {
+            let _ = self.run_tmux(&["delete-buffer", "-b", &buffer]).await;
+        }
+        pasted.map(|_| ())
+    }
+
+    /// Given a pane ID like %47, query tmux for session:window or session:window.pane.
+    async fn resolve_pane_id(&self, pane_id: &str, format: &str) -> Result<String, TmuxError> {
+        self.run_tmux(&["display-message", "-t", pane_id, "-p", format])
+            .await
+            .map(|s| s.trim().to_string())
+    }
+
+    /// Resolve a pane target ("x", "y.x", "sess:y.x" or a pane ID like "%12")
+    /// to something tmux accepts with -t, filling in the current session and
+    /// window from $TMUX_PANE.
+    async fn resolve_pane_target(&self, t: &str) -> Result<String, TmuxError> {
+        if t.starts_with('%') {
+            return Ok(t.to_string());
+        }
+        if t.contains(':') {
+            // "sess:y.x" - fully qualified
+            if !t.contains('.') {
+                return Err(TmuxError::InvalidArgument(format!("Invalid target \"{t}\": expected \"sess:window.pane\" but no pane specifier found. Use get_window_contents to read an entire window.")));
+            }
+            return Ok(t.to_string());
+        }
+        let Some(pane_id) = &self.current_pane_id else {
+            return Err(TmuxError::NotInTmux("Not running inside tmux".into()));
+        };
+        if t.contains('.') {
+            // "y.x" - window.pane, prepend current session
+            let session = self.resolve_pane_id(pane_id, "#{session_name}").await?;
+            Ok(format!("{session}:{t}"))
+        } else {
+            // "x" - bare pane index, prepend current session:window
+            let current_window = self
+                .resolve_pane_id(pane_id, "#{session_name}:#{window_index}")
+                .await?;
+            Ok(format!("{current_window}.{t}"))
+        }
+    }
+
+    /// Resolve a window target ("y", "sess:y", or None for the current window).
+    async fn resolve_window_target(&self, t: Option<&str>) -> Result<String, TmuxError> {
+        match t {
+            Some(t) if t.contains(':') || t.starts_with('@') => Ok(t.to_string()),
+            Some(t) => {
+                // Bare window index, prepend current session
+                let Some(pane_id) = &self.current_pane_id else {
+                    return Err(TmuxError::NotInTmux("Not running inside tmux".into()));
+                };
+                let session = self.resolve_pane_id(pane_id, "#{session_name}").await?;
+                Ok(format!("{session}:{t}"))
+            }
+            None => {
+                let Some(pane_id) = &self.current_pane_id else {
+                    return Err(TmuxError::NotInTmux(
+                        "No target specified and not running inside tmux".into(),
+                    ));
+                };
+                self.resolve_pane_id(pane_id, "#{session_name}:#{window_index}")
+                    .await
+            }
+        }
+    }
+
+    async fn fetch_panes(
+        &self,
+        session: &str,
+        window_index: &str,
+    ) -> Result<Vec<PaneInfo>, TmuxError> {
+        let target = format!("{session}:{window_index}");
+        let format =
+            "#{pane_index}\t#{pane_width}\t#{pane_height}\t#{pane_current_command}\t#{?pane_active,1,0}\t#{pane_id}\t#{pane_dead}\t#{pane_dead_status}\t#{pane_dead_signal}";
+        let output = self.run_tmux(&["list-panes", "-t", &target, "-F", format]).await?;
+        let mut panes = Vec::new();
+        for line in output.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 9 {
+                continue;
+            }
+            panes.push(PaneInfo {
+                pane_index: fields[0].parse().unwrap_or(0),
+                width: fields[1].parse().unwrap_or(0),
+                height: fields[2].parse().unwrap_or(0),
+                current_command: fields[3].to_string(),
+                is_active: fields[4] == "1",
+                pane_id: fields[5].to_string(),
+                dead: (fields[6] == "1").then(|| describe_exit(fields[7], fields[8])),
+            });
+        }
+        Ok(panes)
+    }
+
+    /// The sessions a user's client is attached to. Unlike
+    /// `session_attached`, this leaves out control-mode clients, such as the
+    /// one `--control-mode` attaches.
+    async fn attached_sessions(&self) -> Result<HashSet<String>, TmuxError> {
+        let output = self
+            .run_tmux(&["list-clients", "-F", "#{client_control_mode}\t#{session_name}"])
+            .await?;
+        Ok(output
+            .lines()
+            .filter_map(|l| l.strip_prefix("0\t"))
+            .map(String::from)
+            .collect())
+    }
+
+    /// All jobs on the server, i.e. windows tagged by start_job.
+    async fn jobs(&self) -> Result<Vec<jobs::Job>, TmuxError> {
+        let output = self.run_tmux(&["list-windows", "-a", "-F", jobs::FORMAT]).await?;
+        Ok(output.lines().filter_map(jobs::Job::parse).collect())
+    }
+
+    /// The job named `job`, or in pane `job`.
+    async fn find_job(&self, job: &str) -> Result<jobs::Job, TmuxError> {
+        let job = job.trim();
+        self.jobs()
+            .await?
+            .into_iter()
+            .find(|j| j.name == job || j.pane_id == job)
+            .ok_or_else(|| {
+                TmuxError::InvalidArgument(format!("No job named {job:?}; list_jobs shows the jobs"))
+            })
+    }
+
+    /// Run `command` under [`jobs::WRAPPER`] in a new window of the jobs
+    /// session, creating the session if needed. `env` holds `NAME=value`
+    /// entries, which go on tmux's command line. Returns the new pane's ID and
+    /// `session:window`.
+    async fn spawn_wrapped(
+        &self,
+        name: &str,
+        dir: Option<&str>,
+        env: &[String],
+        command: &str,
+    ) -> Result<(String, String), TmuxError> {
+        let mut args = vec!["-d", "-n", name, "-P", "-F", "#{pane_id}\t#{session_name}:#{window_index}"];
+        if let Some(dir) = dir {
+            args.extend(["-c", dir]);
+        }
+        for var in env {
+            args.extend(["-e", var.as_str()]);
+        }
+        args.extend(["sh", "-c", jobs::WRAPPER, "sh", command]);
+        let session = format!("={}:", jobs::SESSION);
+        let created = match self.run_tmux(&[&["new-window", "-t", &session][..], &args].concat()).await {
+            Ok(created) => created,
+            Err(_) => {
+                self.run_tmux(&[&["new-session", "-s", jobs::SESSION][..], &args].concat())
+                    .await?
+            }
+        };
+        match created.trim_end().split_once('\t') {
+            Some((pane_id, window)) => Ok((pane_id.to_string(), window.to_string())),
+            None => Err(TmuxError::Tmux(format!("Unexpected new window info: {created:?}"))),
+        }
+    }
+
+    /// Capture a pane's visible area plus `scroll_back` lines of history. With
+    /// `escapes`, text attributes are kept as SGR sequences (`-e`).
+    async fn capture_pane(
+        &self,
+        target: &str,
+        scroll_back: u32,
+        escapes: bool,
+    ) -> Result<String, TmuxError> {
+        let start_line = if scroll_back > 0 {
+            format!("-{scroll_back}")
+        } else {
+            "0".to_string()
+        };
+
+        let mut args = vec!["capture-pane", "-p", "-J", "-t", target, "-S", &start_line];
+        if escapes {
+            args.push("-e");
+        }
+        self.run_tmux(&args).await
+    }
+
+    /// Capture a pane, with a note when it is on the alternate screen or in a
+    /// mode. With `normal_screen`, a pane on the alternate screen is captured
+    /// from the saved normal screen (`capture-pane -a`) instead.
+    async fn capture_pane_with_state(
+        &self,
+        target: &str,
+        scroll_back: u32,
+        normal_screen: bool,
+        escapes: bool,
+    ) -> Result<PaneCapture, TmuxError> {
+        let state = self
+            .run_tmux(&["display-message", "-t", target, "-p", PaneScreenState::FORMAT])
+            .await?;
+        let state = PaneScreenState::parse(&state);
+        let showing_normal = normal_screen && state.alternate_on;
+        let note = state.note(showing_normal);
+
+        if !showing_normal {
+            let contents = self.capture_pane(target, scroll_back, escapes).await?;
+            return Ok(PaneCapture { note, contents });
+        }
+
+        let mut contents = String::new();
+        // The alternate screen only replaces the visible area; history still
+        // belongs to the normal screen, so read it separately.
+        if scroll_back > 0 && state.history_size > 0 {
+            let start_line = format!("-{scroll_back}");
+            let mut args = vec!["capture-pane", "-p", "-J", "-t", target, "-S", &start_line, "-E", "-1"];
+            if escapes {
+                args.push("-e");
+            }
+            contents.push_str(&self.run_tmux(&args).await?);
+        }
+        let mut args = vec!["capture-pane", "-p", "-J", "-a", "-q", "-t", target];
+        if escapes {
+            args.push("-e");
+        }
+        contents.push_str(&self.run_tmux(&args).await?);
+        Ok(PaneCapture { note, contents })
+    }
+}
+
+impl TmuxMcp {
+    /// Create `session` from `template`. The caller removes the session if this
+    /// fails part way.
+    async fn build_workspace(
+        &self,
+        session: &str,
+        template: &template::Template,
+        force: bool,
+    ) -> Result<(), TmuxError> {
+        let base_root = template::resolve_root(None, template.root.as_deref());
+        for (i, window) in template.windows.iter().enumerate() {
+            let window_root = template::resolve_root(base_root.as_deref(), window.root.as_deref());
+            let default_pane = [template::Pane::default()];
+            let panes = if window.panes.is_empty() {
+                &default_pane[..]
+            } else {
+                &window.panes[..]
+            };
+
+            // split-window puts the new pane right after its target, so
+            // splitting the previous pane keeps the template's order.
+            let mut first_pane = String::new();
+            let mut last_pane = String::new();
+            for (j, pane) in panes.iter().enumerate() {
+                let root = template::resolve_root(window_root.as_deref(), pane.root.as_deref());
+                let env: Vec<String> = template
+                    .env
+                    .iter()
+                    .chain(&window.env)
+                    .chain(&pane.env)
+                    .map(|(k, v)| format!("{k}={v}"))
+                    .collect();
+                let session_target = format!("={session}:");
+
+                let mut args = match (i, j) {
+                    (0, 0) => vec!["new-session", "-d", "-s", session],
+                    (_, 0) => vec!["new-window", "-d", "-t", &session_target],
+                    _ => vec!["split-window", "-d", "-t", &last_pane],
+                };
+                if j == 0
+                    && let Some(name) = &window.name
+                {
+                    args.extend(["-n", name]);
+                }
+                if let Some(root) = &root {
+                    args.extend(["-c", root]);
+                }
+                for var in &env {
+                    args.extend(["-e", var]);
+                }
+                args.extend(["-P", "-F", "#{pane_id}"]);
+                let pane_id = self.run_tmux(&args).await?.trim().to_string();
+
+                if j == 0 {
+                    first_pane = pane_id.clone();
+                } else {
+                    // Keep room for the next split; the window's own layout
+                    // is applied once every pane exists.
+                    self.run_tmux(&["select-layout", "-t", &first_pane, "tiled"]).await?;
+                }
+                if let Some(command) = &pane.command {
+                    self.send_line(&pane_id, command, force).await?;
+                }
+                last_pane = pane_id;
+            }
+
+            if let Some(requested) = &window.layout {
+                let layout = if layout::PRESETS.contains(&requested.as_str()) {
+                    requested.clone()
+                } else {
+                    layout::with_checksum(layout::body(requested))
+                };
+                self.run_tmux(&["select-layout", "-t", &first_pane, &layout]).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TmuxMcp {
+    async fn snapshot_session(
+        &self,
+        name: &str,
+        scroll_back: u32,
+    ) -> Result<snapshot::Session, TmuxError> {
+        let exact = format!("={name}");
+        let windows = self
+            .run_tmux(&[
+                "list-windows", "-t", &exact, "-F",
+                "#{window_index}\t#{?window_active,1,0}\t#{window_layout}\t#{window_name}",
+            ])
+            .await?;
+        let panes = self
+            .run_tmux(&[
+                "list-panes", "-s", "-t", &exact, "-F",
+                "#{window_index}\t#{pane_index}\t#{pane_id}\t#{pane_pid}\t#{?pane_active,1,0}\t#{pane_current_command}\t#{pane_current_path}",
+            ])
+            .await?;
+
+        let mut session = snapshot::Session {
+            name: name.to_string(),
+            windows: Vec::new(),
+        };
+        for line in windows.lines() {
+            let f: Vec<&str> = line.splitn(4, '\t').collect();
+            if f.len() < 4 {
+                continue;
+            }
+            session.windows.push(snapshot::Window {
+                index: f[0].parse().unwrap_or(0),
+                active: f[1] == "1",
+                layout: f[2].to_string(),
+                name: f[3].to_string(),
+                panes: Vec::new(),
+            });
+        }
+        for line in panes.lines() {
+            let f: Vec<&str> = line.splitn(7, '\t').collect();
+            if f.len() < 7 {
+                continue;
+            }
+            let window_index: u32 = f[0].parse().unwrap_or(0);
+            let Some(window) = session.windows.iter_mut().find(|w| w.index == window_index) else {
+                continue;
+            };
+            let argv = f[3]
+                .parse()
+                .ok()
+                .and_then(procfs::foreground_pid)
+                .and_then(procfs::cmdline);
+            // Full-screen programs are re-run rather than replayed, so keep
+            // the shell output underneath them.
+            let scrollback = if scroll_back > 0 {
+                let capture = self
+                    .capture_pane_with_state(f[2], scroll_back, true, false)
+                    .await?;
+                Some(format!("{}\n", capture.contents.trim_end()))
+            } else {
+                None
+            };
+            window.panes.push(snapshot::Pane {
+                index: f[1].parse().unwrap_or(0),
+                active: f[4] == "1",
+                command: f[5].to_string(),
+                path: f[6].to_string(),
+                argv,
+                scrollback,
+            });
+        }
+        Ok(session)
+    }
+
+    /// Recreate a saved session, returning notes on what was or wasn't re-run.
+    /// The caller removes the session if this fails part way.
+    /// Panes with saved scrollback start by printing and deleting their file
+    /// in the replay directory, from [`write_replay_files`], where `replay`
+    /// also gives the session's position; the last one removes the directory.
+    async fn restore_session(
+        &self,
+        session: &snapshot::Session,
+        rerun: &[String],
+        force: bool,
+        replay: Option<(&Path, usize)>,
+    ) -> Result<Vec<String>, TmuxError> {
+        let name = session.name.as_str();
+        let mut notes = Vec::new();
+        for (i, window) in session.windows.iter().enumerate() {
+            let size = layout::Layout::parse(&window.layout)
+                .map(|l| (l.width.to_string(), l.height.to_string()))
+                .unwrap_or_else(|_| ("80".into(), "24".into()));
+            let window_target = format!("={name}:{}", window.index);
+
+            let mut first_pane = String::new();
+            let mut last_pane = String::new();
+            for (j, pane) in window.panes.iter().enumerate() {
+                let mut args = match (i, j) {
+                    (0, 0) => vec!["new-session", "-d", "-s", name, "-x", &size.0, "-y", &size.1],
+                    (_, 0) => vec!["new-window", "-d", "-t", &window_target],
+                    _ => vec!["split-window", "-d", "-t", &last_pane],
+                };
+                if j == 0 {
+                    args.extend(["-n", &window.name]);
+                }
+                args.extend(["-c", &pane.path, "-P", "-F", "#{pane_id}"]);
+
+                // Saved scrollback is shown by starting the pane with `cat`
+                // before handing over to the shell.
+                let replay = match (&pane.scrollback, replay) {
+                    (Some(_), Some((dir, k))) => {
+                        let file = replay_file(dir, k, i, j);
+                        let file = snapshot::shell_quote(&file.to_string_lossy());
+                        let dir = snapshot::shell_quote(&dir.to_string_lossy());
+                        Some(format!(
+                            "cat {file}; rm -f {file}; rmdir {dir} 2>/dev/null; exec \"$SHELL\""
+                        ))
+                    }
+                    _ => None,
+                };
+                if let Some(replay) = &replay {
+                    args.push(replay);
+                }
+                let pane_id = self.run_tmux(&args).await?.trim().to_string();
+
+                if j == 0 {
+                    first_pane = pane_id.clone();
+                    if i == 0 {
+                        // new-session uses base-index rather than the saved index.
+                        let index = self
+                            .run_tmux(&["display-message", "-t", &pane_id, "-p", "#{window_index}"])
+                            .await?;
+                        let index = index.trim();
+                        if index != window.index.to_string()
+                            && let Err(e) = self
+                                .run_tmux(&["move-window", "-s", &pane_id, "-t", &window_target])
+                                .await
+                        {
+                            notes.push(format!(
+                                "Window {} kept index {index} instead of {}: {}",
+                                window.name,
+                                window.index,
+                                e.message()
+                            ));
+                        }
+                    }
+                } else {
+                    self.run_tmux(&["select-layout", "-t", &first_pane, "tiled"]).await?;
+                }
+
+                let address = format!("{name}:{}.{}", window.index, pane.index);
+                match pane.rerun_command(rerun) {
+                    Some(command) => {
+                        self.send_line(&pane_id, &command, force).await?;
+                        notes.push(format!("Re-ran in {address}: {command}"));
+                    }
+                    None if !is_shell(&pane.command) => {
+                        let command = pane.argv.as_ref().map_or(pane.command.clone(), |a| a.join(" "));
+                        notes.push(format!("Not re-run in {address}: {command}"));
+                    }
+                    None => {}
+                }
+                last_pane = pane_id;
+            }
+
+            self.run_tmux(&["select-layout", "-t", &first_pane, &window.layout]).await?;
+            if let Some(active) = window.panes.iter().find(|p| p.active) {
+                let target = format!("{window_target}.{}", active.index);
+                self.run_tmux(&["select-pane", "-t", &target]).await?;
+            }
+        }
+        if let Some(active) = session.windows.iter().find(|w| w.active) {
+            let target = format!("={name}:{}", active.index);
+            self.run_tmux(&["select-window", "-t", &target]).await?;
+        }
+        Ok(notes)
+    }
+}
+
+/// A new directory in the temp dir that only this user can enter.
+fn private_temp_dir(prefix: &str) -> std::io::Result<tempfile::TempDir> {
+    tempfile::Builder::new()
+        .prefix(prefix)
+        .permissions(std::fs::Permissions::from_mode(0o700))
+        .tempdir()
+}
+
+/// Write the saved scrollback of `sessions` to a new private directory, so
+/// concurrent restores can't swap or remove each other's files and nothing
+/// else can plant one there. None if there is no scrollback.
+fn write_replay_files(sessions: &[&snapshot::Session]) -> std::io::Result<Option<PathBuf>> {
+    let has_scrollback = sessions
+        .iter()
+        .flat_map(|s| &s.windows)
+        .flat_map(|w| &w.panes)
+        .any(|p| p.scrollback.is_some());
+    if !has_scrollback {
+        return Ok(None);
+    }
+    let dir = private_temp_dir("tmux-mcp-restore-")?;
+    for (k, session) in sessions.iter().enumerate() {
+        for (i, window) in session.windows.iter().enumerate() {
+            for (j, pane) in window.panes.iter().enumerate() {
+                if let Some(text) = &pane.scrollback {
+                    write_new_file(&replay_file(dir.path(), k, i, j), text.as_bytes())?;
+                }
+            }
+        }
+    }
+    Ok(Some(dir.keep()))
+}
+
+/// The prefix of the files holding the saved scrollback of the `k`th session
+/// being restored. Named by position, as session names can hold anything.
+fn replay_file_prefix(k: usize) -> String {
+    format!("{k}.")
+}
+
+/// The file holding the saved scrollback of the `k`th session's `j`th pane in
+/// its `i`th window.
+fn replay_file(dir: &Path, k: usize, i: usize, j: usize) -> PathBuf {
+    dir.join(format!("{}{i}-{j}.txt", replay_file_prefix(k)))
+}
+
+/// Remove the `k`th session's scrollback files after its restore failed, when
+/// its panes are gone or never started.
+fn remove_replay_files(dir: &Path, k: usize) {
+    let prefix = replay_file_prefix(k);
+    for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+    let _ = std::fs::remove_dir(dir);
+}
+
+/// Write a file that must not exist yet, so a symlink or file planted at the
+/// path is never followed or overwritten.
+fn write_new_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)?
+        .write_all(contents)
+}
+
+/// Whether a pane's command is just its shell.
+fn is_shell(command: &str) -> bool {
+    matches!(command, "bash" | "zsh" | "fish" | "sh" | "dash" | "ksh" | "tcsh" | "csh" | "nu")
+}
+
+impl TmuxMcp {
+    /// The text of the line holding `target`'s cursor.
+    async fn cursor_line(&self, target: &str) -> Result<String, TmuxError> {
+        let y = self
+            .run_tmux(&["display-message", "-t", target, "-p", "#{cursor_y}"])
+            .await?;
+        let y = y.trim();
+        let line = self
+            .run_tmux(&["capture-pane", "-p", "-t", target, "-S", y, "-E", y])
+            .await?;
+        Ok(line.trim_end().to_string())
+    }
+
+    /// Refuse to type into a password or confirmation prompt, where the input
+    /// could answer a question meant for the user, unless `force`.
+    async fn check_input(&self, target: &str, force: bool) -> Result<(), TmuxError> {
+        if force {
+            return Ok(());
+        }
+        let line = self.cursor_line(target).await?;
+        match pane_state::input_blocker(&line) {
+            Some(kind) => Err(TmuxError::PolicyDenied(format!(
+                "Not sending input to {target}: it is showing {kind} ({line:?}). Let the user answer it, or pass force=true if the input is meant as the answer."
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Type `text` into `target` literally and press Enter, after
+    /// check_input.
+    async fn send_line(&self, target: &str, text: &str, force: bool) -> Result<(), TmuxError> {
+        self.check_input(target, force).await?;
+        self.run_tmux(&["send-keys", "-t", target, "-l", "--", text]).await?;
+        self.run_tmux(&["send-keys", "-t", target, "Enter"]).await?;
+        Ok(())
+    }
+
+    /// The panes matching a find_panes filter, as the fields pane ID, session,
+    /// `session:window.pane`, window name, command, path and title.
+    async fn matching_panes(&self, req: &FindPanesRequest) -> Result<Vec<Vec<String>>, TmuxError> {
+        let is_regex = req.regex.unwrap_or(false);
+
+        let home = std::env::var("HOME").ok();
+        let path = req.path.as_deref().map(|p| expand_tilde(p, home.as_deref(), is_regex));
+        let filters = [
+            (req.session_name.as_deref(), 1),
+            (req.window_name.as_deref(), 3),
+            (req.command.as_deref(), 4),
+            (path.as_deref(), 5),
+            (req.title.as_deref(), 6),
+        ];
+        let mut matchers = Vec::new();
+        for (pattern, field) in filters {
+            let Some(pattern) = pattern else { continue };
+            matchers.push((compile_pattern(pattern, is_regex)?, field));
+        }
+
+        let format = "#{pane_id}\t#{session_name}\t#{session_name}:#{window_index}.#{pane_index}\t#{window_name}\t#{pane_current_command}\t#{pane_current_path}\t#{pane_title}";
+        let output = self.run_tmux(&["list-panes", "-a", "-F", format]).await?;
+
+        Ok(output
+            .lines()
+            .filter_map(|line| {
+                let f: Vec<&str> = line.split('\t').collect();
+                if f.len() < 7 {
+                    return None;
+                }
+                if !matchers.iter().all(|(re, field)| re.is_match(f[*field])) {
+                    return None;
+                }
+                Some(f.iter().map(|s| s.to_string()).collect())
+            })
+            .collect())
+    }
+
+    /// Capture lines `from..to` (exclusive), numbered from the oldest line of
+    /// a pane whose history holds `history_size` lines, without joining
+    /// wrapped lines.
+    async fn capture_numbered(
+        &self,
+        target: &str,
+        history_size: u64,
+        from: u64,
+        to: u64,
+    ) -> Result<String, TmuxError> {
+        if from >= to {
+            return Ok(String::new());
+        }
+        let start = (from as i64 - history_size as i64).to_string();
+        let end = (to as i64 - 1 - history_size as i64).to_string();
+        self.run_tmux(&["capture-pane", "-p", "-t", target, "-S", &start, "-E", &end])
+            .await
+    }
+}
+
+// -- Tool implementations --
+
+#[tool_router]
+impl TmuxMcp {
+    #[tool(
+        description = "List all tmux sessions with their properties. Set verbose=true for a full tree showing sessions, windows, and panes."
+    )]
+    async fn list_sessions(
+        &self,
+        Parameters(req): Parameters<ListSessionsRequest>,
+    ) -> Result<String, TmuxError> {
+        let verbose = req.verbose.unwrap_or(false);
+
+        let format = "#{session_name}\t#{session_windows}";
+        let output = self.run_tmux(&["list-sessions", "-F", format]).await?;
+        let attached = self.attached_sessions().await?;
+
+        struct SessionRow {
+            name: String,
+            window_count: String,
+            state: String,
+        }
+
+        let sessions: Vec<SessionRow> = output
+            .lines()
+            .filter_map(|line| {
+                let f: Vec<&str> = line.split('\t').collect();
+                if f.len() < 2 {
+                    return None;
+                }
+                let state = if attached.contains(f[0]) { "attached" } else { "detached" };
+                Some(SessionRow {
+                    name: f[0].to_string(),
+                    window_count: f[1].to_string(),
+                    state: state.to_string(),
+                })
+            })
+            .collect();
+
+        if !verbose {
+            let rows: Vec<Vec<String>> = sessions
+                .iter()
+                .map(|s| {
+                    vec![
+                        truncate(&s.name, MAX_NAME_LEN),
+                        format!("({})", s.state),
+                        format!(
+                            "{} window{}",
+                            s.window_count,
+                            if s.window_count == "1" { "" } else { "s" }
+                        ),
+                    ]
+                })
+                .collect();
+            return Ok(align_columns(&rows).join("\n"));
+        }
+
+        // Verbose: full tree
+        let mut out = String::new();
+        for session in &sessions {
+            let wcount: u32 = session.window_count.parse().unwrap_or(0);
+            out.push_str(&format!(
+                "{} ({})  {} window{}\n",
+                truncate(&session.name, MAX_NAME_LEN),
+                session.state,
+                wcount,
+                if wcount == 1 { "" } else { "s" }
+            ));
+
+            // Fetch windows for this session
+            let win_format = "#{window_index}\t#{window_name}\t#{window_panes}";
+            let wins = match self.run_tmux(&[
+                "list-windows",
+                "-t",
+                &format!("{}:", session.name),
+                "-F",
+                win_format,
+            ])
+            .await
+            {
+                Ok(w) => w,
+                Err(_) => continue,
+            };
+
+            for win_line in wins.lines() {
+                let wf: Vec<&str> = win_line.split('\t').collect();
+                if wf.len() < 3 {
+                    continue;
+                }
+                let win_idx = wf[0];
+                let win_name = wf[1];
+                let pane_count: u32 = wf[2].parse().unwrap_or(0);
+
+                out.push_str(&format!(
+                    "  {}:  {}  {} pane{}\n",
+                    win_idx,
+                    truncate(win_name, MAX_NAME_LEN),
+                    pane_count,
+                    if pane_count == 1 { "" } else { "s" }
+                ));
+
+                // Fetch panes
+                let panes = match self.fetch_panes(&session.name, win_idx).await {
+                    Ok(p) => p,
+                    Err(_) => continue,
+                };
+
+                let pane_rows: Vec<Vec<String>> = panes
+                    .iter()
+                    .map(|p| {
+                        let mut cols = vec![
+                            format!("    .{}", p.pane_index),
+                            format!("{}x{}", p.width, p.height),
+                            truncate(&p.current_command, MAX_CMD_LEN),
+                        ];
+                        let mut suffix = String::new();
+                        if let Some(exit) = &p.dead {
+                            suffix = format!("dead ({exit})");
+                        }
+                        if p.is_active {
+                            if !suffix.is_empty() {
+                                suffix.push_str("  ");
+                            }
+                            suffix.push_str("(active)");
+                        }
+                        if self.current_pane_id.as_deref() == Some(p.pane_id.as_str()) {
+                            if !suffix.is_empty() {
+                                suffix.push_str("  ");
+                            }
+                            suffix.push_str("<-- current");
+                        }
+                        if !suffix.is_empty() {
+                            cols.push(suffix);
+                        }
+                        cols
+                    })
+                    .collect();
+
+                for line in align_columns(&pane_rows) {
+                    out.push_str(&line);
+                    out.push('\n');
+                }
+            }
+        }
+
+        Ok(out.trim_end().to_string())
+    }
+
+    #[tool(
+        description = "List tmux windows. Optionally filter by session name. Set verbose=true to include pane details beneath each window."
+    )]
+    async fn list_windows(
+        &self,
+        Parameters(req): Parameters<ListWindowsRequest>,
+    ) -> Result<String, TmuxError> {
+        let verbose = req.verbose.unwrap_or(false);
+
+        let format = "#{session_name}\t#{window_index}\t#{window_name}\t#{window_panes}\t#{?window_active,active,}";
+
+        let result = match &req.session {
+            Some(session) => {
+                let target = format!("{session}:");
+                self.run_tmux(&["list-windows", "-t", &target, "-F", format]).await
+            }
+            None => self.run_tmux(&["list-windows", "-a", "-F", format]).await,
+        };
+
+        let output = result?;
+
+        // Resolve current window/pane for markers
+        let current_window = match &self.current_pane_id {
+            Some(pane_id) => {
+                self.resolve_pane_id(pane_id, "#{session_name}:#{window_index}")
+                    .await
+                    .ok()
+            }
+            None => None,
+        };
+
+        struct WinRow {
+            session: String,
+            index: String,
+            name: String,
+            pane_count: String,
+            active: String,
+        }
+
+        let windows: Vec<WinRow> = output
+            .lines()
+            .filter_map(|line| {
+                let f: Vec<&str> = line.split('\t').collect();
+                if f.len() < 5 {
+                    return None;
+                }
+                Some(WinRow {
+                    session: f[0].to_string(),
+                    index: f[1].to_string(),
+                    name: f[2].to_string(),
+                    pane_count: f[3].to_string(),
+                    active: f[4].to_string(),
+                })
+            })
+            .collect();
+
+        if !verbose {
+            let rows: Vec<Vec<String>> = windows
+                .iter()
+                .map(|w| {
+                    let key = format!("{}:{}", w.session, w.index);
+                    let pcount: u32 = w.pane_count.parse().unwrap_or(0);
+                    let mut cols = vec![
+                        key.clone(),
+                        truncate(&w.name, MAX_NAME_LEN),
+                        format!(
+                            "{} pane{}",
+                            pcount,
+                            if pcount == 1 { "" } else { "s" }
+                        ),
+                    ];
+                    if w.active == "active" {
+                        cols.push("active".to_string());
+                    }
+                    if current_window.as_deref() == Some(&key) {
+                        cols.push("<-- current".to_string());
+                    }
+                    cols
+                })
+                .collect();
+            return Ok(align_columns(&rows).join("\n"));
+        }
+
+        // Verbose: windows with panes expanded
+        let mut out = String::new();
+        for w in &windows {
+            let key = format!("{}:{}", w.session, w.index);
+            let pcount: u32 = w.pane_count.parse().unwrap_or(0);
+            let is_current_window = current_window.as_deref() == Some(&key);
+
+            out.push_str(&format!(
+                "{}:  {}  {} pane{}",
+                key,
+                truncate(&w.name, MAX_NAME_LEN),
+                pcount,
+                if pcount == 1 { "" } else { "s" }
+            ));
+            if w.active == "active" {
+                out.push_str("  active");
+            }
+            out.push('\n');
+
+            // Fetch panes if this window is accessible
+            let panes = match self.fetch_panes(&w.session, &w.index).await {
+                Ok(p) => p,
+                Err(_) => continue,
+            };
+
+            let pane_rows: Vec<Vec<String>> = panes
+                .iter()
+                .map(|p| {
+                    let mut cols = vec![
+                        format!("  .{}", p.pane_index),
+                        format!("{}x{}", p.width, p.height),
+                        truncate(&p.current_command, MAX_CMD_LEN),
+                    ];
+                    let mut suffix = String::new();
+                    if let Some(exit) = &p.dead {
+                        suffix = format!("dead ({exit})");
+                    }
+                    if p.is_active {
+                        if !suffix.is_empty() {
+                            suffix.push_str("  ");
+                        }
+                        suffix.push_str("(active)");
+                    }
+                    if is_current_window
+                        && self.current_pane_id.as_deref() == Some(p.pane_id.as_str())
+                    {
+                        if !suffix.is_empty() {
+                            suffix.push_str("  ");
+                        }
+                        suffix.push_str("<-- current");
+                    }
+                    if !suffix.is_empty() {
+                        cols.push(suffix);
+                    }
+                    cols
+                })
+                .collect();
+
+            for line in align_columns(&pane_rows) {
+                out.push_str(&line);
+                out.push('\n');
+            }
+        }
+
+        Ok(out.trim_end().to_string())
+    }
+
+    #[tool(
+        description = "Get the current tmux session name and window that this MCP server is running in."
+    )]
+    async fn get_current_session(&self) -> Result<String, TmuxError> {
+        let Some(pane_id) = &self.current_pane_id else {
+            return Err(TmuxError::NotInTmux(
+                "Not running inside tmux (TMUX_PANE not set)".into(),
+            ));
+        };
+
+        self.resolve_pane_id(pane_id, "#{session_name}:#{window_index} (window: #{window_name})")
+            .await
+    }
+
+    #[tool(
+        description = "Get the current tmux window index and name that this MCP server is running in."
+    )]
+    async fn get_current_window(&self) -> Result<String, TmuxError> {
+        let Some(pane_id) = &self.current_pane_id else {
+            return Err(TmuxError::NotInTmux(
+                "Not running inside tmux (TMUX_PANE not set)".into(),
+            ));
+        };
+
+        self.resolve_pane_id(
+            pane_id,
+            "#{session_name}:#{window_index}\t#{window_name}\t#{window_panes} panes",
+        )
+        .await
+    }
+
+    #[tool(
+        description = "Get the contents of a specific tmux pane. Supports scrollback history. When the pane is on the alternate screen (vim, less, htop) or in copy mode, the first line notes alternate_on, pane_in_mode and the copy-mode scroll position; set normal_screen=true to read the shell output underneath a full-screen program."
+    )]
+    async fn get_pane_contents(
+        &self,
+        Parameters(req): Parameters<GetPaneContentsRequest>,
+    ) -> Result<String, TmuxError> {
+        let scroll_back = req.scroll_back_lines.unwrap_or(0);
+
+        let normal_screen = req.normal_screen.unwrap_or(false);
+        let style = req.style.unwrap_or_default();
+
+        let target = self.resolve_pane_target(req.target.trim()).await?;
+
+        let capture = self
+            .capture_pane_with_state(&target, scroll_back, normal_screen, style != CaptureStyle::Plain)
+            .await?;
+        Ok(match style {
+            CaptureStyle::Plain => capture.into_text(),
+            CaptureStyle::Markup => PaneCapture {
+                contents: ansi::to_markup(&capture.contents),
+                ..capture
+            }
+            .into_text(),
+            CaptureStyle::Spans => serde_json::json!({
+                "note": capture.note,
+                "lines": ansi::parse(&capture.contents),
+            })
+            .to_string(),
+        })
+    }
+
+    #[tool(
+        description = "Get the contents of all panes in a tmux window. Supports scrollback history. If target is omitted, defaults to the current window."
+    )]
+    async fn get_window_contents(
+        &self,
+        Parameters(req): Parameters<GetWindowContentsRequest>,
+    ) -> Result<String, TmuxError> {
+        let scroll_back = req.scroll_back_lines.unwrap_or(0);
+        let normal_screen = req.normal_screen.unwrap_or(false);
+
+        let target = self.resolve_window_target(req.target.as_deref()).await?;
+
+        let pane_format = "#{session_name}:#{window_index}.#{pane_index}\t#{pane_title}\t#{pane_width}x#{pane_height}\t#{?pane_active,active,}";
+
+        let panes = self.run_tmux(&["list-panes", "-t", &target, "-F", pane_format]).await?;
+
+        let mut output = String::new();
+        for line in panes.lines() {
+            let pane_target = line.split('\t').next().unwrap_or("");
+            if pane_target.is_empty() {
+                continue;
+            }
+
+            output.push_str(&format!("=== Pane {pane_target} ({}) ===\n", line));
+            output.push_str(
+                &self
+                    .capture_pane_with_state(pane_target, scroll_back, normal_screen, false)
+                    .await?
+                    .into_text(),
+            );
+            output.push('\n');
+        }
+        Ok(output)
+    }
+
+    #[tool(
+        description = "Show how the panes of a tmux window are arranged: the layout string (which select_layout can apply again later), an ASCII map of the splits with each pane's index, ID, command and size, and one line per pane with its position (e.g. \"top-right\"). If target is omitted, defaults to the current window."
+    )]
+    async fn get_window_layout(
+        &self,
+        Parameters(req): Parameters<GetWindowLayoutRequest>,
+    ) -> Result<String, TmuxError> {
+        let max_width = req.width.unwrap_or(80).max(10);
+
+        let target = self.resolve_window_target(req.target.as_deref()).await?;
+
+        let window = self
+            .run_tmux(&[
+                "display-message", "-t", &target, "-p",
+                "#{session_name}:#{window_index}\t#{window_name}\t#{window_layout}",
+            ])
+            .await?;
+        let mut fields = window.trim_end_matches('\n').splitn(3, '\t');
+        let address = fields.next().unwrap_or("");
+        let name = fields.next().unwrap_or("");
+        let raw = fields.next().unwrap_or("");
+        let layout = layout::Layout::parse(raw).map_err(|e| TmuxError::Tmux(e.to_string()))?;
+
+        let pane_format = "#{pane_id}\t#{pane_index}\t#{pane_current_command}\t#{?pane_active,1,}";
+        let panes = self.run_tmux(&["list-panes", "-t", &target, "-F", pane_format]).await?;
+        // Keyed by the number in the pane ID, which is what the layout uses.
+        let panes: std::collections::HashMap<u32, Vec<&str>> = panes
+            .lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                let id = fields.first()?.strip_prefix('%')?.parse().ok()?;
+                Some((id, fields))
+            })
+            .collect();
+        let field = |id: u32, i: usize| panes.get(&id).and_then(|f| f.get(i)).copied().unwrap_or("");
+
+        let mut output = format!(
+            "{address} \"{name}\" {}x{} layout {raw}\n",
+            layout.width, layout.height
+        );
+        output.push_str(&layout::draw(&layout, max_width, |id| {
+            let active = if field(id, 3) == "1" { "*" } else { "" };
+            vec![
+                format!("{}{active} %{id}", field(id, 1)),
+                field(id, 2).to_string(),
+            ]
+        }));
+
+        let mut rows = Vec::new();
+        for pane in layout.panes() {
+            let layout::Node::Pane(id) = pane.node else {
+                continue;
+            };
+            rows.push(vec![
+                format!("%{id}"),
+                format!("{address}.{}", field(id, 1)),
+                layout::position(pane, &layout),
+                format!("{}x{}", pane.width, pane.height),
+                truncate(field(id, 2), MAX_CMD_LEN),
+                if field(id, 3) == "1" { "(active)".into() } else { String::new() },
+            ]);
+        }
+        for line in align_columns(&rows) {
+            output.push_str(line.trim_end());
+            output.push('\n');
+        }
+        Ok(output)
+    }
+
+    #[tool(
+        description = "Arrange the panes of a tmux window with a preset layout (even-horizontal, even-vertical, main-horizontal, main-vertical, tiled) or a layout string saved from get_window_layout. A layout string must have one cell per pane in the window. If target is omitted, defaults to the current window."
+    )]
+    async fn select_layout(
+        &self,
+        Parameters(req): Parameters<SelectLayoutRequest>,
+    ) -> Result<String, TmuxError> {
+        let target = self.resolve_window_target(req.target.as_deref()).await?;
+
+        let requested = req.layout.trim();
+        let layout = if layout::PRESETS.contains(&requested) {
+            requested.to_string()
+        } else {
+            let parsed = layout::Layout::parse(requested).map_err(|e| {
+                TmuxError::InvalidArgument(format!(
+                    "Not a preset ({}) or a valid layout string: {e}",
+                    layout::PRESETS.join(", ")
+                ))
+            })?;
+            let pane_count = self
+                .run_tmux(&["display-message", "-t", &target, "-p", "#{window_panes}"])
+                .await?;
+            let pane_count: usize = pane_count.trim().parse().unwrap_or(0);
+            let cells = parsed.panes().len();
+            if cells != pane_count {
+                return Err(TmuxError::InvalidArgument(format!(
+                    "Layout has {cells} panes but window {target} has {pane_count}"
+                )));
+            }
+            layout::with_checksum(layout::body(requested))
+        };
+
+        self.run_tmux(&["select-layout", "-t", &target, &layout]).await?;
+        let applied = self
+            .run_tmux(&["display-message", "-t", &target, "-p", "#{window_layout}"])
+            .await?;
+        Ok(format!("Applied layout to {target}: {}", applied.trim()))
+    }
+
+    #[tool(
+        description = "Resize a tmux pane, either to a width and/or height in cells or percent of the window, or by moving one of its edges in a direction."
+    )]
+    async fn resize_pane(
+        &self,
+        Parameters(req): Parameters<ResizePaneRequest>,
+    ) -> Result<String, TmuxError> {
+        // A number of cells, or a percentage of the window.
+        let is_size = |v: &str| {
+            let n = v.strip_suffix('%').unwrap_or(v);
+            !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())
+        };
+        for (name, value) in [("width", &req.width), ("height", &req.height)] {
+            if let Some(v) = value
+                && !is_size(v)
+            {
+                return Err(TmuxError::InvalidArgument(format!(
+                    "{name} must be a number of cells or a percentage like \"50%\", got {v:?}"
+                )));
+            }
+        }
+
+        let target = self.resolve_pane_target(req.target.trim()).await?;
+
+        let amount = req.amount.unwrap_or(1).to_string();
+        let mut args = vec!["resize-pane", "-t", &target];
+        if let Some(width) = &req.width {
+            args.extend(["-x", width]);
+        }
+        if let Some(height) = &req.height {
+            args.extend(["-y", height]);
+        }
+        if let Some(direction) = req.direction {
+            args.extend([direction.flag(), &amount]);
+        }
+        if args.len() == 3 {
+            return Err(TmuxError::InvalidArgument(
+                "Give width, height or direction".into(),
+            ));
+        }
+        self.run_tmux(&args).await?;
+
+        let size = self
+            .run_tmux(&["display-message", "-t", &target, "-p", "#{pane_width}x#{pane_height}"])
+            .await?;
+        Ok(format!("Pane {target} is now {}", size.trim()))
+    }
+
+    #[tool(
+        description = "Zoom a tmux pane to fill its window, or unzoom the window. With zoom omitted, toggles."
+    )]
+    async fn zoom_pane(&self, Parameters(req): Parameters<ZoomPaneRequest>) -> Result<String, TmuxError> {
+        let target = self.resolve_pane_target(req.target.trim()).await?;
+
+        let state = self
+            .run_tmux(&["display-message", "-t", &target, "-p", "#{window_zoomed_flag}\t#{pane_active}"])
+            .await?;
+        let (zoomed, active) = state.trim_end().split_once('\t').unwrap_or(("", ""));
+        let (zoomed, active) = (zoomed == "1", active == "1");
+
+        // resize-pane -Z unzooms a zoomed window whichever pane it is given,
+        // so zooming a pane while another is zoomed takes two toggles.
+        let toggles = match req.zoom {
+            None => 1,
+            Some(true) if zoomed && active => 0,
+            Some(true) if zoomed => 2,
+            Some(true) => 1,
+            Some(false) if zoomed => 1,
+            Some(false) => 0,
+        };
+        for _ in 0..toggles {
+            self.run_tmux(&["resize-pane", "-Z", "-t", &target]).await?;
+        }
+
+        let zoomed = match req.zoom {
+            Some(zoom) => zoom,
+            None => !zoomed,
+        };
+        Ok(if zoomed {
+            format!("Pane {target} is zoomed")
+        } else {
+            format!("Window of pane {target} is not zoomed")
+        })
+    }
+
+    #[tool(
+        description = "Render the visible area of a tmux pane as an image, with its real size and colors, to see exactly what the pane shows. Returns an SVG by default, or a PNG with format=\"png\"."
+    )]
+    async fn render_pane(
+        &self,
+        Parameters(req): Parameters<RenderPaneRequest>,
+    ) -> Result<RenderedPane, TmuxError> {
+        let normal_screen = req.normal_screen.unwrap_or(false);
+
+        let target = self.resolve_pane_target(req.target.trim()).await?;
+
+        let size = self
+            .run_tmux(&["display-message", "-t", &target, "-p", "#{pane_width}\t#{pane_height}"])
+            .await?;
+        let (width, height) = size
+            .trim()
+            .split_once('\t')
+            .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+            .ok_or_else(|| TmuxError::Tmux(format!("Unexpected pane size: {size:?}")))?;
+
+        let capture = self
+            .capture_pane_with_state(&target, 0, normal_screen, true)
+            .await?;
+        let grid = render::Grid::new(&capture.contents, width, height);
+        let base64 = base64::engine::general_purpose::STANDARD;
+        let image = match req.format.unwrap_or_default() {
+            ImageFormat::Svg => Content::image(base64.encode(grid.to_svg()), "image/svg+xml"),
+            ImageFormat::Png => {
+                let png = grid
+                    .to_png()
+                    .map_err(|e| TmuxError::Tmux(format!("Failed to encode PNG: {e}")))?;
+                Content::image(base64.encode(png), "image/png")
+            }
+        };
+        Ok(RenderedPane {
+            note: capture.note,
+            image,
+        })
+    }
+
+    #[tool(
+        description = "List the workspace templates create_workspace can use, with the session each creates and its windows."
+    )]
+    async fn list_templates(&self) -> Result<String, TmuxError> {
+        let templates = template::list(&self.templates_dir);
+        if templates.is_empty() {
+            return Ok(format!("No templates in {}", self.templates_dir.display()));
+        }
+
+        let rows: Vec<Vec<String>> = templates
+            .into_iter()
+            .map(|(name, template)| match template {
+                Ok(t) => {
+                    let windows: Vec<&str> = t
+                        .windows
+                        .iter()
+                        .map(|w| w.name.as_deref().unwrap_or("-"))
+                        .collect();
+                    vec![
+                        name.clone(),
+                        format!("session {}", t.session.unwrap_or(name)),
+                        format!("windows: {}", windows.join(", ")),
+                    ]
+                }
+                Err(e) => vec![name, "(invalid)".into(), format!("error: {e}")],
+            })
+            .collect();
+        Ok(align_columns(&rows).join("\n"))
+    }
+
+    #[tool(
+        description = "Create a tmux session from a workspace template: its windows, panes, layouts, start directories, commands and environment. Does nothing if the session already exists, so it is safe to call again."
+    )]
+    async fn create_workspace(
+        &self,
+        Parameters(req): Parameters<CreateWorkspaceRequest>,
+    ) -> Result<String, TmuxError> {
+        let template = template::load(&self.templates_dir, req.template.trim())?;
+        let session = req
+            .session
+            .or_else(|| template.session.clone())
+            .unwrap_or_else(|| req.template.trim().to_string());
+        if session.is_empty() || session.contains([':', '.']) {
+            return Err(TmuxError::InvalidArgument(format!(
+                "Invalid session name {session:?}: it can't be empty or contain ':' or '.'"
+            )));
+        }
+        // Check layout strings up front rather than leaving a half-built session.
+        for window in &template.windows {
+            let Some(requested) = &window.layout else {
+                continue;
+            };
+            if layout::PRESETS.contains(&requested.as_str()) {
+                continue;
+            }
+            let cells = layout::Layout::parse(requested)
+                .map_err(|e| TmuxError::InvalidArgument(format!("Layout {requested:?}: {e}")))?
+                .panes()
+                .len();
+            let panes = window.panes.len().max(1);
+            if cells != panes {
+                return Err(TmuxError::InvalidArgument(format!(
+                    "Layout {requested:?} has {cells} panes but the window has {panes}"
+                )));
+            }
+        }
+
+        let exact = format!("={session}");
+        let created = match self.run_tmux(&["has-session", "-t", &exact]).await {
+            Ok(_) => false,
+            Err(TmuxError::TargetNotFound(_) | TmuxError::ServerNotRunning(_)) => {
+                if let Err(e) = self.build_workspace(&session, &template, req.force.unwrap_or(false)).await {
+                    let _ = self.run_tmux(&["kill-session", "-t", &exact]).await;
+                    return Err(e);
+                }
+                true
+            }
+            Err(e) => return Err(e),
+        };
+
+        let windows = self
+            .run_tmux(&[
+                "list-windows", "-t", &exact, "-F",
+                "#{window_index}: #{window_name} (#{window_panes} panes)",
+            ])
+            .await?;
+        let status = if created {
+            format!("Created session {session} from template {}", req.template.trim())
+        } else {
+            format!("Session {session} already exists; left it unchanged")
+        };
+        Ok(format!("{status}\n{}", windows.trim_end()))
+    }
+
+    #[tool(
+        description = "Save sessions to a named JSON snapshot: windows, layouts, working directories, running commands and optionally scrollback, so restore_snapshot can recreate them after the tmux server restarts."
+    )]
+    async fn save_snapshot(
+        &self,
+        Parameters(req): Parameters<SaveSnapshotRequest>,
+    ) -> Result<String, TmuxError> {
+        let scroll_back = req.scroll_back_lines.unwrap_or(0);
+
+        let names = self.run_tmux(&["list-sessions", "-F", "#{session_name}"]).await?;
+        let names: Vec<&str> = names.lines().collect();
+        if let Some(wanted) = &req.sessions
+            && let Some(missing) = wanted.iter().find(|w| !names.contains(&w.as_str()))
+        {
+            return Err(TmuxError::TargetNotFound(format!("can't find session: {missing}")));
+        }
+
+        let mut sessions = Vec::new();
+        for name in names {
+            if req.sessions.as_ref().is_some_and(|w| !w.iter().any(|w| w == name)) {
+                continue;
+            }
+            sessions.push(self.snapshot_session(name, scroll_back).await?);
+        }
+
+        let windows: usize = sessions.iter().map(|s| s.windows.len()).sum();
+        let panes: usize = sessions
+            .iter()
+            .flat_map(|s| &s.windows)
+            .map(|w| w.panes.len())
+            .sum();
+        let session_count = sessions.len();
+        let path = snapshot::save(
+            &self.snapshots_dir,
+            req.name.trim(),
+            &snapshot::Snapshot::new(sessions),
+        )?;
+        Ok(format!(
+            "Saved {session_count} sessions, {windows} windows, {panes} panes to {}",
+            path.display()
+        ))
+    }
+
+    #[tool(
+        description = "Recreate sessions from a snapshot made by save_snapshot, with their windows, layouts, directories and saved scrollback. Programs on the rerun list (by default viewers like vim, less and htop) are started again; others are only reported. Sessions that already exist are skipped."
+    )]
+    async fn restore_snapshot(
+        &self,
+        Parameters(req): Parameters<RestoreSnapshotRequest>,
+    ) -> Result<String, TmuxError> {
+        let saved = snapshot::load(&self.snapshots_dir, req.name.trim())?;
+        let rerun = req
+            .rerun
+            .unwrap_or_else(|| snapshot::DEFAULT_RERUN.map(String::from).to_vec());
+
+        let mut output = Vec::new();
+        let mut restoring = Vec::new();
+        for session in &saved.sessions {
+            if req.sessions.as_ref().is_some_and(|w| !w.contains(&session.name)) {
+                continue;
+            }
+            let exact = format!("={}", session.name);
+            match self.run_tmux(&["has-session", "-t", &exact]).await {
+                Ok(_) => {
+                    output.push(format!("Skipped session {}: it already exists", session.name));
+                    continue;
+                }
+                Err(TmuxError::TargetNotFound(_) | TmuxError::ServerNotRunning(_)) => {}
+                Err(e) => return Err(e),
+            }
+            restoring.push(session);
+        }
+
+        // Every scrollback file is written before any pane starts, as each
+        // pane tries to remove the directory once it has printed its own.
+        let replay_dir = write_replay_files(&restoring)
+            .map_err(|e| TmuxError::Tmux(format!("Failed to save scrollback for replay: {e}")))?;
+        for (n, session) in restoring.iter().enumerate() {
+            let replay = replay_dir.as_deref().map(|dir| (dir, n));
+            let restored = self
+                .restore_session(session, &rerun, req.force.unwrap_or(false), replay)
+                .await;
+            match restored {
+                Ok(notes) => {
+                    output.push(format!(
+                        "Restored session {} with {} windows",
+                        session.name,
+                        session.windows.len()
+                    ));
+                    output.extend(notes.into_iter().map(|n| format!("  {n}")));
+                }
+                Err(e) => {
+                    let exact = format!("={}", session.name);
+                    let _ = self.run_tmux(&["kill-session", "-t", &exact]).await;
+                    if let Some(dir) = &replay_dir {
+                        for k in n..restoring.len() {
+                            remove_replay_files(dir, k);
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        if output.is_empty() {
+            return Ok("No matching sessions in the snapshot".into());
+        }
+        Ok(output.join("\n"))
+    }
+
+    #[tool(
+        description = "Read only the output a pane has printed since this client last read it, so polling a build or server doesn't resend the whole screen. The first read returns the visible screen. The line holding the cursor (usually the prompt) is shown separately and not counted as read until output moves past it. Notes when output was lost because the history was cleared or overflowed between reads."
+    )]
+    async fn read_new_output(
+        &self,
+        Parameters(req): Parameters<ReadNewOutputRequest>,
+    ) -> Result<String, TmuxError> {
+        let max_lines = req.max_lines.unwrap_or(500) as usize;
+
+        let target = self.resolve_pane_target(req.target.trim()).await?;
+
+        let info = self
+            .run_tmux(&[
+                "display-message", "-t", &target, "-p",
+                "#{pane_id}\t#{history_size}\t#{cursor_y}\t#{alternate_on}\t#{pane_current_command}",
+            ])
+            .await?;
+        let f: Vec<&str> = info.trim_end_matches('\n').splitn(5, '\t').collect();
+        let [pane_id, history_size, cursor_y, alternate_on, command] = f[..] else {
+            return Err(TmuxError::Tmux(format!("Unexpected pane info: {info:?}")));
+        };
+        if alternate_on == "1" {
+            return Ok(format!(
+                "[{pane_id} is showing the alternate screen of {command}, which doesn't add to its history; use get_pane_contents to read the screen]"
+            ));
+        }
+        let history_size: u64 = history_size.parse().unwrap_or(0);
+        // Lines before the cursor's are complete; numbering starts at the
+        // oldest history line.
+        let end = history_size + cursor_y.parse::<u64>().unwrap_or(0);
+
+        if req.reset.unwrap_or(false) {
+            self.read_cursors.lock().unwrap().remove(pane_id);
+        }
+        let cursor = self.read_cursors.lock().unwrap().get(pane_id).cloned();
+
+        let (start, lost) = match cursor {
+            None => (history_size, false),
+            Some(cursor) => {
+                let from = cursor.next.saturating_sub(cursor.anchor_len());
+                let unmoved = if cursor.next <= end {
+                    let lines = self.capture_numbered(&target, history_size, from, cursor.next).await?;
+                    cursor.locate(from, &lines.lines().collect::<Vec<_>>()) == Some(cursor.next)
+                } else {
+                    false
+                };
+                if unmoved {
+                    (cursor.next, false)
+                } else {
+                    // History was trimmed or cleared: look for the anchor
+                    // anywhere before the old position.
+                    let lines = self
+                        .capture_numbered(&target, history_size, 0, cursor.next.min(end))
+                        .await?;
+                    match cursor.locate(0, &lines.lines().collect::<Vec<_>>()) {
+                        Some(next) => (next, false),
+                        None => (0, true),
+                    }
+                }
+            }
+        };
+
+        // Capture through the cursor line, joining wrapped lines.
+        let from = (start as i64 - history_size as i64).to_string();
+        let output = self
+            .run_tmux(&["capture-pane", "-p", "-J", "-t", &target, "-S", &from, "-E", cursor_y])
+            .await?;
+        let mut lines: Vec<&str> = output.lines().map(str::trim_end).collect();
+        let current = lines.pop().unwrap_or("");
+
+        let anchor = self
+            .capture_numbered(&target, history_size, end.saturating_sub(3), end)
+            .await?;
+        self.read_cursors.lock().unwrap().insert(
+            pane_id.to_string(),
+            incremental::ReadCursor::new(end, &anchor.lines().collect::<Vec<_>>()),
+        );
+
+        let omitted = lines.len().saturating_sub(max_lines);
+        let lines = &lines[omitted..];
+        let mut header = match lines.len() {
+            0 => "[No new output".to_string(),
+            1 => "[1 new line".to_string(),
+            n => format!("[{n} new lines"),
+        };
+        if omitted > 0 {
+            let noun = if omitted == 1 { "line" } else { "lines" };
+            header.push_str(&format!("; {omitted} earlier {noun} omitted"));
+        }
+        if lost {
+            header.push_str("; some output was lost because the history was cleared or overflowed since the last read");
+        }
+        header.push(']');
+
+        let mut out = vec![header];
+        out.extend(lines.iter().map(|l| l.to_string()));
+        if !current.is_empty() {
+            out.push(format!("[cursor line] {current}"));
+        }
+        Ok(out.join("\n"))
+    }
+
+    #[tool(
+        description = "Type text and press keys in a pane. Refuses when the pane's cursor line shows a password, passphrase or one-time code prompt, or a confirmation such as [y/N], so input meant for a shell can't answer it by accident; pass force=true only when the input is the intended answer. Pressing only C-c or Escape is always allowed."
+    )]
+    async fn send_keys(
+        &self,
+        Parameters(req): Parameters<SendKeysRequest>,
+    ) -> Result<String, TmuxError> {
+        let target = self.resolve_pane_target(req.target.trim()).await?;
+        let text = req.text.unwrap_or_default();
+        let mut keys = req.keys.unwrap_or_default();
+        if req.enter.unwrap_or(false) {
+            keys.push("Enter".to_string());
+        }
+        if text.is_empty() && keys.is_empty() {
+            return Err(TmuxError::InvalidArgument(
+                "Nothing to send: give text, keys or enter".into(),
+            ));
+        }
+
+        // Interrupting is how a prompt gets abandoned, so it is never refused.
+        let interrupt = text.is_empty() && keys.iter().all(|k| k == "C-c" || k == "Escape");
+        if !interrupt {
+            self.check_input(&target, req.force.unwrap_or(false)).await?;
+        }
+        if !text.is_empty() {
+            self.run_tmux(&["send-keys", "-t", &target, "-l", "--", &text]).await?;
+        }
+        if !keys.is_empty() {
+            let mut args = vec!["send-keys", "-t", &target, "--"];
+            args.extend(keys.iter().map(String::as_str));
+            self.run_tmux(&args).await?;
+        }
+
+        let mut sent = Vec::new();
+        if !text.is_empty() {
+            let n = text.chars().count();
+            sent.push(format!("{n} character{}", if n == 1 { "" } else { "s" }));
+        }
+        if !keys.is_empty() {
+            sent.push(keys.join(" "));
+        }
+        Ok(format!("Sent {} to {target}", sent.join(" then ")))
+    }
+
+    #[tool(
+        description = "Tell what a pane is doing before typing into it: idle (a shell waiting for a command), running (a foreground command), interactive (a full-screen program such as vim or htop), password prompt, or dead (its program exited). Also reports the foreground process and how long it has run, the processes below the pane, the cursor line and how recently the window had output."
+    )]
+    async fn get_pane_state(
+        &self,
+        Parameters(req): Parameters<GetPaneStateRequest>,
+    ) -> Result<String, TmuxError> {
+        let target = self.resolve_pane_target(req.target.trim()).await?;
+        let info = self
+            .run_tmux(&[
+                "display-message", "-t", &target, "-p",
+                "#{pane_id}\t#{session_name}:#{window_index}.#{pane_index}\t#{pane_pid}\t#{pane_dead}\t#{pane_dead_status}\t#{pane_dead_signal}\t#{alternate_on}\t#{window_activity}\t#{pane_mode}\t#{pane_current_command}",
+            ])
+            .await?;
+        let f: Vec<&str> = info.trim_end_matches('\n').splitn(10, '\t').collect();
+        let [pane_id, address, pid, dead, dead_status, dead_signal, alternate_on, activity, mode, command] = f[..]
+        else {
+            return Err(TmuxError::Tmux(format!("Unexpected pane info: {info:?}")));
+        };
+        let prompt = compile_pattern(
+            req.prompt_pattern.as_deref().unwrap_or(blocks::DEFAULT_PROMPT),
+            true,
+        )?;
+        let cursor_line = self.cursor_line(&target).await?;
+        let pid: Option<u32> = pid.parse().ok();
+        let activity_age = activity.parse().ok().and_then(|secs| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+                .ok()
+        });
+
+        let state = pane_state::classify(&pane_state::Observation {
+            dead: dead == "1",
+            command,
+            root_in_foreground: pid
+                .and_then(procfs::stat)
+                .map(|stat| stat.tpgid == pid.unwrap_or_default() as i64),
+            alternate_on: alternate_on == "1",
+            cursor_line: &cursor_line,
+            at_prompt: pane_state::is_prompt(&cursor_line, &prompt),
+            activity_age,
+        });
+
+        let mut out = vec![
+            format!("state: {}", state.name()),
+            format!("hint: {}", state.hint()),
+            format!("pane: {pane_id} {address}"),
+        ];
+        if state == pane_state::State::Dead {
+            if dead_signal.is_empty() {
+                out.push(format!("exit status: {dead_status}"));
+            } else {
+                out.push(format!("killed by signal: {dead_signal}"));
+            }
+        } else if let Some(fg) = pid.and_then(procfs::foreground_pid) {
+            let args = procfs::cmdline(fg).map_or_else(|| command.to_string(), |a| a.join(" "));
+            let age = procfs::age(fg)
+                .map(|d| format!(", started {} ago", format_duration(d)))
+                .unwrap_or_default();
+            out.push(format!("foreground: {} (pid {fg}{age})", truncate(&args, 200)));
+        } else {
+            out.push(format!("foreground: {command}"));
+        }
+        if let Some(pid) = pid {
+            let below = procfs::descendants(pid);
+            if !below.is_empty() {
+                let mut names: Vec<String> = below
+                    .iter()
+                    .take(10)
+                    .map(|d| format!("{}({})", d.stat.comm, d.pid))
+                    .collect();
+                if below.len() > 10 {
+                    names.push(format!("and {} more", below.len() - 10));
+                }
+                out.push(format!("processes: {}", names.join(" ")));
+            }
+        }
+        if !mode.is_empty() {
+            out.push(format!("mode: {mode} (keys go to the mode, not the program)"));
+        }
+        out.push(format!("cursor line: {cursor_line:?}"));
+        if let Some(age) = activity_age {
+            out.push(format!("window activity: {} ago", format_duration(age)));
+        }
+        Ok(out.join("\n"))
+    }
+
+    #[tool(
+        description = "Show what a pane is actually running: the process tree under the pane's shell with each process's PID, CPU usage (sampled over a quarter second), resident memory, elapsed time, listening TCP addresses, working directory and command line. Needs Linux /proc on the machine running tmux."
+    )]
+    async fn get_pane_processes(
+        &self,
+        Parameters(req): Parameters<GetPaneProcessesRequest>,
+    ) -> Result<String, TmuxError> {
+        const SAMPLE: Duration = Duration::from_millis(250);
+
+        let target = self.resolve_pane_target(req.target.trim()).await?;
+        let info = self
+            .run_tmux(&[
+                "display-message", "-t", &target, "-p",
+                "#{pane_id}\t#{session_name}:#{window_index}.#{pane_index}\t#{pane_pid}",
+            ])
+            .await?;
+        let f: Vec<&str> = info.trim_end().splitn(3, '\t').collect();
+        let [pane_id, address, pid] = f[..] else {
+            return Err(TmuxError::Tmux(format!("Unexpected pane info: {info:?}")));
+        };
+        let Some(root) = pid.parse().ok().and_then(|pid| Some((pid, procfs::stat(pid)?))) else {
+            return Err(TmuxError::Tmux(format!(
+                "Can't read the process of {pane_id} (pid {pid:?}) from /proc; this needs Linux with tmux on the same machine"
+            )));
+        };
+
+        let mut processes = vec![procfs::Descendant {
+            pid: root.0,
+            depth: 0,
+            stat: root.1,
+        }];
+        processes.extend(procfs::descendants(root.0));
+        tokio::time::sleep(SAMPLE).await;
+
+        let mut rows = vec![
+            ["PID", "CPU", "MEM", "ELAPSED", "LISTEN", "CWD", "COMMAND"]
+                .map(String::from)
+                .to_vec(),
+        ];
+        let mut running = 0;
+        for process in &processes {
+            let pid = process.pid;
+            // Gone since the first look.
+            let Some(now) = procfs::stat(pid) else {
+                continue;
+            };
+            running += 1;
+            let busy = procfs::cpu_time(&now).saturating_sub(procfs::cpu_time(&process.stat));
+            let cpu = busy.as_secs_f64() / SAMPLE.as_secs_f64() * 100.0;
+            let listen: Vec<String> = procfs::listening_tcp(pid)
+                .iter()
+                .map(|a| a.to_string())
+                .collect();
+            let command = procfs::cmdline(pid)
+                .map_or_else(|| format!("[{}]", now.comm), |args| args.join(" "));
+            rows.push(vec![
+                pid.to_string(),
+                format!("{cpu:.0}%"),
+                procfs::rss_kib(pid).map_or("-".into(), format_kib),
+                procfs::age(pid).map_or("-".into(), format_duration),
+                if listen.is_empty() { "-".into() } else { listen.join(",") },
+                procfs::cwd(pid).map_or("-".into(), |d| d.display().to_string()),
+                format!("{}{}", "  ".repeat(process.depth), truncate(&command, 120)),
+            ]);
+        }
+
+        let mut out = vec![format!(
+            "{pane_id} {address}: {running} process{}",
+            if running == 1 { "" } else { "es" }
+        )];
+        out.extend(align_columns(&rows));
+        Ok(out.join("\n"))
+    }
+
+    #[tool(
+        description = "Send a signal to the job in the foreground of a pane's terminal, i.e. its whole process group, for when C-c isn't enough: a stuck process or a program that swallows the key. Refuses the pane this server runs in, any job this server belongs to, and an idle shell. Reports whether the job exited."
+    )]
+    async fn signal_pane(
+        &self,
+        Parameters(req): Parameters<SignalPaneRequest>,
+    ) -> Result<String, TmuxError> {
+        use std::os::unix::fs::MetadataExt;
+
+        let signal = req.signal.unwrap_or(Signal::Sigint);
+        let target = self.resolve_pane_target(req.target.trim()).await?;
+        let info = self
+            .run_tmux(&[
+                "display-message", "-t", &target, "-p",
+                "#{pane_id}\t#{pane_pid}\t#{pane_tty}\t#{pane_dead}",
+            ])
+            .await?;
+        let f: Vec<&str> = info.trim_end().splitn(4, '\t').collect();
+        let [pane_id, pid, tty, dead] = f[..] else {
+            return Err(TmuxError::Tmux(format!("Unexpected pane info: {info:?}")));
+        };
+        if dead == "1" {
+            return Err(TmuxError::InvalidArgument(format!(
+                "{pane_id} is dead; nothing in it can be signalled"
+            )));
+        }
+        if self.current_pane_id.as_deref() == Some(pane_id) {
+            return Err(TmuxError::PolicyDenied(format!(
+                "Won't signal {pane_id}: this server runs in it, so its foreground job is the client using this server"
+            )));
+        }
+
+        let Some((pid, root)) = pid.parse().ok().and_then(|pid| Some((pid, procfs::stat(pid)?))) else {
+            return Err(TmuxError::Tmux(format!(
+                "Can't read the process of {pane_id} (pid {pid:?}) from /proc; this needs Linux with tmux on the same machine"
+            )));
+        };
+        // The foreground group /proc reports must be that of the pane's tty.
+        if std::fs::metadata(tty).is_ok_and(|m| m.rdev() != root.tty_nr) {
+            return Err(TmuxError::Tmux(format!(
+                "The process of {pane_id} is no longer attached to {tty}"
+            )));
+        }
+        let pgid = u32::try_from(root.tpgid).ok().filter(|&g| g > 0).ok_or_else(|| {
+            TmuxError::Tmux(format!("{tty} has no foreground process group"))
+        })?;
+        if pgid == root.pgrp && is_shell(&root.comm) && procfs::descendants(pid).is_empty() {
+            return Err(TmuxError::InvalidArgument(format!(
+                "Nothing is running in {pane_id}: its shell is waiting for a command"
+            )));
+        }
+        // Nor anything this server runs under, wherever it is.
+        let mut ancestor = Some(std::process::id());
+        while let Some(stat) = ancestor.and_then(procfs::stat) {
+            if stat.pgrp == pgid {
+                return Err(TmuxError::PolicyDenied(format!(
+                    "Won't signal {pane_id}: its foreground job includes this server or the client running it"
+                )));
+            }
+            ancestor = (stat.ppid > 1).then_some(stat.ppid);
+        }
+
+        let job = procfs::cmdline(pgid)
+            .map(|args| args.join(" "))
+            .or_else(|| procfs::stat(pgid).map(|s| s.comm))
+            .unwrap_or_else(|| format!("process group {pgid}"));
+        let group = nix::unistd::Pid::from_raw(pgid as i32);
+        nix::sys::signal::killpg(group, signal.to_nix()).map_err(|e| {
+            TmuxError::Tmux(format!("Failed to send SIG{} to process group {pgid}: {e}", signal.name()))
+        })?;
+
+        let mut remaining = 0;
+        for _ in 0..10 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            remaining = procfs::group_members(pgid).len();
+            if remaining == 0 {
+                break;
+            }
+        }
+        let outcome = match remaining {
+            0 => "it has exited".to_string(),
+            n => {
+                let verb = if n == 1 { "is" } else { "are" };
+                let mut s = format!("{n} of its processes {verb} still running");
+                if signal != Signal::Sigkill {
+                    s.push_str("; SIGKILL forces the job to exit");
+                }
+                s
+            }
+        };
+        Ok(format!(
+            "Sent SIG{} to {} in {pane_id}; {outcome}",
+            signal.name(),
+            truncate(&job, 120)
+        ))
+    }
+
+    #[tool(
+        description = "Restart a pane in place: its previous command, or a new one, runs again in the same spot of the window. Meant for panes whose program exited and stayed open (remain-on-exit), which list_windows marks as dead with their exit code. A pane whose program is still running is only respawned with kill=true. Refuses the pane this server runs in."
+    )]
+    async fn respawn_pane(
+        &self,
+        Parameters(req): Parameters<RespawnPaneRequest>,
+    ) -> Result<String, TmuxError> {
+        let target = self.resolve_pane_target(req.target.trim()).await?;
+        let info = self
+            .run_tmux(&[
+                "display-message", "-t", &target, "-p",
+                "#{pane_id}\t#{pane_dead}\t#{pane_current_command}",
+            ])
+            .await?;
+        let f: Vec<&str> = info.trim_end_matches('\n').splitn(3, '\t').collect();
+        let [pane_id, dead, command] = f[..] else {
+            return Err(TmuxError::Tmux(format!("Unexpected pane info: {info:?}")));
+        };
+        if self.current_pane_id.as_deref() == Some(pane_id) {
+            return Err(TmuxError::PolicyDenied(format!(
+                "Won't respawn {pane_id}: this server runs in it"
+            )));
+        }
+        let kill = req.kill.unwrap_or(false);
+        if dead != "1" && !kill {
+            return Err(TmuxError::InvalidArgument(format!(
+                "{pane_id} is still running {command}; pass kill=true to kill it and start over"
+            )));
+        }
+
+        let mut args = vec!["respawn-pane"];
+        if kill {
+            args.push("-k");
+        }
+        if let Some(dir) = req.start_directory.as_deref() {
+            args.extend(["-c", dir]);
+        }
+        args.extend(["-t", pane_id]);
+        let new_command = req.command.as_deref().map(str::trim).filter(|c| !c.is_empty());
+        args.extend(new_command);
+        self.run_tmux(&args).await?;
+
+        Ok(match new_command {
+            Some(c) => format!("Respawned {pane_id} running {c}"),
+            None => format!("Respawned {pane_id} with its previous command"),
+        })
+    }
+
+    #[tool(
+        description = "Start a long-running command as a background job, e.g. a full test suite or a build, without taking up a pane the user sees. It runs in its own window of the detached \"mcp-jobs\" session, which stays open after the command finishes so its output and exit code can be read. Jobs are kept in tmux, so they survive a restart of this server. Follow up with job_status (which can wait for it) and job_output."
+    )]
+    async fn start_job(&self, Parameters(req): Parameters<StartJobRequest>) -> Result<String, TmuxError> {
+        let command = req.command.trim();
+        if command.is_empty() {
+            return Err(TmuxError::InvalidArgument("command is empty".into()));
+        }
+        // No server yet just means no jobs; new-session starts one.
+        let existing = self.jobs().await.unwrap_or_default();
+        let taken: Vec<&str> = existing.iter().map(|j| j.name.as_str()).collect();
+        let name = jobs::unique_name(req.name.as_deref(), command, &taken);
+
+        let (pane_id, window) = self
+            .spawn_wrapped(&name, req.start_directory.as_deref(), &[], command)
+            .await?;
+        let pane_id = pane_id.as_str();
+
+        let started = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .to_string();
+        for (option, value) in [
+            ("@mcp_job", name.as_str()),
+            ("@mcp_job_command", command),
+            ("@mcp_job_started", &started),
+        ] {
+            self.run_tmux(&["set-option", "-w", "-t", pane_id, option, value]).await?;
+        }
+        Ok(format!(
+            "Started job {name} in {window} ({pane_id}); check on it with job_status or job_output"
+        ))
+    }
+
+    #[tool(
+        description = "List the background jobs started with start_job: name, whether each is running or how it ended (exit code or signal), when it started, how long it ran and its command."
+    )]
+    async fn list_jobs(&self) -> Result<String, TmuxError> {
+        let jobs = self.jobs().await?;
+        if jobs.is_empty() {
+            return Ok("No jobs".into());
+        }
+        let now = SystemTime::now();
+        let rows: Vec<Vec<String>> = jobs
+            .iter()
+            .map(|job| {
+                let started = job
+                    .started
+                    .and_then(|s| now.duration_since(s).ok())
+                    .map(|d| format!("started {} ago", format_duration(d)))
+                    .unwrap_or_default();
+                let ran = match (&job.status, job.duration(now)) {
+                    (jobs::Status::Running, Some(d)) => format!("for {}", format_duration(d)),
+                    (_, Some(d)) => format!("took {}", format_duration(d)),
+                    (_, None) => String::new(),
+                };
+                vec![
+                    job.name.clone(),
+                    job.status.to_string(),
+                    started,
+                    ran,
+                    job.window.clone(),
+                    truncate(&job.command, 60),
+                ]
+            })
+            .collect();
+        Ok(align_columns(&rows)
+            .iter()
+            .map(|l| l.trim_end())
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    #[tool(
+        description = "Report on a background job: running or how it ended (exit code or signal), its command, when it started, how long it ran and its last line of output. With wait_seconds, first waits up to that long for it to finish."
+    )]
+    async fn job_status(&self, Parameters(req): Parameters<JobStatusRequest>) -> Result<String, TmuxError> {
+        let wait = Duration::from_secs(req.wait_seconds.unwrap_or(0).min(300));
+        let deadline = tokio::time::Instant::now() + wait;
+        let mut job = self.find_job(&req.job).await?;
+        while job.status == jobs::Status::Running {
+            let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            // Waiting in slices notices a job killed before it could signal
+            // its channel too.
+            let channel = jobs::channel(&job.pane_id);
+            let slice = left.min(Duration::from_secs(2));
+            let _ = tokio::time::timeout(slice, self.run_tmux(&["wait-for", &channel])).await;
+            job = self.find_job(&req.job).await?;
+        }
+
+        let now = SystemTime::now();
+        let mut out = vec![
+            format!("job: {}", job.name),
+            format!("status: {}", job.status),
+            format!("command: {}", job.command),
+            format!("window: {} ({})", job.window, job.pane_id),
+        ];
+        if let Some(started) = job.started.and_then(|s| now.duration_since(s).ok()) {
+            out.push(format!("started: {} ago", format_duration(started)));
+        }
+        if let Some(duration) = job.duration(now) {
+            let label = if job.status == jobs::Status::Running { "running for" } else { "took" };
+            out.push(format!("{label}: {}", format_duration(duration)));
+        }
+        // The note in a dead pane can scroll the last output off the screen.
+        let screen = self
+            .run_tmux(&["capture-pane", "-p", "-J", "-S", "-50", "-t", &job.pane_id])
+            .await?;
+        if let Some(last) = jobs::output_lines(&screen).iter().rev().find(|l| !l.trim().is_empty()) {
+            out.push(format!("last output: {}", truncate(last.trim(), 200)));
+        }
+        Ok(out.join("\n"))
+    }
+
+    #[tool(
+        description = "Read the output of a background job, the last 100 lines by default, from its window's scrollback. Works while the job runs and after it ended."
+    )]
+    async fn job_output(&self, Parameters(req): Parameters<JobOutputRequest>) -> Result<String, TmuxError> {
+        let job = self.find_job(&req.job).await?;
+        let captured = self
+            .run_tmux(&["capture-pane", "-p", "-J", "-S", "-", "-E", "-", "-t", &job.pane_id])
+            .await?;
+        let lines = jobs::output_lines(&captured);
+        let wanted = req.lines.unwrap_or(100).max(1);
+        let shown = &lines[lines.len().saturating_sub(wanted)..];
+        let mut out = if shown.len() < lines.len() {
+            format!(
+                "[job {}: {}; last {} of {} lines]\n",
+                job.name,
+                job.status,
+                shown.len(),
+                lines.len()
+            )
+        } else {
+            format!(
+                "[job {}: {}; {} line{}]\n",
+                job.name,
+                job.status,
+                lines.len(),
+                if lines.len() == 1 { "" } else { "s" }
+            )
+        };
+        out.push_str(&shown.join("\n"));
+        Ok(out)
+    }
+
+    #[tool(
+        description = "Run a short shell command without touching any pane and return its output and exit code, e.g. \"git status\" in a project's directory. It runs in a throwaway window with the working directory of a chosen pane (by default the one this server runs in) and the tmux server's environment, plus any variables named in env, and the window is removed afterwards. For long-running commands use start_job."
+    )]
+    async fn exec(&self, Parameters(req): Parameters<ExecRequest>) -> Result<String, TmuxError> {
+        let command = req.command.trim();
+        if command.is_empty() {
+            return Err(TmuxError::InvalidArgument("command is empty".into()));
+        }
+        let timeout = Duration::from_secs(req.timeout_seconds.unwrap_or(30).clamp(1, 300));
+        let names = req.env.unwrap_or_default();
+        if let Some(name) = names.iter().find(|n| n.is_empty() || n.contains('=')) {
+            return Err(TmuxError::InvalidArgument(format!(
+                "Invalid variable name {name:?} in env"
+            )));
+        }
+
+        let pane = match req.target.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+            Some(target) => Some(self.resolve_pane_target(target).await?),
+            None => self.current_pane_id.clone(),
+        };
+        let mut dir = None;
+        let mut env = Vec::new();
+        if let Some(pane) = &pane {
+            let info = self
+                .run_tmux(&["display-message", "-t", pane, "-p", "#{pane_current_path}\t#{pane_pid}"])
+                .await?;
+            let (path, pid) = info.trim_end_matches('\n').split_once('\t').unwrap_or_default();
+            dir = Some(path.to_string()).filter(|p| !p.is_empty());
+            if !names.is_empty() {
+                env = pid
+                    .parse()
+                    .ok()
+                    .and_then(procfs::environ)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|var| var.split_once('=').is_some_and(|(name, _)| names.iter().any(|n| n == name)))
+                    .collect();
+            }
+        }
+        let dir = req.start_directory.as_deref().or(dir.as_deref());
+        let (pane_id, _) = self.spawn_wrapped("exec", dir, &env, command).await?;
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        let channel = jobs::channel(&pane_id);
+        let status = loop {
+            let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+            let slice = left.min(Duration::from_secs(2));
+            let _ = tokio::time::timeout(slice, self.run_tmux(&["wait-for", &channel])).await;
+            let fields = self
+                .run_tmux(&["display-message", "-t", &pane_id, "-p", jobs::STATUS_FORMAT])
+                .await?;
+            let f: Vec<&str> = fields.trim_end_matches('\n').split('\t').collect();
+            let [exit, dead, dead_status, dead_signal] = f[..] else {
+                return Err(TmuxError::Tmux(format!("Unexpected pane info: {fields:?}")));
+            };
+            let status = jobs::Status::parse(exit, dead, dead_status, dead_signal);
+            if status != jobs::Status::Running || tokio::time::Instant::now() >= deadline {
+                break status;
+            }
+        };
+
+        let captured = self
+            .run_tmux(&["capture-pane", "-p", "-J", "-S", "-", "-E", "-", "-t", &pane_id])
+            .await;
+        let _ = self.run_tmux(&["kill-window", "-t", &pane_id]).await;
+        let captured = captured?;
+        let lines = jobs::output_lines(&captured);
+
+        let mut out = if status == jobs::Status::Running {
+            format!("[still running after {}s; killed]", timeout.as_secs())
+        } else {
+            format!("[{status}]")
+        };
+        if lines.is_empty() {
+            out.insert_str(out.len() - 1, "; no output");
+        }
+        for line in lines {
+            out.push('\n');
+            out.push_str(line);
+        }
+        Ok(out)
+    }
+
+    #[tool(
+        description = "Run the same shell command in several panes at once and gather each pane's output and exit status, e.g. \"uptime\" in every SSH pane of a window. Target a whole window, a list of panes, or the panes a find_panes filter matches. The command is typed at each pane's prompt as a single line run by sh -c, followed by a short printf that reports its exit status, and each pane is waited on separately until it finishes or the timeout passes. Skips the pane this server runs in, and panes showing a password or confirmation prompt unless forced."
+    )]
+    async fn broadcast_command(
+        &self,
+        Parameters(req): Parameters<BroadcastCommandRequest>,
+    ) -> Result<String, TmuxError> {
+        let command = req.command.trim();
+        if command.is_empty() {
+            return Err(TmuxError::InvalidArgument("command is empty".into()));
+        }
+        if command.contains(['\n', '\r']) {
+            return Err(TmuxError::InvalidArgument(
+                "command must be a single line; join several commands with ; or &&".into(),
+            ));
+        }
+        let timeout = Duration::from_secs(req.timeout_seconds.unwrap_or(30).clamp(1, 300));
+        let max_lines = req.max_lines.unwrap_or(50).max(1);
+        let force = req.force.unwrap_or(false);
+
+        let address_format = "#{pane_id}\t#{session_name}:#{window_index}.#{pane_index}";
+        let mut targets: Vec<(String, String)> = Vec::new();
+        match (&req.window, &req.panes, &req.filter) {
+            (Some(window), None, None) => {
+                let window = self.resolve_window_target(Some(window.trim())).await?;
+                let panes = self.run_tmux(&["list-panes", "-t", &window, "-F", address_format]).await?;
+                for line in panes.lines() {
+                    if let Some((id, address)) = line.split_once('\t') {
+                        targets.push((id.to_string(), address.to_string()));
+                    }
+                }
+            }
+            (None, Some(panes), None) => {
+                for pane in panes {
+                    let target = self.resolve_pane_target(pane.trim()).await?;
+                    let info = self
+                        .run_tmux(&["display-message", "-t", &target, "-p", address_format])
+                        .await?;
+                    if let Some((id, address)) = info.trim_end().split_once('\t')
+                        && !targets.iter().any(|(known, _)| known == id)
+                    {
+                        targets.push((id.to_string(), address.to_string()));
+                    }
+                }
+            }
+            (None, None, Some(filter)) => {
+                for f in self.matching_panes(filter).await? {
+                    targets.push((f[0].clone(), f[2].clone()));
+                }
+            }
+            _ => {
+                return Err(TmuxError::InvalidArgument(
+                    "Give exactly one of window, panes or filter".into(),
+                ));
+            }
+        }
+        if targets.is_empty() {
+            return Err(TmuxError::InvalidArgument("No panes to run the command in".into()));
+        }
+
+        // A marker unique to this call, so output of an earlier broadcast
+        // can't be mistaken for this one's.
+        let token = format!("mcp-done-{}", unique_suffix());
+        let line = broadcast_line(command, &token);
+
+        let mut runs: Vec<BroadcastRun> = targets
+            .into_iter()
+            .map(|(pane_id, address)| BroadcastRun {
+                status: if self.current_pane_id.as_deref() == Some(pane_id.as_str()) {
+                    Err("not sent: this server runs in it".to_string())
+                } else {
+                    Ok(jobs::Status::Running)
+                },
+                pane_id,
+                address,
+                start: 0,
+                output: Vec::new(),
+            })
+            .collect();
+        for run in runs.iter_mut().filter(|r| r.status.is_ok()) {
+            let position = self
+                .run_tmux(&["display-message", "-t", &run.pane_id, "-p", "#{history_size}\t#{cursor_y}"])
+                .await?;
+            let (history_size, cursor_y) = position.trim_end().split_once('\t').unwrap_or_default();
+            run.start = history_size.parse::<u64>().unwrap_or(0) + cursor_y.parse::<u64>().unwrap_or(0);
+            if let Err(e) = self.send_line(&run.pane_id, &line, force).await {
+                run.status = Err(format!("not sent: {}", e.message()));
+            }
+        }
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            for run in runs.iter_mut().filter(|r| r.status == Ok(jobs::Status::Running)) {
+                let pane_id = run.pane_id.as_str();
+                let history_size = self.run_tmux(&["display-message", "-t", pane_id, "-p", "#{history_size}"]).await;
+                let captured = match history_size {
+                    Ok(history_size) => {
+                        let from = (run.start as i64 - history_size.trim().parse::<i64>().unwrap_or(0)).to_string();
+                        self.run_tmux(&["capture-pane", "-p", "-J", "-t", pane_id, "-S", &from, "-E", "-"])
+                            .await
+                    }
+                    Err(e) => Err(e),
+                };
+                let captured = match captured {
+                    Ok(captured) => captured,
+                    Err(e) => {
+                        run.status = Err(format!("lost: {}", e.message()));
+                        continue;
+                    }
+                };
+                // The first line is the prompt with the typed command.
+                let lines: Vec<&str> = captured.lines().skip(1).map(str::trim_end).collect();
+                let end = match find_done_marker(&lines, &token) {
+                    Some((end, code)) => {
+                        run.status = Ok(jobs::Status::Exited(code));
+                        end
+                    }
+                    None => lines.len(),
+                };
+                let mut lines = &lines[..end];
+                while let [rest @ .., last] = lines
+                    && last.is_empty()
+                {
+                    lines = rest;
+                }
+                run.output = lines.iter().map(|l| l.to_string()).collect();
+            }
+            let running = runs.iter().any(|r| r.status == Ok(jobs::Status::Running));
+            if !running || tokio::time::Instant::now() >= deadline {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+
+        let count = |f: &dyn Fn(&Result<jobs::Status, String>) -> bool| runs.iter().filter(|r| f(&r.status)).count();
+        let summary = [
+            (count(&|s| *s == Ok(jobs::Status::Exited(0))), "succeeded"),
+            (count(&|s| matches!(s, Ok(jobs::Status::Exited(c)) if *c != 0)), "failed"),
+            (count(&|s| *s == Ok(jobs::Status::Running)), "still running"),
+            (count(&|s| s.is_err()), "not run"),
+        ]
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, label)| format!("{n} {label}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+        let mut out = format!(
+            "[{} pane{}: {summary}]\n",
+            runs.len(),
+            if runs.len() == 1 { "" } else { "s" }
+        );
+        for BroadcastRun { pane_id, address, status, output, .. } in &runs {
+            let mut note = match status {
+                Ok(jobs::Status::Running) => format!("still running after {}s", timeout.as_secs()),
+                Ok(status) => status.to_string(),
+                Err(reason) => reason.clone(),
+            };
+            let shown = &output[output.len().saturating_sub(max_lines)..];
+            if shown.len() < output.len() {
+                note.push_str(&format!("; last {} of {} lines", shown.len(), output.len()));
+            }
+            out.push_str(&format!("=== {pane_id} {address} ({note}) ===\n"));
+            for line in shown {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+        Ok(out)
+    }
+
+    #[tool(
+        description = "Paste text into a pane as if the user pasted it, e.g. a multi-line script into a shell or code into an editor. Faster than send_keys for long text, and indentation survives because programs that support bracketed paste don't auto-indent it. The text goes through a tmux buffer that is deleted afterwards. Refuses panes showing a password or confirmation prompt unless forced."
+    )]
+    async fn paste_text(&self, Parameters(req): Parameters<PasteTextRequest>) -> Result<String, TmuxError> {
+        if req.text.is_empty() {
+            return Err(TmuxError::InvalidArgument("text is empty".into()));
+        }
+        let target = self.resolve_pane_target(req.target.trim()).await?;
+        self.paste(&target, req.text.as_bytes(), req.force.unwrap_or(false)).await?;
+        let mut out = format!("Pasted {} into {target}", describe_text(&req.text));
+        if req.enter.unwrap_or(false) {
+            self.run_tmux(&["send-keys", "-t", &target, "Enter"]).await?;
+            out.push_str(" and pressed Enter");
+        }
+        Ok(out)
+    }
+
+    #[tool(
+        description = "Paste the contents of a local text file into a pane, like paste_text, e.g. a script into a REPL. Refuses binary files and files over 1 MiB."
+    )]
+    async fn paste_file(&self, Parameters(req): Parameters<PasteFileRequest>) -> Result<String, TmuxError> {
+        let path = expand_home(req.path.trim());
+        let size = std::fs::metadata(&path)
+            .map_err(|e| TmuxError::InvalidArgument(format!("Can't read {path}: {e}")))?
+            .len();
+        if size > 1 << 20 {
+            return Err(TmuxError::InvalidArgument(format!(
+                "{path} is {}, more than the 1M that can be pasted",
+                format_kib(size / 1024)
+            )));
+        }
+        let content = std::fs::read(&path)
+            .map_err(|e| TmuxError::InvalidArgument(format!("Can't read {path}: {e}")))?;
+        let Some(text) = String::from_utf8(content).ok().filter(|t| !t.contains('\0')) else {
+            return Err(TmuxError::InvalidArgument(format!("{path} is not a text file")));
+        };
+        if text.is_empty() {
+            return Err(TmuxError::InvalidArgument(format!("{path} is empty")));
+        }
+
+        let target = self.resolve_pane_target(req.target.trim()).await?;
+        self.paste(&target, text.as_bytes(), req.force.unwrap_or(false)).await?;
+        let mut out = format!("Pasted {path} ({}) into {target}", describe_text(&text));
+        if req.enter.unwrap_or(false) {
+            self.run_tmux(&["send-keys", "-t", &target, "Enter"]).await?;
+            out.push_str(" and pressed Enter");
+        }
+        Ok(out)
+    }
+
+    #[tool(
+        description = "List tmux's paste buffers, most recent first, with their size, age and first characters. Text the user copies in copy mode lands in a buffer, so this is how to see what they yanked."
+    )]
+    async fn list_buffers(&self) -> Result<String, TmuxError> {
+        let output = self
+            .run_tmux(&[
+                "list-buffers", "-F",
+                "#{buffer_name}\t#{buffer_size}\t#{buffer_created}\t#{buffer_sample}",
+            ])
+            .await?;
+        let now = SystemTime::now();
+        let rows: Vec<Vec<String>> = output
+            .lines()
+            .filter_map(|line| {
+                let f: Vec<&str> = line.splitn(4, '\t').collect();
+                let [name, size, created, sample] = f[..] else {
+                    return None;
+                };
+                let age = created
+                    .parse()
+                    .ok()
+                    .filter(|&secs| secs > 0)
+                    .and_then(|secs| now.duration_since(SystemTime::UNIX_EPOCH + Duration::from_secs(secs)).ok())
+                    .map(|d| format!("{} ago", format_duration(d)))
+                    .unwrap_or_default();
+                Some(vec![
+                    name.to_string(),
+                    format!("{size} bytes"),
+                    age,
+                    format!("\"{}\"", truncate(sample, 50)),
+                ])
+            })
+            .collect();
+        if rows.is_empty() {
+            return Ok("No buffers".into());
+        }
+        Ok(align_columns(&rows).join("\n"))
+    }
+
+    #[tool(
+        description = "Read the contents of a tmux paste buffer, by default the most recent one, e.g. text the user just copied in copy mode."
+    )]
+    async fn get_buffer(&self, Parameters(req): Parameters<GetBufferRequest>) -> Result<String, TmuxError> {
+        match req.name.as_deref().map(str::trim).filter(|n| !n.is_empty()) {
+            Some(name) => self.run_tmux(&["show-buffer", "-b", name]).await,
+            None => self.run_tmux(&["show-buffer"]).await,
+        }
+    }
 
-        let format = "#{session_name}\t#{window_index}\t#{window_name}\t#{window_panes}\t#{?window_active,active,}";
+    #[tool(
+        description = "Put text in a tmux paste buffer for the user to paste with their paste key, optionally copying it to the system clipboard too. Replaces a buffer of the same name."
+    )]
+    async fn set_buffer(&self, Parameters(req): Parameters<SetBufferRequest>) -> Result<String, TmuxError> {
+        let name = req.name.as_deref().map(str::trim).filter(|n| !n.is_empty());
+        let clipboard = req.clipboard.unwrap_or(false);
+        let mut args = vec!["load-buffer"];
+        if let Some(name) = name {
+            args.extend(["-b", name]);
+        }
+        if clipboard {
+            args.push("-w");
+        }
+        args.push("-");
+        self.tmux.run_with_input(&args, req.content.as_bytes()).await?;
+
+        let name = match name {
+            Some(name) => name.to_string(),
+            // The buffer just loaded is the most recent one.
+            None => self
+                .run_tmux(&["list-buffers", "-F", "#{buffer_name}"])
+                .await?
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        };
+        let mut out = format!("Set buffer {name} ({})", describe_text(&req.content));
+        if clipboard {
+            out.push_str(" and copied it to the clipboard");
+        }
+        Ok(out)
+    }
+
+    #[tool(description = "Delete a tmux paste buffer.")]
+    async fn delete_buffer(&self, Parameters(req): Parameters<DeleteBufferRequest>) -> Result<String, TmuxError> {
+        let name = req.name.trim();
+        self.run_tmux(&["delete-buffer", "-b", name]).await?;
+        Ok(format!("Deleted buffer {name}"))
+    }
+
+    #[tool(
+        description = "Read what the user is pointing at: the text selected in copy mode in their pane, and the contents of the pane they marked (select-pane -m, prefix m by default). Use when the user asks about \"this\" or \"the selection\"."
+    )]
+    async fn get_user_selection(
+        &self,
+        Parameters(req): Parameters<GetUserSelectionRequest>,
+    ) -> Result<String, TmuxError> {
+        const ADDRESS: &str = "#{pane_id} (#{session_name}:#{window_index}.#{pane_index})";
+        let pane = match req.target.as_deref() {
+            Some(t) => Some(self.resolve_pane_target(t).await?),
+            None => {
+                let clients = self
+                    .run_tmux(&[
+                        "list-clients",
+                        "-F",
+                        "#{client_control_mode}\t#{client_activity}\t#{pane_id}",
+                    ])
+                    .await?;
+                // A control-mode client, such as this server's own, is no user.
+                clients
+                    .lines()
+                    .filter_map(|l| l.strip_prefix("0\t")?.split_once('\t'))
+                    .max_by_key(|(activity, _)| activity.parse::<u64>().unwrap_or(0))
+                    .map(|(_, pane)| pane.to_string())
+            }
+        };
+
+        let mut found = false;
+        let mut sections = Vec::new();
+        match pane {
+            None => sections.push("No selection: no client is attached".to_string()),
+            Some(pane) => {
+                let address = self.run_tmux(&["display-message", "-t", &pane, "-p", ADDRESS]).await?;
+                let address = address.trim();
+                let state = self
+                    .run_tmux(&["display-message", "-t", &pane, "-p", selection::FORMAT])
+                    .await?;
+                match selection::Selection::parse(&state) {
+                    Some(sel) => {
+                        let (start, end) = sel.capture_range();
+                        let captured = self
+                            .run_tmux(&["capture-pane", "-p", "-t", &pane, "-S", &start, "-E", &end])
+                            .await?;
+                        found = true;
+                        sections.push(format!("=== Selection in {address} ===\n{}", sel.extract(&captured)));
+                    }
+                    None => sections.push(format!("No selection in {address}")),
+                }
+            }
+        }
+
+        // {marked} quietly resolves to nothing when no pane is marked.
+        let marked = self
+            .run_tmux(&["display-message", "-t", "{marked}", "-p", &format!("#{{pane_marked}}\t{ADDRESS}")])
+            .await
+            .unwrap_or_default();
+        match marked.trim().strip_prefix("1\t") {
+            Some(address) => {
+                let pane_id = address.split(' ').next().unwrap_or_default();
+                let contents = self.capture_pane(pane_id, 0, false).await?;
+                found = true;
+                sections.push(format!("=== Marked pane {address} ===\n{}", contents.trim_end()));
+            }
+            None => sections.push("No marked pane".to_string()),
+        }
+        if !found {
+            sections.push(
+                "Ask the user to select the text in copy mode, or to mark the pane with prefix m.".to_string(),
+            );
+        }
+        Ok(sections.join("\n\n"))
+    }
+
+    #[tool(
+        description = "List the last commands run in a pane with their output, split into one block per command. When the pane is tracked with track_commands and its shell emits OSC 133 semantic prompt marks, blocks come from those marks and include exit codes and durations. Otherwise the scrollback is split at lines matching a prompt pattern."
+    )]
+    async fn get_command_history(
+        &self,
+        Parameters(req): Parameters<GetCommandHistoryRequest>,
+    ) -> Result<String, TmuxError> {
+        let count = req.count.unwrap_or(5) as usize;
+        let max_output_lines = req.max_output_lines.unwrap_or(50) as usize;
+
+        let target = self.resolve_pane_target(req.target.trim()).await?;
+        let pane_id = self
+            .run_tmux(&["display-message", "-t", &target, "-p", "#{pane_id}"])
+            .await?
+            .trim()
+            .to_string();
+
+        // Some(None) when tracked but no marks have been seen.
+        let tracked = self
+            .command_trackers
+            .lock()
+            .unwrap()
+            .get(&pane_id)
+            .map(|t| t.recorder.marked.then(|| t.recorder.blocks()));
+        let (blocks, header) = match tracked {
+            Some(Some(blocks)) => (blocks, "from shell integration marks".to_string()),
+            _ => {
+                let prompt = compile_pattern(
+                    req.prompt_pattern.as_deref().unwrap_or(blocks::DEFAULT_PROMPT),
+                    true,
+                )?;
+                let capture = self
+                    .run_tmux(&["capture-pane", "-p", "-J", "-t", &target, "-S", "-"])
+                    .await?;
+                let lines: Vec<&str> = capture.lines().collect();
+                let mut how = format!("split at lines matching {}", prompt.as_str());
+                if tracked.is_some() {
+                    how.push_str("; track_commands has seen no shell integration marks yet");
+                }
+                how.push_str("; exit codes and durations need shell integration");
+                (blocks::split_at_prompts(&lines, &prompt), how)
+            }
+        };
+
+        if blocks.is_empty() {
+            return Ok(format!("[No commands found in {pane_id}, {header}]"));
+        }
+        let skip = blocks.len().saturating_sub(count);
+        let shown = &blocks[skip..];
+        let mut out = vec![format!(
+            "[Last {} of {} commands in {pane_id}, {header}]",
+            shown.len(),
+            blocks.len()
+        )];
+        for block in shown {
+            out.push(String::new());
+            let status = match (block.duration, block.exit_code, block.started) {
+                (Some(d), Some(code), _) => format!("  [exit {code}, {}]", format_duration(d)),
+                (Some(d), None, _) => format!("  [{}]", format_duration(d)),
+                (None, _, Some(started)) => format!(
+                    "  [running for {}]",
+                    format_duration(started.elapsed().unwrap_or_default())
+                ),
+                (None, _, None) => String::new(),
+            };
+            out.push(format!("$ {}{status}", block.command));
+            let omitted = block.output.len().saturating_sub(max_output_lines);
+            if omitted > 0 || block.truncated {
+                out.push("[... earlier output omitted]".to_string());
+            }
+            out.extend(block.output[omitted..].iter().cloned());
+        }
+        Ok(out.join("\n"))
+    }
+
+    #[tool(
+        description = "Start recording a pane's raw output (via pipe-pane) so get_command_history can use its shell's OSC 133 semantic prompt marks to report each command's exit code and duration. Only commands run after tracking starts are recorded, and the shell needs prompt integration enabled (e.g. in fish, or via the terminal's integration scripts for bash and zsh). A pane can have only one pipe, so this fails if it is already piped."
+    )]
+    async fn track_commands(
+        &self,
+        Parameters(req): Parameters<TrackCommandsRequest>,
+    ) -> Result<String, TmuxError> {
+        let target = self.resolve_pane_target(req.target.trim()).await?;
+        let info = self
+            .run_tmux(&["display-message", "-t", &target, "-p", "#{pane_id}\t#{pane_pipe}"])
+            .await?;
+        let (pane_id, piped) = info.trim_end().split_once('\t').unwrap_or((info.trim(), "0"));
+        let pane_id = pane_id.to_string();
+
+        if req.stop.unwrap_or(false) {
+            let Some(tracker) = self.command_trackers.lock().unwrap().remove(&pane_id) else {
+                return Err(TmuxError::InvalidArgument(format!(
+                    "{pane_id} is not being tracked"
+                )));
+            };
+            tracker.stop();
+            self.run_tmux(&["pipe-pane", "-t", &target]).await?;
+            return Ok(format!("Stopped tracking commands in {pane_id}"));
+        }
+        if self.command_trackers.lock().unwrap().contains_key(&pane_id) {
+            return Ok(format!("Already tracking commands in {pane_id}"));
+        }
+        if piped == "1" {
+            return Err(TmuxError::InvalidArgument(format!(
+                "{pane_id} is already piped to another command (pipe-pane), and a pane can only have one pipe"
+            )));
+        }
+
+        // The pane's output goes through a FIFO, read as it arrives so each
+        // mark gets a timestamp. It lives in a directory only we can reach.
+        let dir = private_temp_dir("tmux-mcp-")
+            .map_err(|e| TmuxError::Tmux(format!("Failed to create a directory for a FIFO: {e}")))?;
+        let fifo = CommandTracker::fifo(dir.path());
+        nix::unistd::mkfifo(&fifo, nix::sys::stat::Mode::S_IRUSR | nix::sys::stat::Mode::S_IWUSR)
+            .map_err(|e| TmuxError::Tmux(format!("Failed to create FIFO {}: {e}", fifo.display())))?;
+
+        let trackers = self.command_trackers.clone();
+        let (id, path) = (pane_id.clone(), fifo.clone());
+        let reader = tokio::spawn(async move {
+            use tokio::io::AsyncReadExt;
+            // Opened read-only, so the read ends once the pipe's writer is
+            // gone: the pane died or its pipe-pane was stopped. The open
+            // waits for that writer.
+            let opening = path.clone();
+            let receiver = match tokio::task::spawn_blocking(move || std::fs::File::open(opening)).await {
+                Ok(Ok(file)) => tokio::net::unix::pipe::Receiver::from_file(file).ok(),
+                _ => None,
+            };
+            if let Some(mut receiver) = receiver {
+                let mut buf = vec![0u8; 8192];
+                while let Ok(n @ 1..) = receiver.read(&mut buf).await {
+                    let mut trackers = trackers.lock().unwrap();
+                    let Some(tracker) = trackers.get_mut(&id) else {
+                        return;
+                    };
+                    tracker.recorder.feed(&buf[..n], SystemTime::now());
+                }
+            }
+            // Forget the pane, unless it has been tracked again since.
+            let mut trackers = trackers.lock().unwrap();
+            if trackers.get(&id).is_some_and(|t| CommandTracker::fifo(t.dir.path()) == path) {
+                trackers.remove(&id);
+            }
+        });
+        self.command_trackers.lock().unwrap().insert(
+            pane_id.clone(),
+            CommandTracker {
+                recorder: Default::default(),
+                reader: reader.abort_handle(),
+                dir,
+            },
+        );
+
+        let command = format!("cat > {}", snapshot::shell_quote(&fifo.to_string_lossy()));
+        if let Err(e) = self
+            .run_tmux(&["pipe-pane", "-o", "-t", &target, &command])
+            .await
+        {
+            if let Some(tracker) = self.command_trackers.lock().unwrap().remove(&pane_id) {
+                tracker.stop();
+            }
+            return Err(e);
+        }
+        Ok(format!(
+            "Tracking commands in {pane_id}. Commands run from now on are listed by get_command_history with exit codes and durations, provided the shell emits OSC 133 prompt marks."
+        ))
+    }
+
+    #[tool(
+        description = "Find panes across all sessions by running command, working directory, title, window name or session name. Returns matching pane IDs and session:window.pane addresses. All given filters must match."
+    )]
+    async fn find_panes(
+        &self,
+        Parameters(req): Parameters<FindPanesRequest>,
+    ) -> Result<String, TmuxError> {
+        let rows: Vec<Vec<String>> = self
+            .matching_panes(&req)
+            .await?
+            .into_iter()
+            .map(|f| {
+                let mut cols = vec![
+                    f[0].clone(),
+                    f[2].clone(),
+                    truncate(&f[3], MAX_NAME_LEN),
+                    truncate(&f[4], MAX_CMD_LEN),
+                    f[5].clone(),
+                ];
+                if req.title.is_some() {
+                    cols.push(format!("\"{}\"", f[6]));
+                }
+                if self.current_pane_id.as_deref() == Some(f[0].as_str()) {
+                    cols.push("<-- current".to_string());
+                }
+                cols
+            })
+            .collect();
+
+        if rows.is_empty() {
+            return Ok("No matching panes".into());
+        }
+        Ok(align_columns(&rows).join("\n"))
+    }
+}
+
+impl ServerHandler for TmuxMcp {
+    fn get_info(&self) -> ServerInfo {
+        ServerInfo {
+            instructions: Some(
+                "MCP server for interacting with tmux sessions, windows, and panes. \
+                 Use list_sessions to discover sessions, list_windows to see windows, \
+                 get_pane_contents to read a specific pane, read_new_output to follow a pane's new output, \
+                 get_command_history to read the last commands and their output, \
+                 get_pane_state to check whether a pane is idle before typing into it, \
+                 get_pane_processes to see a pane's processes and listening ports, \
+                 signal_pane to interrupt or stop a pane's foreground job, \
+                 respawn_pane to restart a pane whose program exited, \
+                 exec to run a short command in a throwaway window and get its output, \
+                 start_job, list_jobs, job_status and job_output to run long commands in the background, \
+                 send_keys to type into a pane (it refuses password and confirmation prompts unless forced), \
+                 broadcast_command to run one command in several panes and gather the results, \
+                 paste_text and paste_file to paste long or multi-line text into a pane, \
+                 list_buffers, get_buffer, set_buffer and delete_buffer to share text with the user through tmux's paste buffers, \
+                 get_user_selection to read the text the user selected in copy mode or the pane they marked, \
+                 get_window_contents to read all panes in a window, \
+                 get_window_layout to see where panes sit, render_pane to see a pane as an image, and find_panes to locate panes by running command, directory or title."
+                    .into(),
+            ),
+            capabilities: ServerCapabilities::builder().enable_tools().build(),
+            ..Default::default()
+        }
+    }
+
+    /// Like the `#[tool_handler]` expansion, but gives up on the call when the
+    /// client cancels the request. Dropping the tool future kills any tmux
+    /// command still running on its behalf.
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let ct = context.ct.clone();
+        let name = request.name.clone();
+        let call = self
+            .tool_router
+            .call(ToolCallContext::new(self, request, context));
+        tokio::select! {
+            result = call => result,
+            _ = ct.cancelled() => {
+                tracing::debug!("tool call {name} cancelled");
+                Err::<(), _>(TmuxError::Cancelled(format!("{name} was cancelled by the client")))
+                    .into_call_tool_result()
+            }
+        }
+    }
+
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParams>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, McpError> {
+        Ok(ListToolsResult {
+            tools: self.tool_router.list_all(),
+            meta: None,
+            next_cursor: None,
+        })
+    }
+
+    fn get_tool(&self, name: &str) -> Option<Tool> {
+        self.tool_router.get(name).cloned()
+    }
+}
+
+const USAGE: &str = "Usage: tmux-mcp [-L NAME] [--control-mode] [--timeout [CMD=]SECS]...
+                [--templates DIR] [--snapshots DIR]
+                [--http ADDR | --unix PATH] [--token TOKEN]
+
+Serves MCP over stdio by default.
+
+Options:
+  -L NAME         Use the tmux server with this socket name (as tmux -L)
+  --control-mode  Send commands over one persistent tmux control-mode client
+                  instead of running tmux once per command. The client
+                  attaches to a session, so tmux counts that session as
+                  attached (session_attached, list-sessions)
+  --timeout SECS  Kill tmux commands that take longer than SECS (default 10)
+  --timeout CMD=SECS
+                  Override the timeout for one tmux command, e.g.
+                  --timeout capture-pane=30
+  --templates DIR Read workspace templates from DIR
+                  (default ~/.config/tmux-mcp/templates)
+  --snapshots DIR Keep session snapshots in DIR
+                  (default ~/.local/state/tmux-mcp/snapshots)
+  --http ADDR     Serve streamable HTTP at http://ADDR/mcp (e.g. 127.0.0.1:8808)
+  --unix PATH     Serve streamable HTTP on a Unix socket
+  --token TOKEN   Require \"Authorization: Bearer TOKEN\" on HTTP requests
+                  (also read from $TMUX_MCP_TOKEN)";
+
+struct Options {
+    /// None when serving over stdio.
+    http: Option<HttpOptions>,
+    socket_name: Option<String>,
+    control_mode: bool,
+    timeouts: Timeouts,
+    templates_dir: Option<PathBuf>,
+    snapshots_dir: Option<PathBuf>,
+}
+
+fn parse_timeout(value: &str, timeouts: &mut Timeouts) -> Result<()> {
+    let (command, secs) = match value.split_once('=') {
+        Some((command, secs)) => (Some(command), secs),
+        None => (None, value),
+    };
+    let duration = secs
+        .parse::<f64>()
+        .ok()
+        .and_then(|s| Duration::try_from_secs_f64(s).ok())
+        .filter(|d| !d.is_zero())
+        .ok_or_else(|| anyhow::anyhow!("invalid --timeout \"{value}\": expected seconds"))?;
+    match command {
+        Some(command) => {
+            timeouts.per_command.insert(command.to_string(), duration);
+        }
+        None => timeouts.default = duration,
+    }
+    Ok(())
+}
+
+fn parse_args() -> Result<Options> {
+    let mut listen = None;
+    let mut token = std::env::var("TMUX_MCP_TOKEN").ok().filter(|t| !t.is_empty());
+    let mut socket_name = None;
+    let mut control_mode = false;
+    let mut timeouts = Timeouts::default();
+    let mut templates_dir = None;
+    let mut snapshots_dir = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| anyhow::anyhow!("{name} requires a value\n\n{USAGE}"))
+        };
+        match arg.as_str() {
+            "-L" => socket_name = Some(value("-L")?),
+            "--control-mode" => control_mode = true,
+            "--timeout" => parse_timeout(&value("--timeout")?, &mut timeouts)?,
+            "--templates" => templates_dir = Some(value("--templates")?.into()),
+            "--snapshots" => snapshots_dir = Some(value("--snapshots")?.into()),
+            "--http" => {
+                let addr = value("--http")?;
+                listen = Some(Listen::Tcp(addr.parse().map_err(|e| {
+                    anyhow::anyhow!("invalid --http address \"{addr}\": {e}")
+                })?));
+            }
+            "--unix" => listen = Some(Listen::Unix(value("--unix")?.into())),
+            "--token" => token = Some(value("--token")?),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            other => bail!("unknown argument \"{other}\"\n\n{USAGE}"),
+        }
+    }
+
+    if let Some(Listen::Tcp(addr)) = &listen
+        && !addr.ip().is_loopback()
+        && token.is_none()
+    {
+        bail!("refusing to listen on non-loopback address {addr} without --token");
+    }
+    Ok(Options {
+        http: listen.map(|listen| HttpOptions { listen, token }),
+        socket_name,
+        control_mode,
+        timeouts,
+        templates_dir,
+        snapshots_dir,
+    })
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let opts = parse_args()?;
+
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env().add_directive(tracing::Level::DEBUG.into()))
+        .with_writer(std::io::stderr)
+        .with_ansi(false)
+        .init();
+
+    tracing::info!("Starting tmux-mcp server");
+
+    let tmux: Arc<dyn TmuxBackend> = if opts.control_mode {
+        Arc::new(ControlMode::new(opts.socket_name, opts.timeouts))
+    } else {
+        Arc::new(Subprocess::new(opts.socket_name, opts.timeouts))
+    };
+    let current_pane_id = std::env::var("TMUX_PANE").ok();
+    let mut mcp = TmuxMcp::new(tmux, current_pane_id);
+    if let Some(dir) = opts.templates_dir {
+        mcp = mcp.with_templates_dir(dir);
+    }
+    if let Some(dir) = opts.snapshots_dir {
+        mcp = mcp.with_snapshots_dir(dir);
+    }
+
+    if let Some(http) = opts.http {
+        return http::serve(http, mcp).await;
+    }
+
+    let service = mcp.serve(stdio()).await.inspect_err(|e| {
+        tracing::error!("serving error: {:?}", e);
+    })?;
+
+    service.waiting().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rmcp::handler::server::tool::IntoCallToolResult;
+
+    use super::*;
+    use crate::backend::fake::{FakeClient, FakeTmux};
+
+    /// Two sessions: "work" (with a client attached, windows "editor" with two panes and
+    /// "server") and "notes". The server runs in work:0.0.
+    fn fixture() -> (TmuxMcp, Arc<FakeTmux>) {
+        let fake = Arc::new(FakeTmux::new());
+        let editor = fake.new_session("work", "editor");
+        let shell = fake.split_window("work", 0);
+        let server = fake.new_window("work", "server");
+        fake.new_session("notes", "todo");
+        fake.with_state(|s| {
+            s.sessions[0].attached = true;
+            s.clients.push(FakeClient {
+                pane_id: editor.clone(),
+                activity: 0,
+                control_mode: false,
+            });
+        });
+        fake.with_pane(&editor, |p| p.command = "nvim".into());
+        fake.with_pane(&shell, |p| {
+            p.history = vec!["old 1".into(), "old 2".into()];
+            p.screen = vec!["$ ls".into(), "Cargo.toml".into()];
+        });
+        fake.with_pane(&server, |p| {
+            p.command = "psql".into();
+            p.path = "/home/user/src/api".into();
+        });
+        let mcp = TmuxMcp::new(fake.clone(), Some(editor));
+        (mcp, fake)
+    }
+
+    #[tokio::test]
+    async fn resolves_pane_targets_relative_to_current_pane() {
+        let (mcp, _) = fixture();
+        assert_eq!(mcp.resolve_pane_target("1").await.unwrap(), "work:0.1");
+        assert_eq!(mcp.resolve_pane_target("1.0").await.unwrap(), "work:1.0");
+        assert_eq!(mcp.resolve_pane_target("notes:0.0").await.unwrap(), "notes:0.0");
+        assert_eq!(mcp.resolve_pane_target("%3").await.unwrap(), "%3");
+        let err = mcp.resolve_pane_target("notes:0").await.unwrap_err();
+        assert_eq!(err.kind(), "invalid_argument");
+    }
+
+    #[tokio::test]
+    async fn resolves_window_targets_relative_to_current_pane() {
+        let (mcp, _) = fixture();
+        assert_eq!(mcp.resolve_window_target(None).await.unwrap(), "work:0");
+        assert_eq!(mcp.resolve_window_target(Some("1")).await.unwrap(), "work:1");
+        assert_eq!(mcp.resolve_window_target(Some("notes:0")).await.unwrap(), "notes:0");
+    }
+
+    #[tokio::test]
+    async fn relative_targets_need_tmux_pane() {
+        let (_, fake) = fixture();
+        let mcp = TmuxMcp::new(fake, None);
+        assert_eq!(
+            mcp.resolve_pane_target("1").await.unwrap_err(),
+            TmuxError::NotInTmux("Not running inside tmux".into())
+        );
+        assert_eq!(
+            mcp.resolve_window_target(None).await.unwrap_err(),
+            TmuxError::NotInTmux("No target specified and not running inside tmux".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn list_sessions_aligns_columns() {
+        let (mcp, fake) = fixture();
+        // A control-mode client, like --control-mode's own, doesn't count.
+        fake.with_state(|s| {
+            s.sessions[1].attached = true;
+            s.clients.push(FakeClient {
+                pane_id: "%3".into(),
+                activity: 0,
+                control_mode: true,
+            });
+        });
+        let out = mcp
+            .list_sessions(Parameters(ListSessionsRequest { verbose: None }))
+            .await
+            .unwrap();
+        assert_eq!(
+            out,
+            "work   (attached)  2 windows\nnotes  (detached)  1 window"
+        );
+    }
+
+    #[tokio::test]
+    async fn list_windows_verbose_marks_current_pane() {
+        let (mcp, _) = fixture();
+        let out = mcp
+            .list_windows(Parameters(ListWindowsRequest {
+                session: Some("work".into()),
+                verbose: Some(true),
+            }))
+            .await
+            .unwrap();
+        assert_eq!(
+            out,
+            "work:0:  editor  2 panes  active\n\
+             \x20 .0  80x24  nvim  (active)  <-- current\n\
+             \x20 .1  80x24  bash\n\
+             work:1:  server  1 pane\n\
+             \x20 .0  80x24  psql  (active)"
+        );
+    }
+
+    #[tokio::test]
+    async fn get_pane_contents_includes_requested_scrollback() {
+        let (mcp, _) = fixture();
+        let visible = mcp
+            .get_pane_contents(Parameters(GetPaneContentsRequest {
+                target: "1".into(),
+                scroll_back_lines: None,
+                normal_screen: None,
+                style: None,
+            }))
+            .await
+            .unwrap();
+        assert_eq!(visible, "$ ls\nCargo.toml\n");
+
+        let with_history = mcp
+            .get_pane_contents(Parameters(GetPaneContentsRequest {
+                target: "work:0.1".into(),
+                scroll_back_lines: Some(1),
+                normal_screen: None,
+                style: None,
+            }))
+            .await
+            .unwrap();
+        assert_eq!(with_history, "old 2\n$ ls\nCargo.toml\n");
+    }
+
+    #[tokio::test]
+    async fn get_pane_contents_notes_alternate_screen() {
+        let (mcp, fake) = fixture();
+        fake.with_pane("%1", |p| {
+            p.command = "vim".into();
+            p.alternate_on = true;
+            p.saved_screen = std::mem::replace(&mut p.screen, vec!["~".into(), "~".into()]);
+        });
+        let read = |normal_screen| {
+            mcp.get_pane_contents(Parameters(GetPaneContentsRequest {
+                target: "1".into(),
+                scroll_back_lines: Some(1),
+                normal_screen: Some(normal_screen),
+                style: None,
+            }))
+        };
+        assert_eq!(
+            read(false).await.unwrap(),
+            "[alternate_on=1 pane_in_mode=0 command=vim] Showing the alternate screen of a full-screen program; set normal_screen=true for the shell output underneath.\n\
+             old 2\n~\n~\n"
+        );
+        assert_eq!(
+            read(true).await.unwrap(),
+            "[alternate_on=1 pane_in_mode=0 command=vim] Showing the normal screen underneath the alternate screen.\n\
+             old 2\n$ ls\nCargo.toml\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn get_pane_contents_styles() {
+        let (mcp, fake) = fixture();
+        fake.with_pane("%1", |p| {
+            p.screen = vec!["\x1b[1;31mFAILED\x1b[0m a[0]".into()];
+        });
+        let read = |style| {
+            mcp.get_pane_contents(Parameters(GetPaneContentsRequest {
+                target: "1".into(),
+                scroll_back_lines: None,
+                normal_screen: None,
+                style: Some(style),
+            }))
+        };
+        assert_eq!(read(CaptureStyle::Plain).await.unwrap(), "FAILED a[0]\n");
+        assert_eq!(
+            read(CaptureStyle::Markup).await.unwrap(),
+            "[bold red]FAILED[/] a[0]\n"
+        );
+        assert_eq!(
+            read(CaptureStyle::Spans).await.unwrap(),
+            r#"{"lines":[[{"bold":true,"fg":"red","text":"FAILED"},{"text":" a[0]"}]],"note":null}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn read_new_output_returns_appended_lines() {
+        let (mcp, fake) = fixture();
+        fake.with_pane("%1", |p| {
+            p.history = vec!["old".into()];
+            p.screen = vec!["$ make".into(), "cc a.c".into(), "cc b.c".into(), "$ ".into()];
+        });
+        let read = || {
+            mcp.read_new_output(Parameters(ReadNewOutputRequest {
+                target: "1".into(),
+                max_lines: Some(2),
+                reset: None,
+            }))
+        };
+        assert_eq!(
+            read().await.unwrap(),
+            "[2 new lines; 1 earlier line omitted]\ncc a.c\ncc b.c\n[cursor line] $"
+        );
+        assert_eq!(read().await.unwrap(), "[No new output]\n[cursor line] $");
+
+        // Output scrolls the screen and tmux trims the oldest history lines.
+        fake.with_pane("%1", |p| {
+            p.history = vec![
+                "$ make".into(),
+                "cc a.c".into(),
+                "cc b.c".into(),
+                "$ make test".into(),
+                "ok 1".into(),
+            ];
+            p.screen = vec!["ok 2".into(), "ok 3".into(), "$ ".into()];
+        });
+        assert_eq!(
+            read().await.unwrap(),
+            "[2 new lines; 2 earlier lines omitted]\nok 2\nok 3\n[cursor line] $"
+        );
+
+        // clear-history drops output nobody has read.
+        fake.with_pane("%1", |p| {
+            p.history = vec![];
+            p.screen = vec!["done".into(), "".into()];
+        });
+        assert_eq!(
+            read().await.unwrap(),
+            "[1 new line; some output was lost because the history was cleared or overflowed since the last read]\ndone"
+        );
+    }
+
+    #[test]
+    fn replay_files_are_named_by_position() {
+        let session = |name: &str| snapshot::Session {
+            name: name.into(),
+            windows: vec![snapshot::Window {
+                index: 0,
+                name: "shell".into(),
+                layout: "b25d,80x24,0,0,0".into(),
+                active: true,
+                panes: vec![snapshot::Pane {
+                    index: 0,
+                    path: "/".into(),
+                    command: "bash".into(),
+                    argv: None,
+                    active: true,
+                    scrollback: Some(format!("{name}\n")),
+                }],
+            }],
+        };
+        // Names that would sanitize to the same file.
+        let (a, b) = (session("a.b"), session("a_b"));
+        let dir = write_replay_files(&[&a, &b]).unwrap().unwrap();
+        assert_eq!(std::fs::read_to_string(replay_file(&dir, 0, 0, 0)).unwrap(), "a.b\n");
+        assert_eq!(std::fs::read_to_string(replay_file(&dir, 1, 0, 0)).unwrap(), "a_b\n");
+        remove_replay_files(&dir, 0);
+        remove_replay_files(&dir, 1);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn broadcast_line_wraps_the_command_in_sh() {
+        assert_eq!(
+            broadcast_line("make &", "mcp-done-1"),
+            r#"sh -c 'sh -c "$1"; printf "\n%s:%s\n" mcp-done-1 "$?"' sh 'make &'"#
+        );
+        assert_eq!(
+            broadcast_line("echo 'hi'", "t"),
+            r#"sh -c 'sh -c "$1"; printf "\n%s:%s\n" t "$?"' sh 'echo '\''hi'\'''"#
+        );
+    }
+
+    #[tokio::test]
+    async fn send_keys_refuses_prompts_unless_forced() {
+        let (mcp, fake) = fixture();
+        let send = |text: &str, keys: &[&str], force| {
+            mcp.send_keys(Parameters(SendKeysRequest {
+                target: "%1".into(),
+                text: Some(text.into()),
+                keys: Some(keys.iter().map(|k| k.to_string()).collect()),
+                enter: None,
+                force: Some(force),
+            }))
+        };
+        fake.with_pane("%1", |p| {
+            p.screen = vec!["$ make deploy".into(), "Deploy to prod? [y/N] ".into()];
+        });
+        assert!(matches!(
+            send("ls", &["Enter"], false).await,
+            Err(TmuxError::PolicyDenied(m)) if m.contains("a confirmation prompt")
+        ));
+        assert_eq!(send("", &["C-c"], false).await.unwrap(), "Sent C-c to %1");
+        assert_eq!(
+            send("y", &["Enter"], true).await.unwrap(),
+            "Sent 1 character then Enter to %1"
+        );
+        let sent: Vec<String> = fake
+            .commands()
+            .into_iter()
+            .filter(|c| c.starts_with("send-keys"))
+            .collect();
+        assert_eq!(
+            sent,
+            vec!["send-keys -t %1 -- C-c", "send-keys -t %1 -l -- y", "send-keys -t %1 -- Enter"]
+        );
+
+        // Keys that look like flags can't re-target the send past the guard.
+        fake.with_pane("%1", |p| p.screen = vec!["$ ".into()]);
+        send("", &["-t", "%2", "-X"], false).await.unwrap();
+        assert_eq!(
+            fake.commands().last().unwrap(),
+            "send-keys -t %1 -- -t %2 -X"
+        );
+
+        fake.with_pane("%1", |p| p.screen = vec!["[sudo] password for me: ".into()]);
+        assert!(matches!(
+            send("ls", &[], false).await,
+            Err(TmuxError::PolicyDenied(m)) if m.contains("a password prompt")
+        ));
+    }
+
+    #[tokio::test]
+    async fn get_pane_state_classifies_panes() {
+        let (mcp, fake) = fixture();
+        let state = || {
+            mcp.get_pane_state(Parameters(GetPaneStateRequest {
+                target: "1".into(),
+                prompt_pattern: None,
+            }))
+        };
+        fake.with_pane("%1", |p| {
+            p.command = "bash".into();
+            p.screen = vec!["$ ls".into(), "a.c".into(), "$ ".into()];
+        });
+        let out = state().await.unwrap();
+        assert!(out.starts_with("state: idle\n"));
+        assert!(out.ends_with("foreground: bash\ncursor line: \"$\""));
+
+        // Off the prompt, the shell is reading something else.
+        fake.with_pane("%1", |p| p.screen.push("Overwrite? ".into()));
+        assert!(state().await.unwrap().starts_with("state: waiting for input\n"));
+
+        fake.with_pane("%1", |p| {
+            p.command = "sudo".into();
+            p.screen.push("[sudo] password for me: ".into());
+        });
+        assert!(state().await.unwrap().starts_with("state: password prompt\n"));
+
+        fake.with_pane("%1", |p| p.dead_status = Some(2));
+        let out = state().await.unwrap();
+        assert!(out.starts_with("state: dead\n"));
+        assert!(out.contains("\nexit status: 2\n"));
+    }
+
+    #[tokio::test]
+    async fn respawn_pane_restarts_dead_panes() {
+        let (mcp, fake) = fixture();
+        fake.with_pane("%1", |p| p.dead_status = Some(1));
+        fake.with_pane("%2", |p| p.dead_signal = Some(9));
+        let out = mcp
+            .list_windows(Parameters(ListWindowsRequest {
+                session: Some("work".into()),
+                verbose: Some(true),
+            }))
+            .await
+            .unwrap();
+        assert!(out.contains("\n  .1  80x24  bash  dead (exit 1)\n"), "{out}");
+        assert!(out.ends_with("  .0  80x24  psql  dead (signal 9)  (active)"), "{out}");
+
+        let respawn = |target: &str, command: Option<&str>, kill: Option<bool>| {
+            Parameters(RespawnPaneRequest {
+                target: target.into(),
+                command: command.map(String::from),
+                start_directory: None,
+                kill,
+            })
+        };
+        assert_eq!(
+            mcp.respawn_pane(respawn("1", None, None)).await.unwrap(),
+            "Respawned %1 with its previous command"
+        );
+        assert_eq!(
+            mcp.respawn_pane(respawn("work:1.0", Some("psql -d app"), None))
+                .await
+                .unwrap(),
+            "Respawned %2 running psql -d app"
+        );
+        assert!(fake.commands().contains(&"respawn-pane -t %2 psql -d app".to_string()));
+
+        // Live panes only with kill, and never the pane this server runs in.
+        let live = mcp.respawn_pane(respawn("notes:0.0", None, None)).await;
+        assert!(matches!(live, Err(TmuxError::InvalidArgument(_))));
+        mcp.respawn_pane(respawn("notes:0.0", None, Some(true))).await.unwrap();
+        assert!(fake.commands().contains(&"respawn-pane -k -t %3".to_string()));
+        let own = mcp.respawn_pane(respawn("0", None, Some(true))).await;
+        assert!(matches!(own, Err(TmuxError::PolicyDenied(_))));
+    }
+
+    #[tokio::test]
+    async fn paste_text_goes_through_a_deleted_buffer() {
+        let (mcp, fake) = fixture();
+        let paste = |text: &str, force| {
+            Parameters(PasteTextRequest {
+                target: "1".into(),
+                text: text.into(),
+                enter: Some(true),
+                force,
+            })
+        };
+        assert_eq!(
+            mcp.paste_text(paste("for i in 1 2; do\n  echo $i\ndone\n", None))
+                .await
+                .unwrap(),
+            "Pasted 3 lines, 32 bytes into work:0.1 and pressed Enter"
+        );
+        let commands = fake.commands();
+        let load = commands.iter().find(|c| c.starts_with("load-buffer")).unwrap();
+        let buffer = load.split(' ').nth(2).unwrap();
+        assert!(commands.contains(&format!("paste-buffer -p -d -b {buffer} -t work:0.1")));
+        fake.with_state(|s| assert!(s.buffers.is_empty()));
+
+        fake.with_pane("%1", |p| p.screen = vec!["Password: ".into()]);
+        let refused = mcp.paste_text(paste("hunter2", None)).await;
+        assert!(matches!(refused, Err(TmuxError::PolicyDenied(_))));
+        mcp.paste_text(paste("hunter2", Some(true))).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn buffers_round_trip() {
+        let (mcp, fake) = fixture();
+        assert_eq!(mcp.list_buffers().await.unwrap(), "No buffers");
+        assert_eq!(
+            mcp.set_buffer(Parameters(SetBufferRequest {
+                content: "SELECT 1;\nSELECT 2;\n".into(),
+                name: None,
+                clipboard: Some(true),
+            }))
+            .await
+            .unwrap(),
+            "Set buffer buffer0 (2 lines, 20 bytes) and copied it to the clipboard"
+        );
+        assert!(fake.commands().contains(&"load-buffer -w -".to_string()));
+        fake.with_state(|s| s.buffers.insert(0, ("yank".into(), "copied text".into())));
+        assert_eq!(
+            mcp.list_buffers().await.unwrap(),
+            "yank     11 bytes    \"copied text\"\n\
+             buffer0  20 bytes    \"SELECT 1;\\nSELECT 2;\\n\""
+        );
+
+        let get = |name: Option<&str>| {
+            mcp.get_buffer(Parameters(GetBufferRequest {
+                name: name.map(String::from),
+            }))
+        };
+        assert_eq!(get(None).await.unwrap(), "copied text");
+        assert_eq!(get(Some("buffer0")).await.unwrap(), "SELECT 1;\nSELECT 2;\n");
+        mcp.delete_buffer(Parameters(DeleteBufferRequest { name: "yank".into() }))
+            .await
+            .unwrap();
+        let missing = get(Some("yank")).await.unwrap_err();
+        assert_eq!(missing.kind(), "target_not_found");
+    }
+
+    #[tokio::test]
+    async fn get_user_selection_ignores_control_clients() {
+        let (mcp, fake) = fixture();
+        let get = || mcp.get_user_selection(Parameters(GetUserSelectionRequest { target: None }));
+        fake.with_state(|s| s.clients.clear());
+        assert!(get().await.unwrap().starts_with("No selection: no client is attached\n\n"));
+
+        let client = |pane_id: &str, activity, control_mode| FakeClient {
+            pane_id: pane_id.into(),
+            activity,
+            control_mode,
+        };
+        fake.with_state(|s| {
+            s.clients = vec![client("%1", 100, false), client("%3", 200, true), client("%2", 50, false)]
+        });
+        assert!(get().await.unwrap().starts_with("No selection in %1 (work:0.1)\n\n"));
+    }
+
+    #[tokio::test]
+    async fn get_command_history_splits_at_prompts() {
+        let (mcp, fake) = fixture();
+        fake.with_pane("%1", |p| {
+            p.history = vec!["~/src$ make".into(), "cc a.c".into(), "".into()];
+            p.screen = vec!["~/src$ ls".into(), "a.c  a.out".into(), "~/src$ ".into()];
+        });
+        let history = |prompt_pattern: Option<&str>| {
+            mcp.get_command_history(Parameters(GetCommandHistoryRequest {
+                target: "1".into(),
+                count: Some(1),
+                max_output_lines: None,
+                prompt_pattern: prompt_pattern.map(String::from),
+            }))
+        };
+        let out = history(None).await.unwrap();
+        assert!(out.starts_with("[Last 1 of 2 commands in %1, split at lines matching"));
+        assert!(out.ends_with("\n\n$ ls\na.c  a.out"));
+        assert!(
+            history(Some("^>>> "))
+                .await
+                .unwrap()
+                .starts_with("[No commands found in %1")
+        );
+        assert!(matches!(
+            history(Some("(")).await,
+            Err(TmuxError::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn render_pane_returns_an_image_at_pane_size() {
+        let (mcp, fake) = fixture();
+        fake.with_pane("%1", |p| {
+            p.width = 20;
+            p.height = 2;
+            p.screen = vec!["\x1b[31mred\x1b[0m".into()];
+        });
+        let rendered = mcp
+            .render_pane(Parameters(RenderPaneRequest {
+                target: "1".into(),
+                format: None,
+                normal_screen: None,
+            }))
+            .await
+            .unwrap();
+        assert!(rendered.note.is_none());
+        let image = rendered.image.as_image().unwrap();
+        assert_eq!(image.mime_type, "image/svg+xml");
+        let svg = base64::engine::general_purpose::STANDARD
+            .decode(&image.data)
+            .unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.contains("width=\"180\" height=\"36\""));
+        assert!(svg.contains("fill=\"#cd0000\">red</text>"));
+    }
+
+    #[tokio::test]
+    async fn get_pane_contents_reports_missing_pane() {
+        let (mcp, _) = fixture();
+        let err = mcp
+            .get_pane_contents(Parameters(GetPaneContentsRequest {
+                target: "work:0.7".into(),
+                scroll_back_lines: None,
+                normal_screen: None,
+                style: None,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err, TmuxError::TargetNotFound("can't find pane: 7".into()));
+        assert_eq!(err.to_string(), "target_not_found: can't find pane: 7");
+    }
+
+    #[tokio::test]
+    async fn errors_become_error_results() {
+        let (mcp, _) = fixture();
+        let result = mcp
+            .get_pane_contents(Parameters(GetPaneContentsRequest {
+                target: "nope:0.0".into(),
+                scroll_back_lines: None,
+                normal_screen: None,
+                style: None,
+            }))
+            .await
+            .into_call_tool_result()
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        let text = result.content[0].as_text().unwrap();
+        assert_eq!(text.text, "target_not_found: can't find session: nope");
+    }
+
+    #[tokio::test]
+    async fn empty_server_reports_server_not_running() {
+        let mcp = TmuxMcp::new(Arc::new(FakeTmux::new()), None);
+        let err = mcp
+            .list_sessions(Parameters(ListSessionsRequest { verbose: None }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), "server_not_running");
+    }
+
+    #[tokio::test]
+    async fn get_window_contents_captures_each_pane() {
+        let (mcp, _) = fixture();
+        let out = mcp
+            .get_window_contents(Parameters(GetWindowContentsRequest {
+                target: None,
+                scroll_back_lines: None,
+                normal_screen: None,
+            }))
+            .await
+            .unwrap();
+        assert!(out.starts_with("=== Pane work:0.0 (work:0.0\thost\t80x24\tactive) ===\n"));
+        assert!(out.contains("=== Pane work:0.1 (work:0.1\thost\t80x24\t) ===\n$ ls\nCargo.toml\n"));
+    }
+
+    #[tokio::test]
+    async fn get_window_layout_maps_panes() {
+        let (mcp, _) = fixture();
+        let map = mcp
+            .get_window_layout(Parameters(GetWindowLayoutRequest {
+                target: None,
+                width: Some(40),
+            }))
+            .await
+            .unwrap();
+        let lines: Vec<&str> = map.lines().collect();
+        assert_eq!(
+            lines[0],
+            "work:0 \"editor\" 161x24 layout 0000,161x24,0,0{80x24,0,0,0,80x24,81,0,1}"
+        );
+        assert_eq!(lines[1], "+------------------+-------------------+");
+        assert_eq!(lines[2], "|0* %0             |1 %1               |");
+        assert_eq!(lines[3], "|nvim              |bash               |");
+        assert_eq!(&lines[lines.len() - 2..], [
+            "%0  work:0.0  left   80x24  nvim  (active)",
+            "%1  work:0.1  right  80x24  bash",
+        ]);
+    }
 
-        let result = match &req.session {
-            Some(session) => {
-                let target = format!("{session}:");
-                run_tmux(&["list-windows", "-t", &target, "-F", format]).await
-            }
-            None => run_tmux(&["list-windows", "-a", "-F", format]).await,
+    #[tokio::test]
+    async fn select_layout_checks_pane_count_and_checksum() {
+        let (mcp, fake) = fixture();
+        let select = |layout: &str| {
+            mcp.select_layout(Parameters(SelectLayoutRequest {
+                target: Some("work:0".into()),
+                layout: layout.into(),
+            }))
         };
+        select("tiled").await.unwrap();
+        let err = select("80x24,0,0,0").await.unwrap_err();
+        assert_eq!(
+            err,
+            TmuxError::InvalidArgument("Layout has 1 panes but window work:0 has 2".into())
+        );
+        assert!(matches!(select("sideways").await, Err(TmuxError::InvalidArgument(_))));
+        select("ffff,120x30,0,0[120x15,0,0,0,120x14,0,16,1]").await.unwrap();
 
-        let output = match result {
-            Ok(output) => output,
-            Err(e) => return e,
-        };
+        let applied: Vec<String> = fake
+            .commands()
+            .into_iter()
+            .filter(|c| c.starts_with("select-layout"))
+            .collect();
+        assert_eq!(applied, [
+            "select-layout -t work:0 tiled",
+            "select-layout -t work:0 e9aa,120x30,0,0[120x15,0,0,0,120x14,0,16,1]",
+        ]);
+    }
 
-        // Resolve current window/pane for markers
-        let current_window = match &self.current_pane_id {
-            Some(pane_id) => {
-                resolve_pane_id(pane_id, "#{session_name}:#{window_index}")
-                    .await
-                    .ok()
-            }
-            None => None,
+    #[tokio::test]
+    async fn resize_pane_validates_sizes() {
+        let (mcp, fake) = fixture();
+        let resize = |width: Option<&str>, direction| {
+            mcp.resize_pane(Parameters(ResizePaneRequest {
+                target: "1".into(),
+                width: width.map(Into::into),
+                height: None,
+                direction,
+                amount: Some(5),
+            }))
         };
+        assert_eq!(resize(Some("50%"), None).await.unwrap(), "Pane work:0.1 is now 80x24");
+        resize(None, Some(ResizeDirection::Left)).await.unwrap();
+        assert!(matches!(
+            resize(Some("half"), None).await,
+            Err(TmuxError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            resize(Some("%"), None).await,
+            Err(TmuxError::InvalidArgument(_))
+        ));
+        assert!(matches!(resize(None, None).await, Err(TmuxError::InvalidArgument(_))));
 
-        struct WinRow {
-            session: String,
-            index: String,
-            name: String,
-            pane_count: String,
-            active: String,
-        }
-
-        let windows: Vec<WinRow> = output
-            .lines()
-            .filter_map(|line| {
-                let f: Vec<&str> = line.split('\t').collect();
-                if f.len() < 5 {
-                    return None;
-                }
-                Some(WinRow {
-                    session: f[0].to_string(),
-                    index: f[1].to_string(),
-                    name: f[2].to_string(),
-                    pane_count: f[3].to_string(),
-                    active: f[4].to_string(),
-                })
-            })
+        let resized: Vec<String> = fake
+            .commands()
+            .into_iter()
+            .filter(|c| c.starts_with("resize-pane"))
             .collect();
+        assert_eq!(resized, [
+            "resize-pane -t work:0.1 -x 50%",
+            "resize-pane -t work:0.1 -L 5",
+        ]);
+    }
 
-        if !verbose {
-            let rows: Vec<Vec<String>> = windows
-                .iter()
-                .map(|w| {
-                    let key = format!("{}:{}", w.session, w.index);
-                    let pcount: u32 = w.pane_count.parse().unwrap_or(0);
-                    let mut cols = vec![
-                        key.clone(),
-                        truncate(&w.name, MAX_NAME_LEN),
-                        format!(
-                            "{} pane{}",
-                            pcount,
-                            if pcount == 1 { "" } else { "s" }
-                        ),
-                    ];
-                    if w.active == "active" {
-                        cols.push("active".to_string());
-                    }
-                    if current_window.as_deref() == Some(&key) {
-                        cols.push("<-- current".to_string());
-                    }
-                    cols
-                })
-                .collect();
-            return align_columns(&rows).join("\n");
-        }
+    #[tokio::test]
+    async fn find_panes_filters_on_command_and_path() {
+        let (mcp, _) = fixture();
+        let find = |command: Option<&str>, path: Option<&str>| {
+            mcp.find_panes(Parameters(FindPanesRequest {
+                command: command.map(Into::into),
+                path: path.map(Into::into),
+                title: None,
+                window_name: None,
+                session_name: None,
+                regex: None,
+            }))
+        };
+        assert_eq!(
+            find(Some("psql"), None).await.unwrap(),
+            "%2  work:1.0  server  psql  /home/user/src/api"
+        );
+        assert_eq!(
+            find(None, Some("*/api")).await.unwrap(),
+            "%2  work:1.0  server  psql  /home/user/src/api"
+        );
+        assert_eq!(find(Some("ps"), None).await.unwrap(), "No matching panes");
+    }
 
-        // Verbose: windows with panes expanded
-        let mut out = String::new();
-        for w in &windows {
-            let key = format!("{}:{}", w.session, w.index);
-            let pcount: u32 = w.pane_count.parse().unwrap_or(0);
-            let is_current_window = current_window.as_deref() == Some(&key);
+    #[test]
+    fn expands_home_literally_in_regexes() {
+        let home = Some("/home/a.b+c");
+        assert_eq!(expand_tilde("~/src", home, false), "/home/a.b+c/src");
+        assert_eq!(expand_tilde("~user/src", home, false), "~user/src");
+        let re = compile_pattern(&expand_tilde("~/src$", home, true), true).unwrap();
+        assert!(re.is_match("/home/a.b+c/src"));
+        assert!(!re.is_match("/home/aXbbc/src"));
+    }
 
-            out.push_str(&format!(
-                "{}:  {}  {} pane{}",
-                key,
-                truncate(&w.name, MAX_NAME_LEN),
-                pcount,
-                if pcount == 1 { "" } else { "s" }
-            ));
-            if w.active == "active" {
-                out.push_str("  active");
-            }
-            out.push('\n');
+    /// A tmux server on its own socket, killed on drop. None if tmux isn't
+    /// installed.
+    struct IsolatedServer {
+        socket_name: String,
+    }
 
-            // Fetch panes if this window is accessible
-            let panes = match fetch_panes(&w.session, &w.index).await {
-                Ok(p) => p,
-                Err(_) => continue,
-            };
+    impl IsolatedServer {
+        fn start(name: &str, command: &str) -> Option<Self> {
+            let socket_name = format!("tmux-mcp-test-{}-{name}", std::process::id());
+            let status = std::process::Command::new("tmux")
+                .args(["-L", &socket_name, "-f", "/dev/null"])
+                .args(["new-session", "-d", "-s", "test", "-x", "80", "-y", "24"])
+                .arg(command)
+                .status()
+                .ok()?;
+            status.success().then_some(Self { socket_name })
+        }
+    }
 
-            let pane_rows: Vec<Vec<String>> = panes
-                .iter()
-                .map(|p| {
-                    let mut cols = vec![
-                        format!("  .{}", p.pane_index),
-                        format!("{}x{}", p.width, p.height),
-                        truncate(&p.current_command, MAX_CMD_LEN),
-                    ];
-                    let mut suffix = String::new();
-                    if p.is_active {
-                        suffix.push_str("(active)");
-                    }
-                    if is_current_window
-                        && self.current_pane_id.as_deref() == Some(p.pane_id.as_str())
-                    {
-                        if !suffix.is_empty() {
-                            suffix.push_str("  ");
-                        }
-                        suffix.push_str("<-- current");
-                    }
-                    if !suffix.is_empty() {
-                        cols.push(suffix);
-                    }
-                    cols
-                })
-                .collect();
+    impl Drop for IsolatedServer {
+        fn drop(&mut self) {
+            let _ = std::process::Command::new("tmux")
+                .args(["-L", &self.socket_name, "kill-server"])
+                .status();
+        }
+    }
 
-            for line in align_columns(&pane_rows) {
-                out.push_str(&line);
-                out.push('\n');
+    async fn capture_when_ready(mcp: &TmuxMcp, expected: &str) -> String {
+        let mut out = String::new();
+        for _ in 0..50 {
+            out = mcp
+                .get_pane_contents(Parameters(GetPaneContentsRequest {
+                    target: "test:0.0".into(),
+                    scroll_back_lines: None,
+                    normal_screen: None,
+                    style: None,
+                }))
+                .await
+                .unwrap();
+            if out.contains(expected) {
+                break;
             }
+            tokio::time::sleep(Duration::from_millis(20)).await;
         }
-
-        out.trim_end().to_string()
+        out
     }
 
-    #[tool(
-        description = "Get the current tmux session name and window that this MCP server is running in."
-    )]
-    async fn get_current_session(&self) -> String {
-        let Some(pane_id) = &self.current_pane_id else {
-            return "Not running inside tmux (TMUX_PANE not set)".into();
+    #[tokio::test]
+    async fn subprocess_backend_against_real_server() {
+        let Some(server) = IsolatedServer::start("subprocess", "printf 'hello\\n'; cat") else {
+            return;
         };
+        let tmux = Arc::new(Subprocess::new(Some(server.socket_name.clone()), Timeouts::default()));
+        let mcp = TmuxMcp::new(tmux, None);
 
-        match resolve_pane_id(pane_id, "#{session_name}:#{window_index} (window: #{window_name})")
+        assert!(capture_when_ready(&mcp, "hello").await.starts_with("hello\n"));
+        let sessions = mcp
+            .list_sessions(Parameters(ListSessionsRequest { verbose: None }))
             .await
-        {
-            Ok(info) => info,
-            Err(e) => e,
-        }
+            .unwrap();
+        assert_eq!(sessions, "test  (detached)  1 window");
+        let missing = mcp
+            .get_pane_contents(Parameters(GetPaneContentsRequest {
+                target: "nope:0.0".into(),
+                scroll_back_lines: None,
+                normal_screen: None,
+                style: None,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(missing.kind(), "target_not_found");
     }
 
-    #[tool(
-        description = "Get the current tmux window index and name that this MCP server is running in."
-    )]
-    async fn get_current_window(&self) -> String {
-        let Some(pane_id) = &self.current_pane_id else {
-            return "Not running inside tmux (TMUX_PANE not set)".into();
+    #[tokio::test]
+    async fn control_mode_backend_against_real_server() {
+        let Some(server) = IsolatedServer::start("control", "printf 'hello\\n'; cat") else {
+            return;
         };
+        let tmux = Arc::new(ControlMode::new(Some(server.socket_name.clone()), Timeouts::default()));
+        let mcp = TmuxMcp::new(tmux, None);
 
-        match resolve_pane_id(
-            pane_id,
-            "#{session_name}:#{window_index}\t#{window_name}\t#{window_panes} panes",
-        )
-        .await
-        {
-            Ok(info) => info,
-            Err(e) => e,
+        assert!(capture_when_ready(&mcp, "hello").await.starts_with("hello\n"));
+        let windows = mcp
+            .list_windows(Parameters(ListWindowsRequest {
+                session: Some("test".into()),
+                verbose: None,
+            }))
+            .await
+            .unwrap();
+        assert!(windows.starts_with("test:0  "), "{windows}");
+        assert!(windows.ends_with("  1 pane  active"), "{windows}");
+        let missing = mcp
+            .get_pane_contents(Parameters(GetPaneContentsRequest {
+                target: "test:0.5".into(),
+                scroll_back_lines: None,
+                normal_screen: None,
+                style: None,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(missing, TmuxError::TargetNotFound("can't find pane: 5".into()));
+    }
+
+    #[tokio::test]
+    async fn stalled_commands_time_out() {
+        let Some(server) = IsolatedServer::start("timeout", "cat") else {
+            return;
+        };
+        let timeouts = Timeouts {
+            default: Duration::from_secs(5),
+            per_command: [("wait-for".to_string(), Duration::from_millis(200))].into(),
+        };
+        let socket = Some(server.socket_name.clone());
+        let backends: [Arc<dyn TmuxBackend>; 2] = [
+            Arc::new(Subprocess::new(socket.clone(), timeouts.clone())),
+            Arc::new(ControlMode::new(socket, timeouts)),
+        ];
+        for tmux in backends {
+            let err = tmux.run(&["wait-for", "never-signalled"]).await.unwrap_err();
+            assert_eq!(
+                err,
+                TmuxError::Timeout(
+                    "tmux wait-for never-signalled did not finish within 200ms".into()
+                )
+            );
+            let after = tmux
+                .run(&["display-message", "-p", "-t", "test:0.0", "still #{session_name}"])
+                .await;
+            assert_eq!(after.unwrap(), "still test\n", "{tmux:?}");
         }
     }
 
-    #[tool(
-        description = "Get the contents of a specific tmux pane. Supports scrollback history."
-    )]
-    async fn get_pane_contents(
-        &self,
-        Parameters(req): Parameters<GetPaneContentsRequest>,
-    ) -> String {
-        let scroll_back = req.scroll_back_lines.unwrap_or(0);
-        let t = req.target.trim().to_string();
+    #[tokio::test]
+    async fn create_workspace_builds_session_once() {
+        let Some(server) = IsolatedServer::start("workspace", "cat") else {
+            return;
+        };
+        let dir = std::env::temp_dir().join(format!("tmux-mcp-test-{}-templates", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("dev.yaml"),
+            "session: ws\nroot: /\nwindows:\n  - name: editor\n  - name: logs\n    root: tmp\n    layout: even-vertical\n    panes: [cat, { root: /usr, command: cat }]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("bad.yaml"),
+            "windows:\n  - panes: [a, b]\n    layout: \"80x24,0,0,0\"\n",
+        )
+        .unwrap();
+        let tmux = Arc::new(Subprocess::new(Some(server.socket_name.clone()), Timeouts::default()));
+        let mcp = TmuxMcp::new(tmux.clone(), None).with_templates_dir(dir.clone());
 
-        // Resolve target to session:window.pane format.
-        let target = if t.contains(':') {
-            // "sess:y.x" - fully qualified
-            if !t.contains('.') {
-                return format!("Invalid target \"{t}\": expected \"sess:window.pane\" but no pane specifier found. Use get_window_contents to read an entire window.");
-            }
-            t
-        } else if t.contains('.') {
-            // "y.x" - window.pane, prepend current session
-            let Some(pane_id) = &self.current_pane_id else {
-                return "Not running inside tmux".into();
-            };
-            match resolve_pane_id(pane_id, "#{session_name}").await {
-                Ok(session) => format!("{session}:{t}"),
-                Err(e) => return e,
-            }
-        } else {
-            // "x" - bare pane index, prepend current session:window
-            let Some(pane_id) = &self.current_pane_id else {
-                return "Not running inside tmux".into();
-            };
-            match resolve_pane_id(pane_id, "#{session_name}:#{window_index}").await {
-                Ok(current_window) => format!("{current_window}.{t}"),
-                Err(e) => return e,
-            }
+        let create = |template: &str| {
+            mcp.create_workspace(Parameters(CreateWorkspaceRequest {
+                template: template.into(),
+                session: None,
+                force: None,
+            }))
         };
+        assert_eq!(
+            create("dev").await.unwrap(),
+            "Created session ws from template dev\n0: editor (1 panes)\n1: logs (2 panes)"
+        );
+        assert_eq!(
+            create("dev").await.unwrap(),
+            "Session ws already exists; left it unchanged\n0: editor (1 panes)\n1: logs (2 panes)"
+        );
+        let paths = tmux
+            .run(&["list-panes", "-t", "=ws:1", "-F", "#{pane_current_path}"])
+            .await
+            .unwrap();
+        assert_eq!(paths, "/tmp\n/usr\n");
 
-        capture_pane(&target, scroll_back).await
+        let err = create("bad").await.unwrap_err();
+        assert_eq!(err.kind(), "invalid_argument");
+        assert_eq!(tmux.run(&["has-session", "-t", "=bad"]).await.unwrap_err().kind(), "target_not_found");
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
-    #[tool(
-        description = "Get the contents of all panes in a tmux window. Supports scrollback history. If target is omitted, defaults to the current window."
-    )]
-    async fn get_window_contents(
-        &self,
-        Parameters(req): Parameters<GetWindowContentsRequest>,
-    ) -> String {
-        let scroll_back = req.scroll_back_lines.unwrap_or(0);
-
-        let target = match req.target {
-            Some(t) if t.contains(':') => t,
-            Some(t) => {
-                // Bare window index, prepend current session
-                let Some(pane_id) = &self.current_pane_id else {
-                    return "Not running inside tmux".into();
-                };
-                match resolve_pane_id(pane_id, "#{session_name}").await {
-                    Ok(session) => format!("{session}:{t}"),
-                    Err(e) => return e,
-                }
-            }
-            None => {
-                let Some(pane_id) = &self.current_pane_id else {
-                    return "No target specified and not running inside tmux".into();
-                };
-                match resolve_pane_id(pane_id, "#{session_name}:#{window_index}").await {
-                    Ok(t) => t,
-                    Err(e) => return e,
-                }
-            }
+    #[tokio::test]
+    async fn snapshots_restore_layout_and_directories() {
+        let Some(server) = IsolatedServer::start("snapshot", "printf 'before restart\\n'; exec sh") else {
+            return;
         };
+        let dir = std::env::temp_dir().join(format!("tmux-mcp-test-{}-snapshots", std::process::id()));
+        let tmux = Arc::new(Subprocess::new(Some(server.socket_name.clone()), Timeouts::default()));
+        let mcp = TmuxMcp::new(tmux.clone(), None).with_snapshots_dir(dir.clone());
+        tmux.run(&["split-window", "-h", "-t", "test:0", "-c", "/tmp"]).await.unwrap();
+        tmux.run(&["new-window", "-d", "-t", "test:3", "-n", "logs", "-c", "/"]).await.unwrap();
+        let layout = tmux
+            .run(&["display-message", "-p", "-t", "test:0", "#{window_layout}"])
+            .await
+            .unwrap();
+        capture_when_ready(&mcp, "before restart").await;
 
-        let pane_format = "#{session_name}:#{window_index}.#{pane_index}\t#{pane_title}\t#{pane_width}x#{pane_height}\t#{?pane_active,active,}";
+        let saved = mcp
+            .save_snapshot(Parameters(SaveSnapshotRequest {
+                name: "daily".into(),
+                sessions: None,
+                scroll_back_lines: Some(10),
+            }))
+            .await
+            .unwrap();
+        assert!(saved.starts_with("Saved 1 sessions, 2 windows, 3 panes to "), "{saved}");
 
-        let panes = match run_tmux(&["list-panes", "-t", &target, "-F", pane_format]).await {
-            Ok(p) => p,
-            Err(e) => return e,
+        let restore = || {
+            mcp.restore_snapshot(Parameters(RestoreSnapshotRequest {
+                name: "daily".into(),
+                sessions: None,
+                rerun: None,
+                force: None,
+            }))
         };
+        assert_eq!(restore().await.unwrap(), "Skipped session test: it already exists");
+        // Another session keeps the server up, so restoring doesn't race its
+        // exit.
+        tmux.run(&["new-session", "-d", "-s", "keep"]).await.unwrap();
+        tmux.run(&["kill-session", "-t", "test"]).await.unwrap();
+        // The first window is created at index 1 and moved to its saved 0.
+        tmux.run(&["set-option", "-g", "base-index", "1"]).await.unwrap();
+        assert_eq!(restore().await.unwrap(), "Restored session test with 2 windows");
 
-        let mut output = String::new();
-        for line in panes.lines() {
-            let pane_target = line.split('\t').next().unwrap_or("");
-            if pane_target.is_empty() {
-                continue;
-            }
+        let panes = tmux
+            .run(&[
+                "list-panes", "-s", "-t", "test", "-F",
+                "#{window_index}.#{pane_index} #{window_name} #{pane_current_path}",
+            ])
+            .await
+            .unwrap();
+        assert!(panes.starts_with("0.0 ") && panes.ends_with(" /tmp\n3.0 logs /\n"), "{panes}");
+        let restored = tmux
+            .run(&["display-message", "-p", "-t", "test:0", "#{window_layout}"])
+            .await
+            .unwrap();
+        // Pane IDs differ after a restore; the geometry must not.
+        let geometry = |l: &str| {
+            let l = layout::Layout::parse(l.trim()).unwrap();
+            l.panes().iter().map(|p| (p.x, p.y, p.width, p.height)).collect::<Vec<_>>()
+        };
+        assert_eq!(geometry(&restored), geometry(&layout));
+        assert!(capture_when_ready(&mcp, "before restart").await.contains("before restart"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 
-            output.push_str(&format!("=== Pane {pane_target} ({}) ===\n", line));
-            output.push_str(&capture_pane(pane_target, scroll_back).await);
-            output.push('\n');
+    #[tokio::test]
+    async fn get_pane_processes_walks_the_process_tree() {
+        if !std::path::Path::new("/proc/self/stat").exists() {
+            return;
+        }
+        let Some(server) = IsolatedServer::start("processes", "sleep 30 | cat") else {
+            return;
+        };
+        let tmux = Arc::new(Subprocess::new(Some(server.socket_name.clone()), Timeouts::default()));
+        let mcp = TmuxMcp::new(tmux, None);
+        // The shell may not have started the pipeline yet.
+        let mut out = String::new();
+        for _ in 0..20 {
+            out = mcp
+                .get_pane_processes(Parameters(GetPaneProcessesRequest {
+                    target: "test:0.0".into(),
+                }))
+                .await
+                .unwrap();
+            if out.lines().count() == 5 {
+                break;
+            }
         }
-        output
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines[0].ends_with(" test:0.0: 3 processes"), "{out}");
+        assert!(lines[1].starts_with("PID "), "{out}");
+        assert!(lines[2].ends_with("sleep 30 | cat"), "{out}");
+        assert!(lines[3..].iter().any(|l| l.ends_with("  sleep 30")), "{out}");
+        assert!(lines[3..].iter().any(|l| l.ends_with("  cat")), "{out}");
     }
-}
 
-#[tool_handler]
-impl ServerHandler for TmuxMcp {
-    fn get_info(&self) -> ServerInfo {
-        ServerInfo {
-            instructions: Some(
-                "MCP server for interacting with tmux sessions, windows, and panes. \
-                 Use list_sessions to discover sessions, list_windows to see windows, \
-                 get_pane_contents to read a specific pane, and get_window_contents to read all panes in a window."
-                    .into(),
-            ),
-            capabilities: ServerCapabilities::builder().enable_tools().build(),
-            ..Default::default()
+    #[tokio::test]
+    async fn signal_pane_stops_the_foreground_job() {
+        if !std::path::Path::new("/proc/self/stat").exists() {
+            return;
         }
+        let Some(server) = IsolatedServer::start("signal", "sleep 30") else {
+            return;
+        };
+        let tmux = Arc::new(Subprocess::new(Some(server.socket_name.clone()), Timeouts::default()));
+        tmux.run(&["set-option", "-g", "remain-on-exit", "on"]).await.unwrap();
+        let pane_id = tmux
+            .run(&["display-message", "-p", "-t", "test:0.0", "#{pane_id}"])
+            .await
+            .unwrap();
+        let pane_id = pane_id.trim();
+        let sigterm = || {
+            Parameters(SignalPaneRequest {
+                target: "test:0.0".into(),
+                signal: Some(Signal::Sigterm),
+            })
+        };
+
+        // Never the pane this server runs in.
+        let own = TmuxMcp::new(tmux.clone(), Some(pane_id.to_string()));
+        assert!(matches!(
+            own.signal_pane(sigterm()).await,
+            Err(TmuxError::PolicyDenied(_))
+        ));
+
+        let mcp = TmuxMcp::new(tmux, None);
+        assert_eq!(
+            mcp.signal_pane(sigterm()).await.unwrap(),
+            format!("Sent SIGTERM to sleep 30 in {pane_id}; it has exited")
+        );
+        assert!(matches!(
+            mcp.signal_pane(sigterm()).await,
+            Err(TmuxError::InvalidArgument(_))
+        ));
     }
-}
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env().add_directive(tracing::Level::DEBUG.into()))
-        .with_writer(std::io::stderr)
-        .with_ansi(false)
-        .init();
+    #[tokio::test]
+    async fn jobs_run_in_the_background_and_outlive_the_server() {
+        let Some(server) = IsolatedServer::start("jobs", "sh") else {
+            return;
+        };
+        let tmux = Arc::new(Subprocess::new(Some(server.socket_name.clone()), Timeouts::default()));
+        let mcp = TmuxMcp::new(tmux.clone(), None);
+        let started = mcp
+            .start_job(Parameters(StartJobRequest {
+                command: "echo one; echo two; exit 4".into(),
+                name: Some("count".into()),
+                start_directory: None,
+            }))
+            .await
+            .unwrap();
+        assert!(started.starts_with("Started job count in mcp-jobs:0 (%"), "{started}");
 
-    tracing::info!("Starting tmux-mcp server");
+        // A new server finds the job through its tmux options.
+        let restarted = TmuxMcp::new(tmux, None);
+        let status = restarted
+            .job_status(Parameters(JobStatusRequest {
+                job: "count".into(),
+                wait_seconds: Some(10),
+            }))
+            .await
+            .unwrap();
+        assert!(status.contains("\nstatus: exit 4\n"), "{status}");
+        assert!(status.ends_with("\nlast output: two"), "{status}");
+        let output = restarted
+            .job_output(Parameters(JobOutputRequest {
+                job: "count".into(),
+                lines: Some(1),
+            }))
+            .await
+            .unwrap();
+        assert_eq!(output, "[job count: exit 4; last 1 of 2 lines]\ntwo");
+        let jobs = restarted.list_jobs().await.unwrap();
+        assert!(jobs.starts_with("count  exit 4  started "), "{jobs}");
+    }
 
-    let service = TmuxMcp::new().serve(stdio()).await.inspect_err(|e| {
-        tracing::error!("serving error: {:?}", e);
-    })?;
+    #[tokio::test]
+    async fn exec_runs_in_a_throwaway_window() {
+        let Some(server) = IsolatedServer::start("exec", "env FOO=bar sh") else {
+            return;
+        };
+        let tmux = Arc::new(Subprocess::new(Some(server.socket_name.clone()), Timeouts::default()));
+        let mcp = TmuxMcp::new(tmux.clone(), None);
+        let exec = |command: &str, env: &[&str], timeout_seconds| {
+            Parameters(ExecRequest {
+                command: command.into(),
+                target: Some("test:0.0".into()),
+                start_directory: Some("/".into()),
+                env: Some(env.iter().map(|n| n.to_string()).collect()),
+                timeout_seconds,
+            })
+        };
+        assert_eq!(
+            mcp.exec(exec("pwd; echo $FOO; exit 3", &["FOO"], None)).await.unwrap(),
+            "[exit 3]\n/\nbar"
+        );
+        // Only the named variables are copied from the pane.
+        assert_eq!(
+            mcp.exec(exec("echo \"[$FOO]\"", &[], None)).await.unwrap(),
+            "[exit 0]\n[]"
+        );
+        assert_eq!(
+            mcp.exec(exec("echo started; sleep 30", &[], Some(1))).await.unwrap(),
+            "[still running after 1s; killed]\nstarted"
+        );
+        let windows = tmux.run(&["list-windows", "-a", "-F", "#{session_name}"]).await.unwrap();
+        assert_eq!(windows, "test\n");
+    }
 
-    service.waiting().await?;
-    Ok(())
+    #[tokio::test]
+    async fn broadcast_command_gathers_each_panes_result() {
+        let Some(server) = IsolatedServer::start("broadcast", "sh") else {
+            return;
+        };
+        let tmux = Arc::new(Subprocess::new(Some(server.socket_name.clone()), Timeouts::default()));
+        tmux.run(&["split-window", "-t", "test:0", "sh"]).await.unwrap();
+        tmux.run(&["split-window", "-t", "test:0", "sh"]).await.unwrap();
+        let mcp = TmuxMcp::new(tmux, Some("%0".into()));
+        let out = mcp
+            .broadcast_command(Parameters(BroadcastCommandRequest {
+                command: "echo \"hi from $TMUX_PANE\"; test $TMUX_PANE = %1".into(),
+                window: Some("test:0".into()),
+                panes: None,
+                filter: None,
+                timeout_seconds: Some(10),
+                max_lines: None,
+                force: None,
+            }))
+            .await
+            .unwrap();
+        assert_eq!(
+            out,
+            "[3 panes: 1 succeeded, 1 failed, 1 not run]\n\
+             === %0 test:0.0 (not sent: this server runs in it) ===\n\
+             === %1 test:0.1 (exit 0) ===\n\
+             hi from %1\n\
+             === %2 test:0.2 (exit 1) ===\n\
+             hi from %2\n"
+        );
+
+        let broadcast = |command: &str| {
+            mcp.broadcast_command(Parameters(BroadcastCommandRequest {
+                command: command.into(),
+                window: None,
+                panes: Some(vec!["%1".into()]),
+                filter: None,
+                timeout_seconds: Some(10),
+                max_lines: None,
+                force: None,
+            }))
+        };
+        // A backgrounded command and a syntax error still report a status.
+        let out = broadcast("sleep 0 &").await.unwrap();
+        assert!(out.starts_with("[1 pane: 1 succeeded]\n"), "{out}");
+        let out = broadcast("if true").await.unwrap();
+        assert!(out.contains("(exit 2)"), "{out}");
+        assert!(matches!(
+            broadcast("echo one\necho two").await,
+            Err(TmuxError::InvalidArgument(m)) if m.contains("single line")
+        ));
+    }
+
+    #[tokio::test]
+    async fn get_user_selection_reads_copy_mode_and_the_marked_pane() {
+        let Some(server) = IsolatedServer::start("selection", "sh") else {
+            return;
+        };
+        let tmux = Arc::new(Subprocess::new(Some(server.socket_name.clone()), Timeouts::default()));
+        let mcp = TmuxMcp::new(tmux.clone(), None);
+        let get = || {
+            mcp.get_user_selection(Parameters(GetUserSelectionRequest {
+                target: Some("test:0.0".into()),
+            }))
+        };
+        assert_eq!(
+            get().await.unwrap(),
+            "No selection in %0 (test:0.0)\n\nNo marked pane\n\n\
+             Ask the user to select the text in copy mode, or to mark the pane with prefix m."
+        );
+
+        tmux.run(&["send-keys", "-t", "%0", "clear; printf 'alpha beta\\ngamma delta\\n'", "Enter"])
+            .await
+            .unwrap();
+        capture_when_ready(&mcp, "gamma delta\n").await;
+        tmux.run(&["set-option", "-g", "mode-keys", "vi"]).await.unwrap();
+        tmux.run(&["copy-mode", "-t", "%0"]).await.unwrap();
+        for key in [
+            "top-line", "start-of-line", "next-word", "begin-selection", "cursor-down", "start-of-line",
+            "cursor-right", "cursor-right",
+        ] {
+            tmux.run(&["send-keys", "-t", "%0", "-X", key]).await.unwrap();
+        }
+        tmux.run(&["select-pane", "-m", "-t", "%0"]).await.unwrap();
+        let out = get().await.unwrap();
+        assert!(out.starts_with("=== Selection in %0 (test:0.0) ===\nbeta\ngam\n\n"), "{out}");
+        assert!(out.contains("=== Marked pane %0 (test:0.0) ===\nalpha beta\ngamma delta"), "{out}");
+    }
 }
@@ -0,0 +1,229 @@
+//! Classifying what a pane is doing, so an agent can tell whether typing into
+//! it reaches a shell, a running command, a full-screen program or a password
+//! prompt.
+
+use std::{sync::LazyLock, time::Duration};
+
+use regex::Regex;
+
+/// A prompt for a secret on the cursor line, e.g. `[sudo] password for me: `
+/// or `Enter passphrase for key '/home/me/.ssh/id_ed25519': `.
+static PASSWORD_PROMPT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b(password|passphrase|passcode|pin|verification code)\b[^:]*:\s*$").unwrap()
+});
+
+/// Output more recent than this means whatever holds the terminal is still
+/// printing.
+pub const RECENT_ACTIVITY: Duration = Duration::from_secs(2);
+
+/// A question whose answer confirms something, e.g. `Proceed? [y/N] `,
+/// `Are you sure you want to continue connecting (yes/no/[fingerprint])? ` or
+/// `rm: remove write-protected regular file 'x'? `.
+static CONFIRMATION_PROMPT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"(?ix)
+        ( [\[(] \s* y(es)? \s* / \s* n(o)? [^\])]* \]? [\])]
+        | \btype \s+ ['"]?(yes|y)['"]? \b .*
+        | ^rm: \s .* \?
+        ) \s* [:?]? \s* $"#,
+    )
+    .unwrap()
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// A shell waiting for a command.
+    Idle,
+    /// A command in the foreground, or the shell itself printing output.
+    Running,
+    /// The shell holds the terminal but its cursor line is no prompt, and it
+    /// has gone quiet: a `read` builtin, a continuation prompt, or a prompt
+    /// the prompt pattern doesn't recognise.
+    WaitingForInput,
+    /// A full-screen program on the alternate screen, e.g. vim or htop.
+    Interactive,
+    PasswordPrompt,
+    /// The pane's process has exited and the pane stayed (remain-on-exit).
+    Dead,
+}
+
+impl State {
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Idle => "idle",
+            State::Running => "running",
+            State::WaitingForInput => "waiting for input",
+            State::Interactive => "interactive",
+            State::PasswordPrompt => "password prompt",
+            State::Dead => "dead",
+        }
+    }
+
+    pub fn hint(self) -> &'static str {
+        match self {
+            State::Idle => "The shell is waiting for a command.",
+            State::Running => {
+                "A command is running in the foreground; anything typed goes to it. Wait for it, or follow it with read_new_output."
+            }
+            State::WaitingForInput => {
+                "The shell is in the foreground but its cursor line doesn't look like a prompt; it may be reading input for a script or a continued command line. Check the cursor line, or pass a prompt_pattern matching this shell's prompt."
+            }
+            State::Interactive => {
+                "A full-screen program has the terminal; keys are its commands, not shell input."
+            }
+            State::PasswordPrompt => {
+                "A program is asking for a secret; leave it to the user rather than typing into it."
+            }
+            State::Dead => {
+                "The pane's program has exited; restart it with respawn_pane or close the pane."
+            }
+        }
+    }
+}
+
+/// What is known about a pane.
+#[derive(Debug, Clone, Default)]
+pub struct Observation<'a> {
+    pub dead: bool,
+    /// `pane_current_command`.
+    pub command: &'a str,
+    /// Whether the pane's own process holds the terminal, where `/proc` can
+    /// tell.
+    pub root_in_foreground: Option<bool>,
+    pub alternate_on: bool,
+    /// The text of the line holding the cursor.
+    pub cursor_line: &'a str,
+    /// Whether the cursor line looks like a shell prompt, see [`is_prompt`].
+    pub at_prompt: bool,
+    /// How long ago the window last had output, where tmux knows.
+    pub activity_age: Option<Duration>,
+}
+
+pub fn is_password_prompt(line: &str) -> bool {
+    PASSWORD_PROMPT.is_match(line)
+}
+
+/// What kind of prompt `line` is, if typing into it could answer a question
+/// the user should answer: "a password prompt" or "a confirmation prompt".
+pub fn input_blocker(line: &str) -> Option<&'static str> {
+    if is_password_prompt(line) {
+        Some("a password prompt")
+    } else if CONFIRMATION_PROMPT.is_match(line) {
+        Some("a confirmation prompt")
+    } else {
+        None
+    }
+}
+
+/// Whether `line`, a cursor line as captured without trailing spaces, matches
+/// `prompt`. The space a prompt such as `$ ` ends in is put back first.
+pub fn is_prompt(line: &str, prompt: &Regex) -> bool {
+    prompt.is_match(&format!("{} ", line.trim_end()))
+}
+
+pub fn classify(o: &Observation) -> State {
+    let recently_active = o.activity_age.is_some_and(|age| age < RECENT_ACTIVITY);
+    if o.dead {
+        State::Dead
+    } else if is_password_prompt(o.cursor_line) {
+        State::PasswordPrompt
+    } else if o.alternate_on {
+        State::Interactive
+    } else if !crate::is_shell(o.command) || o.root_in_foreground == Some(false) {
+        State::Running
+    } else if o.at_prompt {
+        State::Idle
+    } else if recently_active {
+        State::Running
+    } else {
+        State::WaitingForInput
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_password_prompts() {
+        assert!(is_password_prompt("[sudo] password for me: "));
+        assert!(is_password_prompt(
+            "Enter passphrase for key '/home/me/.ssh/id_ed25519':"
+        ));
+        assert!(is_password_prompt("me@db.example.com's password: "));
+        assert!(!is_password_prompt("$ grep -r password: src"));
+        assert!(!is_password_prompt("Password changed."));
+    }
+
+    #[test]
+    fn recognises_confirmation_prompts() {
+        for line in [
+            "Do you want to continue? [Y/n] ",
+            "Drop database prod? (yes/no)",
+            "Are you sure you want to continue connecting (yes/no/[fingerprint])? ",
+            "rm: remove write-protected regular file 'a.txt'? ",
+            "Type 'yes' to confirm: ",
+        ] {
+            assert_eq!(input_blocker(line), Some("a confirmation prompt"), "{line}");
+        }
+        assert_eq!(input_blocker("$ echo [y/n]; ls"), None);
+        assert_eq!(input_blocker("~/src$ "), None);
+        assert_eq!(input_blocker("Password: "), Some("a password prompt"));
+    }
+
+    #[test]
+    fn recognises_prompts_on_the_cursor_line() {
+        let prompt = Regex::new(crate::blocks::DEFAULT_PROMPT).unwrap();
+        assert!(is_prompt("user@host:~/src$", &prompt));
+        assert!(is_prompt("~/src$ git sta", &prompt));
+        assert!(!is_prompt("Compiling tmux-mcp v0.5.0", &prompt));
+        assert!(!is_prompt("", &prompt));
+    }
+
+    #[test]
+    fn classifies_panes() {
+        let shell = Observation {
+            command: "bash",
+            cursor_line: "~/src$",
+            at_prompt: true,
+            activity_age: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        assert_eq!(classify(&shell), State::Idle);
+        // The shell itself printing, e.g. a `for` loop of builtins.
+        let printing = Observation {
+            cursor_line: "line 41",
+            at_prompt: false,
+            activity_age: Some(Duration::ZERO),
+            ..shell.clone()
+        };
+        assert_eq!(classify(&printing), State::Running);
+        // Quiet and off the prompt, e.g. in `read -p "Name? "`.
+        let reading = Observation {
+            cursor_line: "Name?",
+            activity_age: Some(Duration::from_secs(30)),
+            ..printing.clone()
+        };
+        assert_eq!(classify(&reading), State::WaitingForInput);
+        // A shell running a subshell or a script still has a child in front.
+        let busy_shell = Observation {
+            root_in_foreground: Some(false),
+            ..shell.clone()
+        };
+        assert_eq!(classify(&busy_shell), State::Running);
+        let vim = Observation {
+            command: "vim",
+            alternate_on: true,
+            ..shell.clone()
+        };
+        assert_eq!(classify(&vim), State::Interactive);
+        let sudo = Observation {
+            command: "sudo",
+            cursor_line: "[sudo] password for me: ",
+            ..shell.clone()
+        };
+        assert_eq!(classify(&sudo), State::PasswordPrompt);
+        let dead = Observation { dead: true, ..sudo };
+        assert_eq!(classify(&dead), State::Dead);
+    }
+}
@@ -0,0 +1,257 @@
+//! Process details from `/proc`, for what tmux's formats don't expose. Every
+//! lookup returns None where `/proc` isn't available.
+
+use std::{
+    collections::HashSet,
+    fs,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
+
+/// Clock ticks per second, which `/proc/<pid>/stat` times are counted in.
+/// 100 on every Linux architecture in practice.
+const CLOCK_TICKS: u64 = 100;
+
+/// The fields of `/proc/<pid>/stat` we use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stat {
+    /// The executable name, cut to 15 bytes by the kernel.
+    pub comm: String,
+    /// `R` running, `S` sleeping, `Z` zombie, ...
+    pub state: char,
+    pub ppid: u32,
+    /// Process group.
+    pub pgrp: u32,
+    /// Device number of the controlling terminal, 0 for none.
+    pub tty_nr: u64,
+    /// Process group in the foreground of the process's terminal.
+    pub tpgid: i64,
+    /// User plus system CPU time, in clock ticks.
+    pub cpu_ticks: u64,
+    /// Start time, in clock ticks after boot.
+    pub start_ticks: u64,
+}
+
+pub fn stat(pid: u32) -> Option<Stat> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The command name is in parentheses and may contain spaces, so count
+    // fields from the last ')': state, ppid, pgrp, session, tty_nr, tpgid,
+    // ... with utime and stime 12th and 13th and starttime 20th.
+    let close = stat.rfind(')')?;
+    let comm = stat.get(stat.find('(')? + 1..close)?.to_string();
+    let fields: Vec<&str> = stat.get(close + 1..)?.split_whitespace().collect();
+    Some(Stat {
+        comm,
+        state: fields.first()?.chars().next()?,
+        ppid: fields.get(1)?.parse().ok()?,
+        pgrp: fields.get(2)?.parse().ok()?,
+        tty_nr: fields.get(4)?.parse().ok()?,
+        tpgid: fields.get(5)?.parse().ok()?,
+        cpu_ticks: fields.get(11)?.parse::<u64>().ok()? + fields.get(12)?.parse::<u64>().ok()?,
+        start_ticks: fields.get(19)?.parse().ok()?,
+    })
+}
+
+/// The process group in the foreground of `pid`'s terminal, if it isn't `pid`
+/// itself; for a pane's shell, the command it is running.
+pub fn foreground_pid(pid: u32) -> Option<u32> {
+    let tpgid = stat(pid)?.tpgid;
+    (tpgid > 0 && tpgid != pid as i64).then_some(tpgid as u32)
+}
+
+/// A process's arguments, e.g. `["vim", "src/main.rs"]`.
+pub fn cmdline(pid: u32) -> Option<Vec<String>> {
+    let raw = fs::read(format!("/proc/{pid}/cmdline")).ok()?;
+    let args: Vec<String> = raw
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    (!args.is_empty()).then_some(args)
+}
+
+/// The environment `pid` started with, as `NAME=value` entries. Changes the
+/// process made since, e.g. a shell's `export`, don't show here.
+pub fn environ(pid: u32) -> Option<Vec<String>> {
+    let raw = fs::read(format!("/proc/{pid}/environ")).ok()?;
+    Some(
+        raw.split(|&b| b == 0)
+            .filter(|var| var.contains(&b'='))
+            .map(|var| String::from_utf8_lossy(var).into_owned())
+            .collect(),
+    )
+}
+
+/// How long ago `pid` started.
+pub fn age(pid: u32) -> Option<Duration> {
+    let uptime: f64 = fs::read_to_string("/proc/uptime")
+        .ok()?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()?;
+    let started = stat(pid)?.start_ticks as f64 / CLOCK_TICKS as f64;
+    Some(Duration::from_secs_f64((uptime - started).max(0.0)))
+}
+
+pub fn cwd(pid: u32) -> Option<PathBuf> {
+    fs::read_link(format!("/proc/{pid}/cwd")).ok()
+}
+
+/// Resident memory, in KiB.
+pub fn rss_kib(pid: u32) -> Option<u64> {
+    let status = fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// CPU time as a duration.
+pub fn cpu_time(stat: &Stat) -> Duration {
+    Duration::from_millis(stat.cpu_ticks * 1000 / CLOCK_TICKS)
+}
+
+/// A process below another, `depth` levels down.
+#[derive(Debug, Clone)]
+pub struct Descendant {
+    pub pid: u32,
+    pub depth: usize,
+    pub stat: Stat,
+}
+
+fn all_processes() -> Vec<(u32, Stat)> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let mut all: Vec<(u32, Stat)> = entries
+        .filter_map(|e| e.ok()?.file_name().to_str()?.parse().ok())
+        .filter_map(|p| Some((p, stat(p)?)))
+        .collect();
+    all.sort_by_key(|(p, _)| *p);
+    all
+}
+
+/// The live processes in process group `pgid`; zombies have exited.
+pub fn group_members(pgid: u32) -> Vec<u32> {
+    all_processes()
+        .into_iter()
+        .filter(|(_, s)| s.pgrp == pgid && s.state != 'Z')
+        .map(|(p, _)| p)
+        .collect()
+}
+
+/// Every process below `pid`, each followed by its own children, in PID order.
+pub fn descendants(pid: u32) -> Vec<Descendant> {
+    let all = all_processes();
+    let mut found = Vec::new();
+    let mut stack = vec![(pid, 0)];
+    while let Some((parent, depth)) = stack.pop() {
+        if parent != pid {
+            let stat = all
+                .iter()
+                .find(|(p, _)| *p == parent)
+                .map(|(_, s)| s.clone());
+            if let Some(stat) = stat {
+                found.push(Descendant {
+                    pid: parent,
+                    depth,
+                    stat,
+                });
+            }
+        }
+        // Pushed in reverse so the lowest PID comes off the stack first.
+        for (child, _) in all.iter().rev().filter(|(_, s)| s.ppid == parent) {
+            stack.push((*child, depth + 1));
+        }
+    }
+    found
+}
+
+/// The TCP addresses `pid` is listening on, going by the sockets among its
+/// open files. Empty when its file descriptors can't be read, e.g. another
+/// user's process.
+pub fn listening_tcp(pid: u32) -> Vec<SocketAddr> {
+    let Ok(fds) = fs::read_dir(format!("/proc/{pid}/fd")) else {
+        return Vec::new();
+    };
+    let inodes: HashSet<u64> = fds
+        .filter_map(|fd| {
+            let target = fs::read_link(fd.ok()?.path()).ok()?;
+            let target = target.to_str()?;
+            target
+                .strip_prefix("socket:[")?
+                .strip_suffix(']')?
+                .parse()
+                .ok()
+        })
+        .collect();
+    if inodes.is_empty() {
+        return Vec::new();
+    }
+    let mut addrs: Vec<SocketAddr> = ["tcp", "tcp6"]
+        .iter()
+        .filter_map(|table| fs::read_to_string(format!("/proc/{pid}/net/{table}")).ok())
+        .flat_map(|table| {
+            table
+                .lines()
+                .skip(1)
+                .filter_map(|line| parse_listener(line, &inodes))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    addrs.sort();
+    addrs.dedup();
+    addrs
+}
+
+/// A line of `/proc/net/tcp` or `tcp6`, if it is a listening socket among
+/// `inodes`: `sl local_address rem_address st ... uid timeout inode`.
+fn parse_listener(line: &str, inodes: &HashSet<u64>) -> Option<SocketAddr> {
+    const LISTEN: &str = "0A";
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.get(3)? != &LISTEN || !inodes.contains(&fields.get(9)?.parse().ok()?) {
+        return None;
+    }
+    let (ip, port) = fields.get(1)?.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    // The address is in 32-bit words, each in host (little-endian) order.
+    let words: Vec<u32> = (0..ip.len() / 8)
+        .map(|i| u32::from_str_radix(&ip[i * 8..i * 8 + 8], 16).map(u32::from_be))
+        .collect::<Result<_, _>>()
+        .ok()?;
+    let ip = match words[..] {
+        [v4] => Ipv4Addr::from(v4.to_be_bytes()).into(),
+        [a, b, c, d] => {
+            let mut bytes = [0u8; 16];
+            for (chunk, word) in bytes.chunks_mut(4).zip([a, b, c, d]) {
+                chunk.copy_from_slice(&word.to_be_bytes());
+            }
+            Ipv6Addr::from(bytes).into()
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_listening_sockets() {
+        let inodes = HashSet::from([4242, 5151]);
+        let v4 = "   0: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 4242 1 0000000000000000 100 0 0 10 0";
+        assert_eq!(
+            parse_listener(v4, &inodes),
+            Some("127.0.0.1:8080".parse().unwrap())
+        );
+        let v6 = "   1: 00000000000000000000000001000000:1538 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 5151 1 0000000000000000 100 0 0 10 0";
+        assert_eq!(
+            parse_listener(v6, &inodes),
+            Some("[::1]:5432".parse().unwrap())
+        );
+        // Established, or someone else's socket.
+        assert_eq!(parse_listener(&v4.replace(" 0A ", " 01 "), &inodes), None);
+        assert_eq!(parse_listener(v4, &HashSet::from([1])), None);
+    }
+}
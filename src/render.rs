@@ -0,0 +1,279 @@
+//! Rendering of a styled capture onto the pane's character grid, as SVG or as
+//! a PNG drawn with an embedded 8x8 bitmap font.
+
+use font8x8::{BASIC_FONTS, BLOCK_FONTS, BOX_FONTS, LATIN_FONTS, UnicodeFonts};
+
+use crate::ansi::{self, Style};
+
+type Rgb = (u8, u8, u8);
+
+const DEFAULT_FG: Rgb = (229, 229, 229);
+const DEFAULT_BG: Rgb = (0, 0, 0);
+
+/// SVG cell size and font size, in pixels.
+const SVG_CELL_WIDTH: usize = 9;
+const SVG_CELL_HEIGHT: usize = 18;
+const SVG_FONT_SIZE: usize = 15;
+
+/// PNG cell size: the 8x8 glyphs are drawn with every row doubled.
+const PNG_CELL_WIDTH: usize = 8;
+const PNG_CELL_HEIGHT: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    ch: char,
+    fg: Rgb,
+    bg: Rgb,
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    strikethrough: bool,
+}
+
+impl Cell {
+    const BLANK: Cell = Cell {
+        ch: ' ',
+        fg: DEFAULT_FG,
+        bg: DEFAULT_BG,
+        bold: false,
+        italic: false,
+        underline: false,
+        strikethrough: false,
+    };
+
+    fn new(ch: char, style: &Style) -> Self {
+        let mut fg = style.fg.map_or(DEFAULT_FG, |c| c.rgb());
+        let mut bg = style.bg.map_or(DEFAULT_BG, |c| c.rgb());
+        if style.reverse {
+            std::mem::swap(&mut fg, &mut bg);
+        }
+        if style.dim {
+            fg = blend(fg, bg);
+        }
+        if style.hidden {
+            fg = bg;
+        }
+        // Control characters can't appear in SVG text.
+        let ch = if ch.is_control() { ' ' } else { ch };
+        Cell {
+            ch,
+            fg,
+            bg,
+            bold: style.bold,
+            italic: style.italic,
+            underline: style.underline,
+            strikethrough: style.strikethrough,
+        }
+    }
+
+    /// Whether two cells can share one SVG `<text>` element.
+    fn same_style(&self, other: &Cell) -> bool {
+        Cell { ch: ' ', ..*self } == Cell { ch: ' ', ..*other }
+    }
+}
+
+fn blend(a: Rgb, b: Rgb) -> Rgb {
+    let mid = |x: u8, y: u8| ((x as u16 + y as u16) / 2) as u8;
+    (mid(a.0, b.0), mid(a.1, b.1), mid(a.2, b.2))
+}
+
+fn hex((r, g, b): Rgb) -> String {
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// A pane's screen as a `width` x `height` grid of styled cells.
+pub struct Grid {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+}
+
+impl Grid {
+    /// Lay out `capture-pane -e` output on the grid. Lines and columns beyond
+    /// the pane's size are dropped.
+    pub fn new(capture: &str, width: usize, height: usize) -> Self {
+        let mut cells = vec![Cell::BLANK; width * height];
+        for (row, spans) in ansi::parse(capture).into_iter().take(height).enumerate() {
+            let mut col = 0;
+            for span in spans {
+                for ch in span.text.chars() {
+                    if col == width {
+                        break;
+                    }
+                    cells[row * width + col] = Cell::new(ch, &span.style);
+                    col += 1;
+                }
+            }
+        }
+        Grid {
+            width,
+            height,
+            cells,
+        }
+    }
+
+    fn row(&self, row: usize) -> &[Cell] {
+        &self.cells[row * self.width..(row + 1) * self.width]
+    }
+
+    pub fn to_svg(&self) -> String {
+        let (w, h) = (self.width * SVG_CELL_WIDTH, self.height * SVG_CELL_HEIGHT);
+        let mut out = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" \
+             font-family=\"ui-monospace, Menlo, Consolas, 'DejaVu Sans Mono', monospace\" font-size=\"{SVG_FONT_SIZE}\">\n\
+             <rect width=\"100%\" height=\"100%\" fill=\"{}\"/>\n",
+            hex(DEFAULT_BG)
+        );
+        let mut text = String::new();
+        for row in 0..self.height {
+            let cells = self.row(row);
+            let y = row * SVG_CELL_HEIGHT;
+            let mut start = 0;
+            while start < cells.len() {
+                let first = cells[start];
+                let end = start
+                    + cells[start..]
+                        .iter()
+                        .take_while(|c| c.same_style(&first))
+                        .count();
+                let run = &cells[start..end];
+                let x = start * SVG_CELL_WIDTH;
+                let run_width = run.len() * SVG_CELL_WIDTH;
+                if first.bg != DEFAULT_BG {
+                    out.push_str(&format!(
+                        "<rect x=\"{x}\" y=\"{y}\" width=\"{run_width}\" height=\"{SVG_CELL_HEIGHT}\" fill=\"{}\"/>\n",
+                        hex(first.bg)
+                    ));
+                }
+                let content: String = run.iter().map(|c| c.ch).collect();
+                let trimmed = content.trim_end();
+                if !trimmed.is_empty() {
+                    // textLength pins each run to its cells, whatever the
+                    // viewer's monospace font measures.
+                    let length = trimmed.chars().count() * SVG_CELL_WIDTH;
+                    text.push_str(&format!(
+                        "<text x=\"{x}\" y=\"{}\" textLength=\"{length}\" lengthAdjust=\"spacingAndGlyphs\" fill=\"{}\"{}>{}</text>\n",
+                        y + SVG_CELL_HEIGHT - 4,
+                        hex(first.fg),
+                        svg_attributes(&first),
+                        xml_escape(trimmed)
+                    ));
+                }
+                start = end;
+            }
+        }
+        out.push_str("<g xml:space=\"preserve\">\n");
+        out.push_str(&text);
+        out.push_str("</g>\n</svg>\n");
+        out
+    }
+
+    pub fn to_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        let (w, h) = (self.width * PNG_CELL_WIDTH, self.height * PNG_CELL_HEIGHT);
+        let mut pixels = vec![0u8; w * h * 3];
+        for (i, cell) in self.cells.iter().enumerate() {
+            let (x0, y0) = (i % self.width * PNG_CELL_WIDTH, i / self.width * PNG_CELL_HEIGHT);
+            let glyph = glyph(cell.ch);
+            for dy in 0..PNG_CELL_HEIGHT {
+                let bits = match dy {
+                    15 if cell.underline => 0xff,
+                    8 if cell.strikethrough => 0xff,
+                    _ => glyph[dy / 2],
+                };
+                // Bold smears each glyph row one pixel to the right.
+                let bits = if cell.bold { bits | bits << 1 } else { bits };
+                for dx in 0..PNG_CELL_WIDTH {
+                    let (r, g, b) = if bits >> dx & 1 == 1 { cell.fg } else { cell.bg };
+                    let at = ((y0 + dy) * w + x0 + dx) * 3;
+                    pixels[at..at + 3].copy_from_slice(&[r, g, b]);
+                }
+            }
+        }
+
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, w as u32, h as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&pixels)?;
+        writer.finish()?;
+        Ok(out)
+    }
+}
+
+fn svg_attributes(cell: &Cell) -> String {
+    let mut attrs = String::new();
+    if cell.bold {
+        attrs.push_str(" font-weight=\"bold\"");
+    }
+    if cell.italic {
+        attrs.push_str(" font-style=\"italic\"");
+    }
+    match (cell.underline, cell.strikethrough) {
+        (true, true) => attrs.push_str(" text-decoration=\"underline line-through\""),
+        (true, false) => attrs.push_str(" text-decoration=\"underline\""),
+        (false, true) => attrs.push_str(" text-decoration=\"line-through\""),
+        (false, false) => {}
+    }
+    attrs
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// The 8x8 bitmap for `ch` (bit 0 is the leftmost pixel), or a `?` for
+/// characters the font doesn't cover.
+fn glyph(ch: char) -> [u8; 8] {
+    BASIC_FONTS
+        .get(ch)
+        .or_else(|| LATIN_FONTS.get(ch))
+        .or_else(|| BOX_FONTS.get(ch))
+        .or_else(|| BLOCK_FONTS.get(ch))
+        .or_else(|| BASIC_FONTS.get('?'))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lays_out_styled_text_on_the_grid() {
+        let grid = Grid::new("ab\x1b[41mc\x1b[0m\n\x1b[7mrev\x1b[0m too long\nhidden", 6, 2);
+        assert_eq!(grid.row(0)[2].bg, (205, 0, 0));
+        assert_eq!(grid.row(0)[3], Cell::BLANK);
+        assert_eq!((grid.row(1)[0].fg, grid.row(1)[0].bg), (DEFAULT_BG, DEFAULT_FG));
+        let second: String = grid.row(1).iter().map(|c| c.ch).collect();
+        assert_eq!(second, "rev to");
+        assert_eq!(grid.cells.len(), 12);
+    }
+
+    #[test]
+    fn renders_svg_runs() {
+        let svg = Grid::new("\x1b[1;32m<ok>\x1b[0m & \x1b[44m  \x1b[0m", 10, 1).to_svg();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"90\" height=\"18\""));
+        assert!(svg.contains(
+            "<text x=\"0\" y=\"14\" textLength=\"36\" lengthAdjust=\"spacingAndGlyphs\" fill=\"#00cd00\" font-weight=\"bold\">&lt;ok&gt;</text>"
+        ));
+        assert!(svg.contains("<text x=\"36\" y=\"14\" textLength=\"18\" lengthAdjust=\"spacingAndGlyphs\" fill=\"#e5e5e5\"> &amp;</text>"));
+        assert!(svg.contains("<rect x=\"63\" y=\"0\" width=\"18\" height=\"18\" fill=\"#0000ee\"/>"));
+    }
+
+    #[test]
+    fn renders_png_at_cell_resolution() {
+        let png = Grid::new("hi", 4, 3).to_png().unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+        // IHDR width and height.
+        assert_eq!(&png[16..24], &[0, 0, 0, 32, 0, 0, 0, 48]);
+    }
+}
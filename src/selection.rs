@@ -0,0 +1,130 @@
+//! Reading the text a user has selected in copy mode.
+//!
+//! tmux has no format for the selected text itself, only for its corners, so
+//! the text is cut out of a capture of the lines it spans. Copying it with
+//! `copy-selection` instead would overwrite the user's most recent buffer.
+
+/// A `display-message` format for a pane's copy-mode selection, for
+/// [`Selection::parse`].
+pub const FORMAT: &str = "#{pane_in_mode}\t#{selection_present}\t#{selection_start_x}\t#{selection_start_y}\t#{selection_end_x}\t#{selection_end_y}\t#{rectangle_toggle}\t#{mode-keys}\t#{history_size}";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    /// The first selected cell as (x, y), y counting from the top of the
+    /// history.
+    pub start: (usize, usize),
+    /// The last selected cell, which is not itself selected in emacs mode.
+    pub end: (usize, usize),
+    /// A block selection (rectangle-toggle) rather than a run of text.
+    pub rectangle: bool,
+    /// Whether `end` is selected, as it is with vi keys.
+    pub inclusive: bool,
+    pub history_size: usize,
+}
+
+impl Selection {
+    /// Parse a line of [`FORMAT`]; None when the pane has no selection.
+    pub fn parse(line: &str) -> Option<Selection> {
+        let f: Vec<&str> = line.trim_end_matches('\n').split('\t').collect();
+        let [in_mode, present, sx, sy, ex, ey, rectangle, keys, history] = f[..] else {
+            return None;
+        };
+        if in_mode != "1" || present != "1" {
+            return None;
+        }
+        let num = |s: &str| s.parse::<usize>().ok();
+        let (a, b) = ((num(sx)?, num(sy)?), (num(ex)?, num(ey)?));
+        // The cursor end can be above or left of where the selection began.
+        let (start, end) = if (a.1, a.0) <= (b.1, b.0) {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        Some(Selection {
+            start,
+            end,
+            rectangle: rectangle == "1",
+            inclusive: keys == "vi",
+            history_size: num(history)?,
+        })
+    }
+
+    /// The `-S` and `-E` arguments for capture-pane covering the selection.
+    pub fn capture_range(&self) -> (String, String) {
+        let line = |y: usize| (y as i64 - self.history_size as i64).to_string();
+        (line(self.start.1), line(self.end.1))
+    }
+
+    /// Cut the selected text out of `captured`, the output of capture-pane
+    /// over [`Selection::capture_range`]. Columns count characters, so lines
+    /// with wide characters may be off by a cell or two.
+    pub fn extract(&self, captured: &str) -> String {
+        let lines: Vec<Vec<char>> = captured.lines().map(|l| l.chars().collect()).collect();
+        let extra = usize::from(self.inclusive);
+        let cut = |line: &[char], from: usize, to: usize| -> String {
+            let to = to.min(line.len());
+            line.get(from..to.max(from))
+                .unwrap_or_default()
+                .iter()
+                .collect()
+        };
+        let last = lines.len().saturating_sub(1);
+        let selected: Vec<String> = lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                if self.rectangle {
+                    let (left, right) = if self.start.0 <= self.end.0 {
+                        (self.start.0, self.end.0)
+                    } else {
+                        (self.end.0, self.start.0)
+                    };
+                    cut(line, left, right + extra)
+                } else {
+                    let from = if i == 0 { self.start.0 } else { 0 };
+                    let to = if i == last {
+                        self.end.0 + extra
+                    } else {
+                        line.len()
+                    };
+                    cut(line, from, to)
+                }
+            })
+            .collect();
+        selected.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Selections made in a real pane, with what copy-selection copied.
+    const CAPTURED: &str = "line-21 abcdef\nline-22 abcdef\n";
+
+    #[test]
+    fn cuts_out_the_selection() {
+        let emacs = Selection::parse("1\t1\t1\t40\t3\t41\t0\temacs\t19\n").unwrap();
+        assert_eq!(emacs.capture_range(), ("21".into(), "22".into()));
+        assert_eq!(emacs.extract(CAPTURED), "ine-21 abcdef\nlin");
+        let vi = Selection {
+            inclusive: true,
+            ..emacs.clone()
+        };
+        assert_eq!(vi.extract(CAPTURED), "ine-21 abcdef\nline");
+        let rectangle = Selection {
+            rectangle: true,
+            ..emacs
+        };
+        assert_eq!(rectangle.extract(CAPTURED), "in\nin");
+    }
+
+    #[test]
+    fn orders_a_selection_made_upwards() {
+        let up = Selection::parse("1\t1\t14\t22\t0\t21\t1\temacs\t9").unwrap();
+        assert_eq!((up.start, up.end), ((0, 21), (14, 22)));
+        assert_eq!(up.extract(CAPTURED), "line-21 abcdef\nline-22 abcdef");
+        assert_eq!(Selection::parse("1\t0\t\t\t\t\t0\temacs\t9"), None);
+        assert_eq!(Selection::parse("0\t\t\t\t\t\t\tvi\t9"), None);
+    }
+}
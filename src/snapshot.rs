@@ -0,0 +1,232 @@
+//! Session snapshots: sessions, windows, layouts, working directories,
+//! running commands and optionally scrollback, saved as JSON so they can be
+//! recreated after the tmux server restarts.
+
+use std::{
+    fs::{DirBuilder, OpenOptions, Permissions},
+    io::Write,
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::TmuxError;
+
+const VERSION: u32 = 1;
+
+/// Programs restore_snapshot re-runs by default: viewers and monitors that are
+/// safe to start again.
+pub const DEFAULT_RERUN: [&str; 9] = [
+    "vim", "nvim", "vi", "less", "man", "htop", "top", "tail", "watch",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    /// Seconds since the Unix epoch.
+    pub created: u64,
+    pub sessions: Vec<Session>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub name: String,
+    pub windows: Vec<Window>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Window {
+    pub index: u32,
+    pub name: String,
+    /// `window_layout`, applied once the panes are recreated.
+    pub layout: String,
+    pub active: bool,
+    pub panes: Vec<Pane>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pane {
+    pub index: u32,
+    pub path: String,
+    /// `pane_current_command`, e.g. "nvim".
+    pub command: String,
+    /// The foreground command's arguments, where `/proc` could tell.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub argv: Option<Vec<String>>,
+    pub active: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scrollback: Option<String>,
+}
+
+impl Snapshot {
+    pub fn new(sessions: Vec<Session>) -> Self {
+        let created = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Snapshot {
+            version: VERSION,
+            created,
+            sessions,
+        }
+    }
+}
+
+impl Pane {
+    /// The command line to type to start this pane's program again, if it is
+    /// one of `allowed`.
+    pub fn rerun_command(&self, allowed: &[String]) -> Option<String> {
+        let argv = self
+            .argv
+            .clone()
+            .unwrap_or_else(|| vec![self.command.clone()]);
+        let program = argv.first()?.rsplit('/').next()?;
+        if !allowed.iter().any(|a| a == program) {
+            return None;
+        }
+        Some(
+            argv.iter()
+                .map(|a| shell_quote(a))
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+    }
+}
+
+/// Quote an argument for a POSIX shell, leaving plain words alone.
+pub fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// `$XDG_STATE_HOME/tmux-mcp/snapshots`, falling back to `~/.local/state`.
+pub fn default_dir() -> PathBuf {
+    let state = std::env::var_os("XDG_STATE_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(crate::expand_home("~/.local/state")));
+    state.join("tmux-mcp").join("snapshots")
+}
+
+fn path(dir: &Path, name: &str) -> Result<PathBuf, TmuxError> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(TmuxError::InvalidArgument(format!(
+            "Invalid snapshot name {name:?}"
+        )));
+    }
+    Ok(dir.join(format!("{name}.json")))
+}
+
+/// Write `snapshot` as `dir/name.json`, returning the path. Only this user
+/// can read it, as it may hold scrollback.
+pub fn save(dir: &Path, name: &str, snapshot: &Snapshot) -> Result<PathBuf, TmuxError> {
+    let path = path(dir, name)?;
+    let json = serde_json::to_string_pretty(snapshot)
+        .map_err(|e| TmuxError::Tmux(format!("Failed to serialize snapshot: {e}")))?;
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .and_then(|()| {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(&path)?;
+            // A snapshot saved before under the same name keeps its mode.
+            file.set_permissions(Permissions::from_mode(0o600))?;
+            file.write_all(json.as_bytes())
+        })
+        .map_err(|e| TmuxError::Tmux(format!("Failed to write {}: {e}", path.display())))?;
+    Ok(path)
+}
+
+pub fn load(dir: &Path, name: &str) -> Result<Snapshot, TmuxError> {
+    let path = path(dir, name)?;
+    let json = std::fs::read_to_string(&path).map_err(|e| {
+        TmuxError::InvalidArgument(format!("Can't read snapshot {}: {e}", path.display()))
+    })?;
+    let snapshot: Snapshot = serde_json::from_str(&json).map_err(|e| {
+        TmuxError::InvalidArgument(format!("Invalid snapshot {}: {e}", path.display()))
+    })?;
+    if snapshot.version != VERSION {
+        return Err(TmuxError::InvalidArgument(format!(
+            "Snapshot {} has unsupported version {}",
+            path.display(),
+            snapshot.version
+        )));
+    }
+    Ok(snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pane(command: &str, argv: Option<&[&str]>) -> Pane {
+        Pane {
+            index: 0,
+            path: "/".into(),
+            command: command.into(),
+            argv: argv.map(|a| a.iter().map(|s| s.to_string()).collect()),
+            active: true,
+            scrollback: None,
+        }
+    }
+
+    #[test]
+    fn reruns_only_allowed_programs() {
+        let allowed: Vec<String> = DEFAULT_RERUN.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            pane("nvim", Some(&["/usr/bin/nvim", "my file.rs"])).rerun_command(&allowed),
+            Some("/usr/bin/nvim 'my file.rs'".into())
+        );
+        assert_eq!(
+            pane("htop", None).rerun_command(&allowed),
+            Some("htop".into())
+        );
+        assert_eq!(
+            pane("cargo", Some(&["cargo", "publish"])).rerun_command(&allowed),
+            None
+        );
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let dir = std::env::temp_dir().join(format!("tmux-mcp-snapshots-{}", std::process::id()));
+        let snapshot = Snapshot::new(vec![Session {
+            name: "work".into(),
+            windows: vec![Window {
+                index: 1,
+                name: "editor".into(),
+                layout: "b25d,80x24,0,0,0".into(),
+                active: true,
+                panes: vec![pane("bash", None)],
+            }],
+        }]);
+        let path = save(&dir, "daily", &snapshot).unwrap();
+        assert_eq!(path, dir.join("daily.json"));
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&dir), 0o700);
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(load(&dir, "daily").unwrap(), snapshot);
+        assert!(matches!(
+            load(&dir, "nope"),
+            Err(TmuxError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            save(&dir, "../x", &snapshot),
+            Err(TmuxError::InvalidArgument(_))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
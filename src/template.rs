@@ -0,0 +1,257 @@
+//! Workspace templates: a session's windows, panes, layouts, start directories,
+//! commands and environment, read from YAML or TOML files in a config
+//! directory, e.g. `~/.config/tmux-mcp/templates/api.yaml`:
+//!
+//! ```yaml
+//! root: ~/src/api
+//! env: { RUST_LOG: debug }
+//! windows:
+//!   - name: editor
+//!     panes: [nvim]
+//!   - name: server
+//!     layout: even-horizontal
+//!     panes:
+//!       - cargo run
+//!       - { command: tail -f server.log, root: logs }
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::error::TmuxError;
+
+const EXTENSIONS: [&str; 3] = ["yaml", "yml", "toml"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    /// Session name; defaults to the template's file name.
+    pub session: Option<String>,
+    /// Start directory for every window unless it sets its own.
+    pub root: Option<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    pub windows: Vec<Window>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Window {
+    pub name: Option<String>,
+    /// Relative to the template's root.
+    pub root: Option<String>,
+    /// A select-layout preset or layout string, applied once all panes exist.
+    pub layout: Option<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Empty means one pane with a shell.
+    #[serde(default)]
+    pub panes: Vec<Pane>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(from = "PaneEntry")]
+pub struct Pane {
+    /// Typed into the pane's shell, so the shell remains when it exits.
+    pub command: Option<String>,
+    /// Relative to the window's root.
+    pub root: Option<String>,
+    pub env: BTreeMap<String, String>,
+}
+
+/// A pane is either just its command or a table.
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum PaneEntry {
+    Command(String),
+    Full {
+        command: Option<String>,
+        root: Option<String>,
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+    },
+}
+
+impl From<PaneEntry> for Pane {
+    fn from(entry: PaneEntry) -> Self {
+        match entry {
+            PaneEntry::Command(command) => Pane {
+                command: Some(command),
+                ..Pane::default()
+            },
+            PaneEntry::Full { command, root, env } => Pane { command, root, env },
+        }
+    }
+}
+
+impl Template {
+    fn parse(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let template: Template = match path.extension().and_then(|e| e.to_str()) {
+            // The full message quotes the offending line over several lines.
+            Some("toml") => toml::from_str(&text).map_err(|e| {
+                let location = e.to_string().lines().next().unwrap_or_default().to_string();
+                format!("{location}: {}", e.message())
+            })?,
+            _ => serde_yaml::from_str(&text).map_err(|e| e.to_string())?,
+        };
+        if template.windows.is_empty() {
+            return Err("a template needs at least one window".into());
+        }
+        Ok(template)
+    }
+}
+
+/// `$XDG_CONFIG_HOME/tmux-mcp/templates`, falling back to `~/.config`.
+pub fn default_dir() -> PathBuf {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(crate::expand_home("~/.config")));
+    config.join("tmux-mcp").join("templates")
+}
+
+/// Load the template called `name` (its file name without extension).
+pub fn load(dir: &Path, name: &str) -> Result<Template, TmuxError> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return Err(TmuxError::InvalidArgument(format!(
+            "Invalid template name {name:?}"
+        )));
+    }
+    let path = EXTENSIONS
+        .iter()
+        .map(|ext| dir.join(format!("{name}.{ext}")))
+        .find(|p| p.is_file())
+        .ok_or_else(|| {
+            TmuxError::InvalidArgument(format!(
+                "No template {name:?} in {} (use list_templates)",
+                dir.display()
+            ))
+        })?;
+    Template::parse(&path)
+        .map_err(|e| TmuxError::InvalidArgument(format!("{}: {e}", path.display())))
+}
+
+/// Every template in `dir` by name, with the error for those that don't parse.
+pub fn list(dir: &Path) -> Vec<(String, Result<Template, String>)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut templates: Vec<_> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let ext = path.extension()?.to_str()?;
+            if !EXTENSIONS.contains(&ext) {
+                return None;
+            }
+            let name = path.file_stem()?.to_str()?.to_string();
+            Some((name, Template::parse(&path)))
+        })
+        .collect();
+    templates.sort_by(|a, b| a.0.cmp(&b.0));
+    templates
+}
+
+/// Resolve a start directory: `~` expands to $HOME, and a relative `root` is
+/// taken relative to `base`.
+pub fn resolve_root(base: Option<&str>, root: Option<&str>) -> Option<String> {
+    let root = root.map(crate::expand_home);
+    match (base, root) {
+        (Some(base), Some(root)) if !root.starts_with('/') => {
+            Some(Path::new(base).join(root).to_string_lossy().into_owned())
+        }
+        (_, Some(root)) => Some(root),
+        (base, None) => base.map(String::from),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, file: &str, contents: &str) {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join(file), contents).unwrap();
+    }
+
+    #[test]
+    fn loads_yaml_and_toml() {
+        let dir = std::env::temp_dir().join(format!("tmux-mcp-templates-{}", std::process::id()));
+        write(
+            &dir,
+            "api.yaml",
+            "root: /src/api\nwindows:\n  - name: editor\n    panes: [nvim, { root: logs, env: { A: b } }]\n",
+        );
+        write(
+            &dir,
+            "notes.toml",
+            "session = \"Notes\"\n[[windows]]\nname = \"notes\"\npanes = [\"vim todo.md\"]\n",
+        );
+        write(&dir, "broken.yml", "windows: []\n");
+        write(&dir, "README.md", "not a template");
+
+        let api = load(&dir, "api").unwrap();
+        assert_eq!(api.root.as_deref(), Some("/src/api"));
+        assert_eq!(
+            api.windows[0].panes,
+            vec![
+                Pane {
+                    command: Some("nvim".into()),
+                    ..Pane::default()
+                },
+                Pane {
+                    command: None,
+                    root: Some("logs".into()),
+                    env: BTreeMap::from([("A".into(), "b".into())]),
+                },
+            ]
+        );
+        assert_eq!(
+            load(&dir, "notes").unwrap().session.as_deref(),
+            Some("Notes")
+        );
+        assert!(matches!(
+            load(&dir, "missing"),
+            Err(TmuxError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            load(&dir, "../api"),
+            Err(TmuxError::InvalidArgument(_))
+        ));
+
+        let listed: Vec<_> = list(&dir)
+            .into_iter()
+            .map(|(n, t)| (n, t.is_ok()))
+            .collect();
+        assert_eq!(
+            listed,
+            vec![
+                ("api".into(), true),
+                ("broken".into(), false),
+                ("notes".into(), true)
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resolves_roots_relative_to_their_parent() {
+        assert_eq!(
+            resolve_root(Some("/src/api"), Some("logs")).as_deref(),
+            Some("/src/api/logs")
+        );
+        assert_eq!(
+            resolve_root(Some("/src/api"), Some("/tmp")).as_deref(),
+            Some("/tmp")
+        );
+        assert_eq!(
+            resolve_root(Some("/src/api"), None).as_deref(),
+            Some("/src/api")
+        );
+        assert_eq!(resolve_root(None, None), None);
+    }
+}